@@ -3,6 +3,7 @@ use std::env;
 use std::fs;
 use std::io;
 use std::path::Path;
+use std::process::Command;
 
 fn get_platform() -> Option<String> {
   let features = env::vars().filter(|&(ref key, _)| key.starts_with("CARGO_FEATURE_MCU_"));
@@ -14,6 +15,16 @@ fn get_platform() -> Option<String> {
   }
 }
 
+fn get_cpu() -> Option<String> {
+  let features = env::vars().filter(|&(ref key, _)| key.starts_with("CARGO_FEATURE_CPU_"));
+  match features.last() {
+    Some((feature_var, _)) => Some(
+      feature_var.trim_left_matches("CARGO_FEATURE_CPU_")
+        .replace("_", "-").to_ascii_lowercase()),
+      None => None,
+  }
+}
+
 fn file_exists(file: &Path) -> bool {
     match fs::metadata(file) {
         Ok(_) => true,
@@ -23,11 +34,7 @@ fn file_exists(file: &Path) -> bool {
 }
 
 fn copy_linker_scripts<P: AsRef<Path>, Q: AsRef<Path>>(target: P, out_path: Q) -> io::Result<()> {
-  let path_prefix = if env::var("CARGO_MANIFEST_DIR").unwrap().find("/examples/").is_none() {
-    Path::new(".")
-  } else {
-    Path::new("./../..")
-  };
+  let path_prefix = path_prefix();
   // Try copying the linker scripts
   let target_dir = Path::new("src/hal").join(target);
   let out_dir: &Path = out_path.as_ref();
@@ -41,6 +48,42 @@ fn copy_linker_scripts<P: AsRef<Path>, Q: AsRef<Path>>(target: P, out_path: Q) -
   Ok(())
 }
 
+fn path_prefix() -> &'static Path {
+  if env::var("CARGO_MANIFEST_DIR").unwrap().find("/examples/").is_none() {
+    Path::new(".")
+  } else {
+    Path::new("./../..")
+  }
+}
+
+/// Assembles the context switch code into libsched.a, the same sched.S is
+/// used for both ARMv6-M and ARMv7-M cores.
+fn build_scheduler<P: AsRef<Path>>(cpu: &str, out_path: P) -> io::Result<()> {
+  let out_dir: &Path = out_path.as_ref();
+  let obj = out_dir.join("sched.o");
+  let lib = out_dir.join("libsched.a");
+
+  let status = try!(Command::new("arm-none-eabi-gcc")
+    .arg(format!("-mcpu={}", cpu))
+    .arg("-mthumb")
+    .arg("-c")
+    .arg(path_prefix().join("src/hal/cortex_common/sched.S"))
+    .arg("-o").arg(&obj)
+    .status());
+  if !status.success() {
+    return Err(io::Error::new(io::ErrorKind::Other, "failed to assemble sched.S"));
+  }
+
+  let status = try!(Command::new("arm-none-eabi-ar")
+    .arg("crs").arg(&lib).arg(&obj)
+    .status());
+  if !status.success() {
+    return Err(io::Error::new(io::ErrorKind::Other, "failed to archive sched.o"));
+  }
+
+  Ok(())
+}
+
 fn main() {
   let platform = match get_platform() {
     Some(p) => p,
@@ -57,6 +100,14 @@ fn main() {
     Err(e) => panic!("Failed to copy linker scripts: {}", e)
   }
 
+  if env::var("CARGO_FEATURE_MULTITASKING").is_ok() {
+    let cpu = get_cpu().expect("multitasking requires a cpu_* feature");
+    match build_scheduler(&cpu, &out_dir) {
+      Ok(_) => println!("cargo:rustc-link-lib=static=sched"),
+      Err(e) => panic!("Failed to build scheduler: {}", e)
+    }
+  }
+
   // Make sure that the output dir is passed to linker
   println!("cargo:rustc-link-search=native={}", out_dir);
 }
@@ -0,0 +1,257 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2014 Vladimir "farcaller" Pouzanov <farcaller@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Decoder for the `zinc::os::deflog` wire format.
+
+/// First byte of every binary log frame.
+const FRAME_START: u8 = 0xFF;
+/// Set in the level byte if some of the arguments were dropped.
+const TRUNCATED_FLAG: u8 = 0x80;
+/// Bytes after the length byte and before the arguments.
+const HEADER_SIZE: usize = 7;
+
+const TAG_U8: u8 = 1;
+const TAG_U16: u8 = 2;
+const TAG_U32: u8 = 3;
+const TAG_I8: u8 = 4;
+const TAG_I16: u8 = 5;
+const TAG_I32: u8 = 6;
+const TAG_BOOL: u8 = 7;
+const TAG_STR: u8 = 8;
+
+/// A decoded log argument.
+#[derive(Debug, PartialEq)]
+enum Arg {
+  Unsigned(u32),
+  Signed(i32),
+  Bool(bool),
+  Str(String),
+}
+
+/// Stream decoder, turns captured output back into text.
+///
+/// Text output from `os::debug` is passed through as is, binary frames are
+/// formatted with the strings from the `.zinc_log` section.
+pub struct Decoder<'a> {
+  strings: &'a [u8],
+  pending: Vec<u8>,
+}
+
+impl<'a> Decoder<'a> {
+  /// Creates a decoder for the contents of the `.zinc_log` section.
+  pub fn new(strings: &'a [u8]) -> Decoder<'a> {
+    Decoder { strings, pending: Vec::new() }
+  }
+
+  /// Feeds captured bytes and returns the text decoded so far. Incomplete
+  /// frames are kept until the next call.
+  pub fn feed(&mut self, bytes: &[u8]) -> String {
+    self.pending.extend_from_slice(bytes);
+    let mut out = String::new();
+    let mut pos = 0;
+
+    while pos < self.pending.len() {
+      if self.pending[pos] != FRAME_START {
+        let end = self.pending[pos..].iter().position(|&b| b == FRAME_START)
+            .map(|p| pos + p).unwrap_or(self.pending.len());
+        out.push_str(&String::from_utf8_lossy(&self.pending[pos..end]));
+        pos = end;
+        continue;
+      }
+
+      if pos + 2 > self.pending.len() {
+        break;
+      }
+      let end = pos + 2 + self.pending[pos + 1] as usize;
+      if end > self.pending.len() {
+        break;
+      }
+      out.push_str(&self.format_frame(&self.pending[pos + 2..end]));
+      pos = end;
+    }
+
+    self.pending.drain(..pos);
+    out
+  }
+
+  fn format_frame(&self, payload: &[u8]) -> String {
+    if payload.len() < HEADER_SIZE {
+      return "<malformed frame>\n".to_string();
+    }
+    let level = payload[0];
+    let offset = payload[1] as usize | (payload[2] as usize) << 8;
+    let timestamp = payload[3] as u32 | (payload[4] as u32) << 8 |
+        (payload[5] as u32) << 16 | (payload[6] as u32) << 24;
+
+    let fmt = match self.string_at(offset) {
+      Some(s) => s,
+      None => return format!("[{}] {} <unknown format string {}>\n",
+                             timestamp, level_name(level), offset),
+    };
+    let args = match decode_args(&payload[HEADER_SIZE..]) {
+      Some(args) => args,
+      None => return format!("[{}] {} <malformed arguments for \"{}\">\n",
+                             timestamp, level_name(level), fmt),
+    };
+
+    let mut line = format!("[{}] {} {}", timestamp, level_name(level),
+                           format_message(&fmt, &args));
+    if level & TRUNCATED_FLAG != 0 {
+      line.push_str(" <truncated>");
+    }
+    line.push('\n');
+    line
+  }
+
+  fn string_at(&self, offset: usize) -> Option<String> {
+    if offset >= self.strings.len() {
+      return None;
+    }
+    let s = &self.strings[offset..];
+    let len = s.iter().position(|&c| c == 0).unwrap_or(s.len());
+    Some(String::from_utf8_lossy(&s[..len]).into_owned())
+  }
+}
+
+fn level_name(level: u8) -> &'static str {
+  match level & !TRUNCATED_FLAG {
+    1 => "ERROR",
+    2 => "WARN",
+    3 => "INFO",
+    4 => "DEBUG",
+    5 => "TRACE",
+    _ => "?",
+  }
+}
+
+fn decode_args(mut data: &[u8]) -> Option<Vec<Arg>> {
+  let mut args = Vec::new();
+  while !data.is_empty() {
+    let tag = data[0];
+    let size = match tag {
+      TAG_U8 | TAG_I8 | TAG_BOOL => 1,
+      TAG_U16 | TAG_I16 => 2,
+      TAG_U32 | TAG_I32 => 4,
+      TAG_STR if data.len() > 1 => 1 + data[1] as usize,
+      _ => return None,
+    };
+    if data.len() < 1 + size {
+      return None;
+    }
+    let v = &data[1..1 + size];
+    let raw = v.iter().rev().fold(0u32, |acc, &b| acc << 8 | b as u32);
+    args.push(match tag {
+      TAG_U8 | TAG_U16 | TAG_U32 => Arg::Unsigned(raw),
+      TAG_I8 => Arg::Signed(raw as u8 as i8 as i32),
+      TAG_I16 => Arg::Signed(raw as u16 as i16 as i32),
+      TAG_I32 => Arg::Signed(raw as i32),
+      TAG_BOOL => Arg::Bool(raw != 0),
+      _ => Arg::Str(String::from_utf8_lossy(&v[1..]).into_owned()),
+    });
+    data = &data[1 + size..];
+  }
+  Some(args)
+}
+
+/// Substitutes `{}`, `{:x}` and `{:?}` placeholders with the arguments.
+fn format_message(fmt: &str, args: &[Arg]) -> String {
+  let mut out = String::new();
+  let mut args = args.iter();
+  let mut chars = fmt.chars().peekable();
+
+  while let Some(c) = chars.next() {
+    match c {
+      '{' if chars.peek() == Some(&'{') => {
+        chars.next();
+        out.push('{');
+      },
+      '}' if chars.peek() == Some(&'}') => {
+        chars.next();
+        out.push('}');
+      },
+      '{' => {
+        let mut spec = String::new();
+        for c in chars.by_ref() {
+          if c == '}' {
+            break;
+          }
+          spec.push(c);
+        }
+        match args.next() {
+          Some(arg) => out.push_str(&format_arg(arg, &spec)),
+          None => out.push_str("<missing>"),
+        }
+      },
+      _ => out.push(c),
+    }
+  }
+  out
+}
+
+fn format_arg(arg: &Arg, spec: &str) -> String {
+  match (arg, spec) {
+    (Arg::Unsigned(v), ":x") => format!("{:x}", v),
+    (Arg::Signed(v), ":x") => format!("{:x}", v),
+    (Arg::Unsigned(v), _) => format!("{}", v),
+    (Arg::Signed(v), _) => format!("{}", v),
+    (Arg::Bool(v), _) => format!("{}", v),
+    (Arg::Str(v), ":?") => format!("{:?}", v),
+    (Arg::Str(v), _) => v.clone(),
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{Decoder, format_message, decode_args, Arg};
+
+  const STRINGS: &[u8] = b"boot\0adc {} vref {:x} {}\0";
+
+  #[test]
+  fn decodes_frame_with_args() {
+    let mut decoder = Decoder::new(STRINGS);
+    let frame = [0xFF, 17, 3, 5, 0, 42, 0, 0, 0,
+                 2, 0x2C, 0x01, 3, 0xFF, 0, 0, 0, 4, 0xFE];
+    assert_eq!(decoder.feed(&frame), "[42] INFO adc 300 vref ff -2\n");
+  }
+
+  #[test]
+  fn passes_text_through() {
+    let mut decoder = Decoder::new(STRINGS);
+    let mut stream = b"[1] INFO text\n".to_vec();
+    stream.extend_from_slice(&[0xFF, 7, 0x81, 0, 0, 1, 0, 0, 0]);
+    assert_eq!(decoder.feed(&stream),
+               "[1] INFO text\n[1] ERROR boot <truncated>\n");
+  }
+
+  #[test]
+  fn keeps_partial_frames() {
+    let mut decoder = Decoder::new(STRINGS);
+    let frame = [0xFF, 7, 2, 0, 0, 9, 0, 0, 0];
+    assert_eq!(decoder.feed(&frame[..4]), "");
+    assert_eq!(decoder.feed(&frame[4..]), "[9] WARN boot\n");
+  }
+
+  #[test]
+  fn decodes_strings() {
+    assert_eq!(decode_args(&[8, 2, b'h', b'i', 7, 1]),
+               Some(vec![Arg::Str("hi".to_string()), Arg::Bool(true)]));
+    assert_eq!(decode_args(&[8, 5, b'h']), None);
+  }
+
+  #[test]
+  fn formats_escapes_and_missing_args() {
+    assert_eq!(format_message("{{}} {}", &[]), "{} <missing>");
+  }
+}
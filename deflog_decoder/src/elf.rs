@@ -0,0 +1,145 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2014 Vladimir "farcaller" Pouzanov <farcaller@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Minimal ELF32 little-endian reader, enough to extract a section by name.
+
+const SHDR_NAME: usize = 0;
+const SHDR_OFFSET: usize = 16;
+const SHDR_SIZE: usize = 20;
+
+fn read_u16(data: &[u8], at: usize) -> Result<u16, String> {
+  if at + 2 > data.len() {
+    return Err("truncated ELF file".to_string());
+  }
+  Ok(data[at] as u16 | (data[at + 1] as u16) << 8)
+}
+
+fn read_u32(data: &[u8], at: usize) -> Result<u32, String> {
+  if at + 4 > data.len() {
+    return Err("truncated ELF file".to_string());
+  }
+  Ok(data[at] as u32 | (data[at + 1] as u32) << 8 |
+     (data[at + 2] as u32) << 16 | (data[at + 3] as u32) << 24)
+}
+
+fn slice(data: &[u8], offset: u32, size: u32) -> Result<&[u8], String> {
+  let start = offset as usize;
+  let end = start + size as usize;
+  if end > data.len() {
+    return Err("section out of file bounds".to_string());
+  }
+  Ok(&data[start..end])
+}
+
+/// Returns the contents of the section called `name`, or `None` if there's
+/// no such section.
+pub fn find_section<'a>(data: &'a [u8], name: &str) -> Result<Option<&'a [u8]>, String> {
+  if data.len() < 52 || &data[0..4] != b"\x7fELF" {
+    return Err("not an ELF file".to_string());
+  }
+  if data[4] != 1 || data[5] != 1 {
+    return Err("only 32-bit little-endian ELF files are supported".to_string());
+  }
+
+  let shoff = read_u32(data, 0x20)? as usize;
+  let shentsize = read_u16(data, 0x2e)? as usize;
+  let shnum = read_u16(data, 0x30)? as usize;
+  let shstrndx = read_u16(data, 0x32)? as usize;
+
+  let header = |index: usize| shoff + index * shentsize;
+  let strtab = slice(data,
+      read_u32(data, header(shstrndx) + SHDR_OFFSET)?,
+      read_u32(data, header(shstrndx) + SHDR_SIZE)?)?;
+
+  for i in 0..shnum {
+    let name_offset = read_u32(data, header(i) + SHDR_NAME)? as usize;
+    if name_offset >= strtab.len() {
+      continue;
+    }
+    let section_name = &strtab[name_offset..];
+    let len = section_name.iter().position(|&c| c == 0).unwrap_or(section_name.len());
+    if &section_name[..len] == name.as_bytes() {
+      let offset = read_u32(data, header(i) + SHDR_OFFSET)?;
+      let size = read_u32(data, header(i) + SHDR_SIZE)?;
+      return slice(data, offset, size).map(Some);
+    }
+  }
+  Ok(None)
+}
+
+#[cfg(test)]
+pub mod test {
+  use super::find_section;
+
+  fn push_u16(v: &mut Vec<u8>, val: u16) {
+    v.push(val as u8);
+    v.push((val >> 8) as u8);
+  }
+
+  fn push_u32(v: &mut Vec<u8>, val: u32) {
+    push_u16(v, val as u16);
+    push_u16(v, (val >> 16) as u16);
+  }
+
+  /// Builds an ELF file with a null section, `.shstrtab` and `.zinc_log`.
+  pub fn build_elf(zinc_log: &[u8]) -> Vec<u8> {
+    let shstrtab = b"\0.shstrtab\0.zinc_log\0";
+    let mut elf = vec![0x7f, b'E', b'L', b'F', 1, 1, 1];
+    elf.resize(52, 0);
+
+    let shstrtab_offset = elf.len() as u32;
+    elf.extend_from_slice(shstrtab);
+    let zinc_log_offset = elf.len() as u32;
+    elf.extend_from_slice(zinc_log);
+    while elf.len() % 4 != 0 {
+      elf.push(0);
+    }
+
+    let shoff = elf.len() as u32;
+    elf.resize(shoff as usize + 40, 0);
+    for &(name, offset, size) in [(1, shstrtab_offset, shstrtab.len() as u32),
+                                  (11, zinc_log_offset, zinc_log.len() as u32)].iter() {
+      let mut shdr = Vec::new();
+      push_u32(&mut shdr, name);
+      shdr.resize(16, 0);
+      push_u32(&mut shdr, offset);
+      push_u32(&mut shdr, size);
+      shdr.resize(40, 0);
+      elf.extend_from_slice(&shdr);
+    }
+
+    let mut header = Vec::new();
+    push_u32(&mut header, shoff);
+    elf[0x20..0x24].copy_from_slice(&header);
+    let mut header = Vec::new();
+    push_u16(&mut header, 40);
+    push_u16(&mut header, 3);
+    push_u16(&mut header, 1);
+    elf[0x2e..0x34].copy_from_slice(&header);
+    elf
+  }
+
+  #[test]
+  fn finds_section_by_name() {
+    let elf = build_elf(b"hello\0");
+    assert_eq!(find_section(&elf, ".zinc_log").unwrap(), Some(&b"hello\0"[..]));
+    assert_eq!(find_section(&elf, ".text").unwrap(), None);
+  }
+
+  #[test]
+  fn rejects_non_elf_files() {
+    assert!(find_section(&[0; 64], ".zinc_log").is_err());
+  }
+}
@@ -0,0 +1,72 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2014 Vladimir "farcaller" Pouzanov <farcaller@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Host decoder for `zinc::os::deflog` binary logs.
+//!
+//! Usage: `deflog_decoder <firmware.elf> [capture]`
+//!
+//! Reads the interned format strings from the `.zinc_log` section of the
+//! firmware and decodes the captured debug output, from a file or from stdin
+//! (e.g. piped from a serial port), printing text to stdout.
+
+use std::env;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::process;
+
+mod decode;
+mod elf;
+
+fn run() -> Result<(), String> {
+  let args: Vec<String> = env::args().collect();
+  if args.len() < 2 || args.len() > 3 {
+    return Err(format!("usage: {} <firmware.elf> [capture]", args[0]));
+  }
+
+  let mut firmware = Vec::new();
+  File::open(&args[1]).and_then(|mut f| f.read_to_end(&mut firmware))
+      .map_err(|e| format!("{}: {}", args[1], e))?;
+  let strings = match elf::find_section(&firmware, ".zinc_log")? {
+    Some(s) => s,
+    None => return Err(format!("{}: no .zinc_log section", args[1])),
+  };
+
+  let mut input: Box<dyn Read> = if args.len() == 3 {
+    Box::new(File::open(&args[2]).map_err(|e| format!("{}: {}", args[2], e))?)
+  } else {
+    Box::new(io::stdin())
+  };
+
+  let mut decoder = decode::Decoder::new(strings);
+  let mut buf = [0u8; 256];
+  let stdout = io::stdout();
+  loop {
+    let len = input.read(&mut buf).map_err(|e| e.to_string())?;
+    if len == 0 {
+      return Ok(());
+    }
+    let text = decoder.feed(&buf[..len]);
+    let mut out = stdout.lock();
+    out.write_all(text.as_bytes()).and_then(|_| out.flush())
+        .map_err(|e| e.to_string())?;
+  }
+}
+
+fn main() {
+  if let Err(e) = run() {
+    let _ = writeln!(io::stderr(), "{}", e);
+    process::exit(1);
+  }
+}
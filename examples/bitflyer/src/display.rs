@@ -7,6 +7,7 @@ use core::cmp::{min,max};
 use core::slice::{Iter};
 use core::iter::{Map};
 use zinc::util::strconv;
+use zinc::drivers::lcd::color::Monochrome;
 use core::option::Option::Some;
 
 pub struct Image {
@@ -207,14 +208,19 @@ fn paint_onerow_misaligned<O: ops::Op>(img: &Image, x: i16, y: i16) {
     }
 }
 
-pub fn set_pixel(val: bool, x: u8, y: u8) {
+/// Lit pixel.
+pub const ON: Monochrome = Monochrome(true);
+/// Dark pixel.
+pub const OFF: Monochrome = Monochrome(false);
+
+pub fn set_pixel(color: Monochrome, x: u8, y: u8) {
     let byte_index = (((y / 8) as usize) * 128) + x as usize;
     let byte_mask = 1 << y % 8;
     unsafe {
         let dest: &mut u8 = &mut BUFFER.buffer[byte_index as usize];
-        match (val) {
-            true  => *dest |= byte_mask,
-            false => *dest &= !byte_mask
+        match color {
+            Monochrome(true)  => *dest |= byte_mask,
+            Monochrome(false) => *dest &= !byte_mask
         };
     }
     invalidate();
@@ -185,74 +185,74 @@ fn game_over(score: u32) {
 
     const ANIM_TIME:f32 = 0.0001;
     for dx in 0..33 {
-        display::set_pixel(true, 64-dx, 16);
-        display::set_pixel(true, 64+dx, 16);
-        display::set_pixel(true, 64-dx, 24);
-        display::set_pixel(true, 65-dx, 25);
-        display::set_pixel(true, 64+dx, 24);
-        display::set_pixel(true, 65+dx, 25);
+        display::set_pixel(display::ON, 64-dx, 16);
+        display::set_pixel(display::ON, 64+dx, 16);
+        display::set_pixel(display::ON, 64-dx, 24);
+        display::set_pixel(display::ON, 65-dx, 25);
+        display::set_pixel(display::ON, 64+dx, 24);
+        display::set_pixel(display::ON, 65+dx, 25);
         display::update_display();
         ubit::sleep(ANIM_TIME);
     }
     for dy in 0..5 {
-        display::set_pixel(true, 31, 16 + dy);
-        display::set_pixel(true, 31, 24 - dy);
-        display::set_pixel(true, 97, 16 + dy);
-        display::set_pixel(true, 97, 24 - dy);
-        display::set_pixel(true, 98, 17 + dy);
-        display::set_pixel(true, 98, 25 - dy);
+        display::set_pixel(display::ON, 31, 16 + dy);
+        display::set_pixel(display::ON, 31, 24 - dy);
+        display::set_pixel(display::ON, 97, 16 + dy);
+        display::set_pixel(display::ON, 97, 24 - dy);
+        display::set_pixel(display::ON, 98, 17 + dy);
+        display::set_pixel(display::ON, 98, 25 - dy);
         display::update_display();
         ubit::sleep(ANIM_TIME);
     }
     for dx in 33..58 {
-        display::set_pixel(true, 64-dx, 20);
-        display::set_pixel(true, 64+dx, 20);
+        display::set_pixel(display::ON, 64-dx, 20);
+        display::set_pixel(display::ON, 64+dx, 20);
         display::update_display();
         ubit::sleep(ANIM_TIME);
     }
     for dy in 20..46 {
-        display::set_pixel(true, 6, dy);
-        display::set_pixel(true, 122, dy);
+        display::set_pixel(display::ON, 6, dy);
+        display::set_pixel(display::ON, 122, dy);
         display::update_display();
         ubit::sleep(ANIM_TIME);
     }
     for dx in 0..5 {
-        display::set_pixel(true, 6+dx, 45);
-        display::set_pixel(true, 121-dx, 45);
+        display::set_pixel(display::ON, 6+dx, 45);
+        display::set_pixel(display::ON, 121-dx, 45);
         display::update_display();
         ubit::sleep(ANIM_TIME);
     }
     for dy in 0..5 {
-        display::set_pixel(true, 11, 44 + dy);
-        display::set_pixel(true, 11, 44 - dy);
-        display::set_pixel(true, 115, 44 + dy);
-        display::set_pixel(true, 115, 44 - dy);
-        display::set_pixel(true, 116, 45 + dy);
-        display::set_pixel(true, 116, 45 - dy);
+        display::set_pixel(display::ON, 11, 44 + dy);
+        display::set_pixel(display::ON, 11, 44 - dy);
+        display::set_pixel(display::ON, 115, 44 + dy);
+        display::set_pixel(display::ON, 115, 44 - dy);
+        display::set_pixel(display::ON, 116, 45 + dy);
+        display::set_pixel(display::ON, 116, 45 - dy);
         display::update_display();
         ubit::sleep(ANIM_TIME);
     }
     for dx in 0..38 {
-        display::set_pixel(true, 11+dx, 39);
-        display::set_pixel(true, 115-dx, 39);
+        display::set_pixel(display::ON, 11+dx, 39);
+        display::set_pixel(display::ON, 115-dx, 39);
 
-        display::set_pixel(true, 11+dx, 49);
-        display::set_pixel(true, 115-dx, 49);
+        display::set_pixel(display::ON, 11+dx, 49);
+        display::set_pixel(display::ON, 115-dx, 49);
 
-        display::set_pixel(true, 12+dx, 50);
-        display::set_pixel(true, 116-dx, 50);
+        display::set_pixel(display::ON, 12+dx, 50);
+        display::set_pixel(display::ON, 116-dx, 50);
 
         display::update_display();
         ubit::sleep(ANIM_TIME);
     }
 
     for dy in 0..6 {
-        display::set_pixel(true, 49, 39 + dy);
-        display::set_pixel(true, 50, 40 + dy);
-        display::set_pixel(true, 49, 49 - dy);
-        display::set_pixel(true, 50, 50 - dy);
-        display::set_pixel(true, 78, 39 + dy);
-        display::set_pixel(true, 78, 49 - dy);
+        display::set_pixel(display::ON, 49, 39 + dy);
+        display::set_pixel(display::ON, 50, 40 + dy);
+        display::set_pixel(display::ON, 49, 49 - dy);
+        display::set_pixel(display::ON, 50, 50 - dy);
+        display::set_pixel(display::ON, 78, 39 + dy);
+        display::set_pixel(display::ON, 78, 49 - dy);
         display::update_display();
         ubit::sleep(ANIM_TIME);
     }
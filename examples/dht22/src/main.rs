@@ -1,10 +1,15 @@
-#![feature(start, plugin, core_intrinsics)]
+#![feature(start, plugin, core_intrinsics, const_fn)]
 #![no_std]
 #![plugin(macro_platformtree)]
 
 extern crate zinc;
 
 use core::option::Option::{Some, None};
+use core::result::Result::{Ok, Err};
+
+use zinc::hal::lpc17xx::edge_capture::GpioEdgeCapture;
+use zinc::hal::lpc17xx::pin::Port::Port0;
+use zinc::hal::lpc17xx::timer::TimerPeripheral::Timer1;
 
 platformtree!(
   lpc17xx@mcu {
@@ -62,23 +67,39 @@ platformtree!(
   }
 );
 
+/// Falling edges of dht_pin, timed by timer 1.
+static CAPTURE: GpioEdgeCapture = GpioEdgeCapture::new(Port0, 4, Timer1);
+
+#[no_mangle]
+pub unsafe extern fn isr_eint_3() {
+  CAPTURE.on_interrupt();
+}
+
 #[zinc_task]
 fn run(args: &pt::run_args) {
   use zinc::drivers::chario::CharIO;
+  use zinc::drivers::dht22::Error;
   use zinc::hal::timer::Timer;
 
   args.timer.wait(3);
 
-  let ret = args.dht.read();
+  let ret = args.dht.read_captured(&CAPTURE);
   match ret {
-    Some(v) => {
+    Ok(v) => {
       args.uart.puts("temp:     "); args.uart.puti(v.temperature as u32);
       args.uart.puts("\n");
       args.uart.puts("humidity: "); args.uart.puti(v.humidity as u32);
       args.uart.puts("\n");
     },
-    None => {
-      args.uart.puts("fail\n");
+    Err(Error::NoResponse) => {
+      args.uart.puts("fail: no sensor\n");
+    },
+    Err(Error::Timeout(bit)) => {
+      args.uart.puts("fail: timeout at bit "); args.uart.puti(bit as u32);
+      args.uart.puts("\n");
+    },
+    Err(Error::BadTiming) | Err(Error::Checksum) => {
+      args.uart.puts("fail: corrupted data\n");
     },
   }
 }
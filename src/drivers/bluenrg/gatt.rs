@@ -0,0 +1,311 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2014 Dzmitry "kvark" Malyshau <kvarkus@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! GATT server on top of the BlueNRG HCI layer.
+//!
+//! ```ignore
+//! let hci = Hci::new(&nrg);
+//! try!(hci.reset());
+//! try!(hci.gatt_init());
+//! try!(hci.gap_init(Role::Peripheral));
+//! let gatt = GattServer::new(&hci);
+//! let service = try!(gatt.add_service(Uuid::Uuid16(0x180f), 4));
+//! let level = try!(gatt.add_characteristic(&service, Uuid::Uuid16(0x2a19), 1,
+//!                                          props::READ | props::NOTIFY));
+//! try!(hci.set_discoverable(&Advertising::default(), b"Zinc"));
+//! loop {
+//!   try!(gatt.poll(&mut handler));
+//!   try!(gatt.update(&level, &[battery_level()]));
+//! }
+//! ```
+
+use core::result::Result::{Ok, Err};
+use core::result::Result;
+use core::slice::SliceExt;
+
+use hal::pin::Gpio;
+use hal::spi::Spi;
+use super::hci::{self, Hci, Event, Error, Params};
+
+/// Characteristic properties, combined with `|`.
+pub mod props {
+  /// Value can be read.
+  pub const READ: u8 = 0x02;
+  /// Value can be written without a response.
+  pub const WRITE_WITHOUT_RESPONSE: u8 = 0x04;
+  /// Value can be written.
+  pub const WRITE: u8 = 0x08;
+  /// Value changes can be notified.
+  pub const NOTIFY: u8 = 0x10;
+  /// Value changes can be indicated.
+  pub const INDICATE: u8 = 0x20;
+}
+
+/// Service or characteristic UUID.
+#[derive(Clone, Copy)]
+pub enum Uuid {
+  /// Bluetooth SIG assigned 16-bit UUID.
+  Uuid16(u16),
+  /// Custom 128-bit UUID, least significant byte first.
+  Uuid128([u8; 16]),
+}
+
+impl Uuid {
+  fn push(&self, params: &mut Params) {
+    match *self {
+      Uuid::Uuid16(uuid) => { params.u8(0x01).u16(uuid); },
+      Uuid::Uuid128(ref uuid) => { params.u8(0x02).bytes(uuid); },
+    }
+  }
+}
+
+/// A service added to the server.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Service {
+  /// Service handle.
+  pub handle: u16,
+}
+
+/// A characteristic added to the server.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Characteristic {
+  /// Handle of the owning service.
+  pub service: u16,
+  /// Characteristic declaration handle.
+  pub handle: u16,
+}
+
+impl Characteristic {
+  /// Returns the handle of the characteristic value attribute, as reported by
+  /// `GattHandler::on_write`.
+  pub fn value_handle(&self) -> u16 {
+    self.handle + 1
+  }
+
+  /// Returns the handle of the client configuration descriptor, written when a
+  /// client enables notifications or indications.
+  pub fn config_handle(&self) -> u16 {
+    self.handle + 2
+  }
+}
+
+/// Callbacks for GATT server events.
+pub trait GattHandler {
+  /// Called when a central connects.
+  fn on_connect(&mut self, _connection: u16, _peer: [u8; 6]) {}
+
+  /// Called when a connection is closed.
+  fn on_disconnect(&mut self, _connection: u16, _reason: u8) {}
+
+  /// Called when a client writes `attribute`, either a characteristic value or
+  /// a client configuration descriptor.
+  fn on_write(&mut self, _connection: u16, _attribute: u16, _data: &[u8]) {}
+}
+
+/// GATT server.
+pub struct GattServer<'a, G: 'a, S: 'a> {
+  hci: &'a Hci<'a, G, S>,
+}
+
+impl<'a, G: Gpio, S: Spi> GattServer<'a, G, S> {
+  /// Creates a GATT server. `Hci::gatt_init` and `Hci::gap_init` must have
+  /// been called.
+  pub fn new(hci: &'a Hci<'a, G, S>) -> GattServer<'a, G, S> {
+    GattServer { hci: hci }
+  }
+
+  /// Adds a primary service with room for `max_attributes` attributes. Each
+  /// characteristic takes 2 attributes, plus 1 if it notifies or indicates.
+  pub fn add_service(&self, uuid: Uuid, max_attributes: u8) -> Result<Service, Error> {
+    let mut params = Params::new();
+    uuid.push(&mut params);
+    params
+      .u8(0x01)                          // primary service
+      .u8(max_attributes.saturating_add(1)); // plus the service declaration
+    let mut ret = [0u8; 2];
+    let len = try!(self.hci.command(hci::opcode::GATT_ADD_SERVICE, &params, &mut ret));
+    if len < 2 {
+      return Err(Error::BadPacket);
+    }
+    Ok(Service { handle: (ret[0] as u16) | ((ret[1] as u16) << 8) })
+  }
+
+  /// Adds a characteristic with a value of up to `max_len` bytes. Writes are
+  /// reported to `GattHandler::on_write`.
+  pub fn add_characteristic(&self, service: &Service, uuid: Uuid, max_len: u8,
+                            properties: u8) -> Result<Characteristic, Error> {
+    let writable = properties & (props::WRITE | props::WRITE_WITHOUT_RESPONSE) != 0;
+    let mut params = Params::new();
+    params.u16(service.handle);
+    uuid.push(&mut params);
+    params
+      .u8(max_len)
+      .u8(properties)
+      .u8(0x00)                          // no security
+      .u8(if writable { 0x01 } else { 0x00 }) // notify the app of writes
+      .u8(16)                            // encryption key size
+      .u8(0x01);                         // variable length value
+    let mut ret = [0u8; 2];
+    let len = try!(self.hci.command(hci::opcode::GATT_ADD_CHARACTERISTIC, &params, &mut ret));
+    if len < 2 {
+      return Err(Error::BadPacket);
+    }
+    Ok(Characteristic {
+      service: service.handle,
+      handle: (ret[0] as u16) | ((ret[1] as u16) << 8),
+    })
+  }
+
+  /// Sets the value of a characteristic. Connected clients that enabled
+  /// notifications or indications are notified.
+  pub fn update(&self, characteristic: &Characteristic, value: &[u8]) -> Result<(), Error> {
+    if value.len() > 0xff {
+      return Err(Error::TooLong);
+    }
+    let mut params = Params::new();
+    params
+      .u16(characteristic.service)
+      .u16(characteristic.handle)
+      .u8(0)                             // offset
+      .u8(value.len() as u8)
+      .bytes(value);
+    self.hci.command(hci::opcode::GATT_UPDATE_CHARACTERISTIC_VALUE, &params, &mut [])
+      .map(|_| ())
+  }
+
+  /// Dispatches one pending event to `handler`, starting with the events
+  /// queued while commands such as `update` were waiting. Returns `false` if
+  /// there was nothing to read.
+  pub fn poll<H: GattHandler>(&self, handler: &mut H) -> Result<bool, Error> {
+    let mut buf = [0u8; hci::MAX_EVENT_LEN];
+    match try!(self.hci.next_event(&mut buf)) {
+      None => return Ok(false),
+      Some(Event::ConnectionComplete { status: 0, handle, peer }) =>
+        handler.on_connect(handle, peer),
+      Some(Event::DisconnectionComplete { handle, reason }) =>
+        handler.on_disconnect(handle, reason),
+      Some(Event::AttributeModified { connection, attribute, data }) =>
+        handler.on_write(connection, attribute, data),
+      Some(_) => {},
+    }
+    Ok(true)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use std::vec::Vec;
+
+  use drivers::bluenrg::BlueNrg;
+  use drivers::bluenrg::hci::Hci;
+  use drivers::bluenrg::hci::test::{Pin, Replay, command_frame};
+  use super::{GattServer, GattHandler, Uuid, Service, Characteristic, props};
+
+  struct Handler {
+    writes: Vec<(u16, u16, Vec<u8>)>,
+    connected: Option<u16>,
+  }
+
+  impl GattHandler for Handler {
+    fn on_connect(&mut self, connection: u16, _peer: [u8; 6]) {
+      self.connected = Some(connection);
+    }
+
+    fn on_disconnect(&mut self, _connection: u16, _reason: u8) {
+      self.connected = None;
+    }
+
+    fn on_write(&mut self, connection: u16, attribute: u16, data: &[u8]) {
+      self.writes.push((connection, attribute, data.to_vec()));
+    }
+  }
+
+  #[test]
+  fn should_add_service_and_characteristic() {
+    let spi = Replay::new();
+    let nrg = BlueNrg::new(Pin, &spi);
+    let hci = Hci::new(&nrg);
+    let gatt = GattServer::new(&hci);
+
+    let add_service = [0x01, 0x02, 0xfd, 0x05, 0x01, 0x0f, 0x18, 0x01, 0x05];
+    spi.accept_write(add_service.len());
+    spi.packet(&[0x04, 0x0e, 0x06, 0x01, 0x02, 0xfd, 0x00, 0x0c, 0x00]);
+    let service = gatt.add_service(Uuid::Uuid16(0x180f), 4).ok();
+    assert!(service == Some(Service { handle: 0x0c }));
+    assert!(spi.take_written()[..5 + add_service.len()] == command_frame(&add_service)[..]);
+
+    let add_char = [0x01, 0x04, 0xfd, 0x0b, 0x0c, 0x00, 0x01, 0x19, 0x2a,
+                    0x01, 0x1a, 0x00, 0x01, 0x10, 0x01];
+    spi.accept_write(add_char.len());
+    spi.packet(&[0x04, 0x0e, 0x06, 0x01, 0x04, 0xfd, 0x00, 0x0d, 0x00]);
+    let characteristic = gatt.add_characteristic(&Service { handle: 0x0c }, Uuid::Uuid16(0x2a19),
+                                                 1, props::READ | props::WRITE | props::NOTIFY);
+    assert!(characteristic.ok() == Some(Characteristic { service: 0x0c, handle: 0x0d }));
+    assert!(spi.take_written()[..5 + add_char.len()] == command_frame(&add_char)[..]);
+
+    let update = [0x01, 0x06, 0xfd, 0x07, 0x0c, 0x00, 0x0d, 0x00, 0x00, 0x01, 0x55];
+    spi.accept_write(update.len());
+    spi.packet(&[0x04, 0x0e, 0x04, 0x01, 0x06, 0xfd, 0x00]);
+    assert!(gatt.update(&Characteristic { service: 0x0c, handle: 0x0d }, &[0x55]).is_ok());
+    assert!(spi.take_written()[..5 + update.len()] == command_frame(&update)[..]);
+  }
+
+  #[test]
+  fn poll_should_dispatch_events() {
+    let spi = Replay::new();
+    let nrg = BlueNrg::new(Pin, &spi);
+    let hci = Hci::new(&nrg);
+    let gatt = GattServer::new(&hci);
+    let mut handler = Handler { writes: Vec::new(), connected: None };
+
+    spi.packet(&[0x04, 0x3e, 0x13, 0x01, 0x00, 0x01, 0x08, 0x01, 0x00,
+                 1, 2, 3, 4, 5, 6, 0x28, 0x00, 0x00, 0x00, 0xd0, 0x07, 0x05]);
+    spi.packet(&[0x04, 0xff, 0x08, 0x01, 0x0c, 0x01, 0x08, 0x0e, 0x00, 0x01, 0x2a]);
+
+    assert!(gatt.poll(&mut handler).ok() == Some(true));
+    assert!(handler.connected == Some(0x0801));
+    assert!(gatt.poll(&mut handler).ok() == Some(true));
+    assert!(handler.writes.len() == 1);
+    assert!(handler.writes[0].0 == 0x0801 && handler.writes[0].1 == 0x0e);
+    assert!(handler.writes[0].2[..] == [0x2a]);
+    assert!(gatt.poll(&mut handler).ok() == Some(false));
+
+    spi.packet(&[0x04, 0x05, 0x04, 0x00, 0x01, 0x08, 0x13]);
+    assert!(gatt.poll(&mut handler).ok() == Some(true));
+    assert!(handler.connected == None);
+  }
+
+  #[test]
+  fn poll_should_dispatch_writes_received_during_update() {
+    let spi = Replay::new();
+    let nrg = BlueNrg::new(Pin, &spi);
+    let hci = Hci::new(&nrg);
+    let gatt = GattServer::new(&hci);
+    let mut handler = Handler { writes: Vec::new(), connected: None };
+
+    let update = [0x01, 0x06, 0xfd, 0x07, 0x0c, 0x00, 0x0d, 0x00, 0x00, 0x01, 0x55];
+    spi.accept_write(update.len());
+    spi.packet(&[0x04, 0xff, 0x08, 0x01, 0x0c, 0x01, 0x08, 0x0e, 0x00, 0x01, 0x2a]);
+    spi.packet(&[0x04, 0x0e, 0x04, 0x01, 0x06, 0xfd, 0x00]);
+    assert!(gatt.update(&Characteristic { service: 0x0c, handle: 0x0d }, &[0x55]).is_ok());
+    assert!(handler.writes.is_empty());
+
+    assert!(gatt.poll(&mut handler).ok() == Some(true));
+    assert!(handler.writes.len() == 1);
+    assert!(handler.writes[0].0 == 0x0801 && handler.writes[0].1 == 0x0e);
+    assert!(handler.writes[0].2[..] == [0x2a]);
+    assert!(gatt.poll(&mut handler).ok() == Some(false));
+  }
+}
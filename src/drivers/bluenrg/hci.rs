@@ -0,0 +1,715 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2014 Dzmitry "kvark" Malyshau <kvarkus@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! HCI commands and events for BlueNRG, including the ACI vendor commands.
+//!
+//! Commands are synchronous: `Hci::command` sends a command and polls for its
+//! Command Complete or Command Status event. Other events that arrive in the
+//! meantime are queued, and `Hci::next_event` returns them before reading new
+//! ones. The queue holds `EVENT_QUEUE_SIZE` bytes, events that don't fit are
+//! dropped and counted in `Hci::dropped_events`.
+// http://www.st.com/st-web-ui/static/active/en/resource/technical/document/user_manual/DM00162667.pdf
+
+use core::cell::RefCell;
+use core::cmp;
+use core::result::Result::{Ok, Err};
+use core::result::Result;
+use core::slice::SliceExt;
+
+use hal::pin::Gpio;
+use hal::spi::Spi;
+use super::BlueNrg;
+
+const COMMAND_PACKET: u8 = 0x01;
+const EVENT_PACKET: u8 = 0x04;
+
+/// Largest HCI event packet: type, code, length and 255 parameter bytes.
+pub const MAX_EVENT_LEN: usize = 3 + 255;
+
+/// Size in bytes of the queue of events received while waiting for a command.
+pub const EVENT_QUEUE_SIZE: usize = 512;
+
+/// Command opcodes, OGF << 10 | OCF.
+#[allow(missing_docs)]
+pub mod opcode {
+  pub const RESET: u16 = 0x0c03;
+  pub const LE_SET_ADVERTISING_DATA: u16 = 0x2008;
+  pub const GAP_SET_NON_DISCOVERABLE: u16 = 0xfc81;
+  pub const GAP_SET_DISCOVERABLE: u16 = 0xfc83;
+  pub const GAP_INIT: u16 = 0xfc8a;
+  pub const GATT_INIT: u16 = 0xfd01;
+  pub const GATT_ADD_SERVICE: u16 = 0xfd02;
+  pub const GATT_ADD_CHARACTERISTIC: u16 = 0xfd04;
+  pub const GATT_UPDATE_CHARACTERISTIC_VALUE: u16 = 0xfd06;
+}
+
+const EVENT_DISCONNECTION_COMPLETE: u8 = 0x05;
+const EVENT_COMMAND_COMPLETE: u8 = 0x0e;
+const EVENT_COMMAND_STATUS: u8 = 0x0f;
+const EVENT_LE_META: u8 = 0x3e;
+const EVENT_VENDOR: u8 = 0xff;
+const LE_CONNECTION_COMPLETE: u8 = 0x01;
+const VENDOR_GATT_ATTRIBUTE_MODIFIED: u16 = 0x0c01;
+
+/// HCI error codes.
+#[derive(Clone, Copy)]
+pub enum Error {
+  /// SPI communication failed.
+  Spi(super::Error),
+  /// The controller returned a non-zero status.
+  Status(u8),
+  /// A received packet is malformed.
+  BadPacket,
+  /// Command parameters don't fit in a packet.
+  TooLong,
+  /// The command didn't complete in time.
+  Timeout,
+}
+
+/// An HCI event, borrowing the packet it was parsed from.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Event<'a> {
+  /// A command completed, `params` are its return parameters after the status.
+  CommandComplete {
+    /// Opcode of the command.
+    opcode: u16,
+    /// Zero on success.
+    status: u8,
+    /// Return parameters.
+    params: &'a [u8],
+  },
+  /// A command was accepted or rejected, it completes later.
+  CommandStatus {
+    /// Opcode of the command.
+    opcode: u16,
+    /// Zero on success.
+    status: u8,
+  },
+  /// A central connected.
+  ConnectionComplete {
+    /// Zero on success.
+    status: u8,
+    /// Connection handle.
+    handle: u16,
+    /// Peer device address, least significant byte first.
+    peer: [u8; 6],
+  },
+  /// A connection was closed.
+  DisconnectionComplete {
+    /// Connection handle.
+    handle: u16,
+    /// HCI reason code.
+    reason: u8,
+  },
+  /// A client wrote an attribute.
+  AttributeModified {
+    /// Connection handle.
+    connection: u16,
+    /// Attribute handle.
+    attribute: u16,
+    /// Written value.
+    data: &'a [u8],
+  },
+  /// Any other event.
+  Other {
+    /// Event code.
+    code: u8,
+    /// Event parameters.
+    params: &'a [u8],
+  },
+}
+
+fn read_u16(bytes: &[u8]) -> u16 {
+  (bytes[0] as u16) | ((bytes[1] as u16) << 8)
+}
+
+impl<'a> Event<'a> {
+  /// Parses an HCI event packet.
+  pub fn parse(packet: &'a [u8]) -> Result<Event<'a>, Error> {
+    if packet.len() < 3 || packet[0] != EVENT_PACKET || packet.len() < 3 + packet[2] as usize {
+      return Err(Error::BadPacket);
+    }
+    let code = packet[1];
+    let params = &packet[3..3 + packet[2] as usize];
+
+    let event = match code {
+      EVENT_COMMAND_COMPLETE if params.len() >= 4 => Event::CommandComplete {
+        opcode: read_u16(&params[1..]),
+        status: params[3],
+        params: &params[4..],
+      },
+      EVENT_COMMAND_STATUS if params.len() >= 4 => Event::CommandStatus {
+        opcode: read_u16(&params[2..]),
+        status: params[0],
+      },
+      EVENT_DISCONNECTION_COMPLETE if params.len() >= 4 => Event::DisconnectionComplete {
+        handle: read_u16(&params[1..]),
+        reason: params[3],
+      },
+      EVENT_LE_META if params.len() >= 12 && params[0] == LE_CONNECTION_COMPLETE => {
+        let mut peer = [0; 6];
+        peer.copy_from_slice(&params[6..12]);
+        Event::ConnectionComplete {
+          status: params[1],
+          handle: read_u16(&params[2..]),
+          peer: peer,
+        }
+      },
+      EVENT_VENDOR if params.len() >= 7 && read_u16(params) == VENDOR_GATT_ATTRIBUTE_MODIFIED => {
+        let len = params[6] as usize;
+        if params.len() < 7 + len {
+          return Err(Error::BadPacket);
+        }
+        Event::AttributeModified {
+          connection: read_u16(&params[2..]),
+          attribute: read_u16(&params[4..]),
+          data: &params[7..7 + len],
+        }
+      },
+      EVENT_COMMAND_COMPLETE | EVENT_COMMAND_STATUS | EVENT_DISCONNECTION_COMPLETE =>
+        return Err(Error::BadPacket),
+      _ => Event::Other { code: code, params: params },
+    };
+    Ok(event)
+  }
+}
+
+/// Command parameters being assembled.
+pub struct Params {
+  buf: [u8; 255],
+  len: usize,
+  overflow: bool,
+}
+
+impl Params {
+  /// Creates empty parameters.
+  pub fn new() -> Params {
+    Params { buf: [0; 255], len: 0, overflow: false }
+  }
+
+  /// Appends a byte.
+  pub fn u8(&mut self, value: u8) -> &mut Params {
+    self.bytes(&[value])
+  }
+
+  /// Appends a 16-bit value, least significant byte first.
+  pub fn u16(&mut self, value: u16) -> &mut Params {
+    self.bytes(&[value as u8, (value >> 8) as u8])
+  }
+
+  /// Appends raw bytes.
+  pub fn bytes(&mut self, bytes: &[u8]) -> &mut Params {
+    if self.len + bytes.len() > self.buf.len() {
+      self.overflow = true;
+    } else {
+      self.buf[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+      self.len += bytes.len();
+    }
+    self
+  }
+
+  /// Returns the assembled bytes.
+  pub fn as_slice(&self) -> &[u8] {
+    &self.buf[..self.len]
+  }
+}
+
+/// GAP role of the device.
+#[derive(Clone, Copy)]
+pub enum Role {
+  /// Connectable peripheral.
+  Peripheral = 0x01,
+  /// Non-connectable broadcaster.
+  Broadcaster = 0x02,
+  /// Central.
+  Central = 0x04,
+  /// Observer.
+  Observer = 0x08,
+}
+
+/// Handles of the GAP service created by `Hci::gap_init`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct GapHandles {
+  /// GAP service handle.
+  pub service: u16,
+  /// Device name characteristic handle.
+  pub device_name: u16,
+  /// Appearance characteristic handle.
+  pub appearance: u16,
+}
+
+/// Advertising parameters for `Hci::set_discoverable`.
+#[derive(Clone, Copy)]
+pub struct Advertising {
+  /// Minimum advertising interval, in 0.625ms units.
+  pub interval_min: u16,
+  /// Maximum advertising interval, in 0.625ms units.
+  pub interval_max: u16,
+  /// Minimum connection interval, in 1.25ms units, 0 for no preference.
+  pub connection_interval_min: u16,
+  /// Maximum connection interval, in 1.25ms units, 0 for no preference.
+  pub connection_interval_max: u16,
+}
+
+impl Default for Advertising {
+  fn default() -> Advertising {
+    Advertising {
+      interval_min: 0x800,
+      interval_max: 0x900,
+      connection_interval_min: 0,
+      connection_interval_max: 0,
+    }
+  }
+}
+
+/// Event packets kept for `Hci::next_event`, oldest first. Each is stored as
+/// a 16-bit length, least significant byte first, followed by the packet.
+struct EventQueue {
+  buf: [u8; EVENT_QUEUE_SIZE],
+  head: usize,
+  len: usize,
+  dropped: u32,
+}
+
+impl EventQueue {
+  fn new() -> EventQueue {
+    EventQueue { buf: [0; EVENT_QUEUE_SIZE], head: 0, len: 0, dropped: 0 }
+  }
+
+  /// Appends a packet, or drops it if it doesn't fit.
+  fn push(&mut self, packet: &[u8]) {
+    if 2 + packet.len() > EVENT_QUEUE_SIZE - self.len {
+      self.dropped = self.dropped.wrapping_add(1);
+      return;
+    }
+    self.push_byte(packet.len() as u8);
+    self.push_byte((packet.len() >> 8) as u8);
+    for &b in packet.iter() {
+      self.push_byte(b);
+    }
+  }
+
+  /// Moves the oldest packet into `out` and returns its length, or 0 if the
+  /// queue is empty. Bytes that don't fit in `out` are dropped.
+  fn pop(&mut self, out: &mut [u8]) -> usize {
+    if self.len == 0 {
+      return 0;
+    }
+    let lo = self.pop_byte() as usize;
+    let hi = self.pop_byte() as usize;
+    let len = lo | (hi << 8);
+    for i in 0..len {
+      let b = self.pop_byte();
+      if i < out.len() {
+        out[i] = b;
+      }
+    }
+    cmp::min(len, out.len())
+  }
+
+  fn push_byte(&mut self, b: u8) {
+    let tail = (self.head + self.len) % EVENT_QUEUE_SIZE;
+    self.buf[tail] = b;
+    self.len += 1;
+  }
+
+  fn pop_byte(&mut self) -> u8 {
+    let b = self.buf[self.head];
+    self.head = (self.head + 1) % EVENT_QUEUE_SIZE;
+    self.len -= 1;
+    b
+  }
+}
+
+/// HCI layer over a `BlueNrg`.
+pub struct Hci<'a, G: 'a, S: 'a> {
+  nrg: &'a BlueNrg<G, S>,
+  max_polls: u32,
+  queue: RefCell<EventQueue>,
+}
+
+impl<'a, G: Gpio, S: Spi> Hci<'a, G, S> {
+  /// Creates an HCI layer. The device must be awake, see `BlueNrg::wakeup`.
+  pub fn new(nrg: &'a BlueNrg<G, S>) -> Hci<'a, G, S> {
+    Hci { nrg: nrg, max_polls: 10000, queue: RefCell::new(EventQueue::new()) }
+  }
+
+  /// Sets how many times to poll for a command to complete before giving up.
+  pub fn set_max_polls(&mut self, max_polls: u32) {
+    self.max_polls = max_polls;
+  }
+
+  /// Sends a command and waits for it to complete. Copies the return
+  /// parameters after the status into `ret` and returns their length.
+  ///
+  /// Other events received in the meantime are queued for `next_event`,
+  /// malformed or oversized ones are skipped.
+  pub fn command(&self, opcode: u16, params: &Params, ret: &mut [u8]) -> Result<usize, Error> {
+    if params.overflow {
+      return Err(Error::TooLong);
+    }
+    let params = params.as_slice();
+    let mut packet = [0u8; 4 + 255];
+    packet[0] = COMMAND_PACKET;
+    packet[1] = opcode as u8;
+    packet[2] = (opcode >> 8) as u8;
+    packet[3] = params.len() as u8;
+    packet[4..4 + params.len()].copy_from_slice(params);
+    try!(self.nrg.send(&packet[..4 + params.len()]).map_err(Error::Spi));
+
+    let mut buf = [0u8; MAX_EVENT_LEN];
+    for _ in 0..self.max_polls {
+      let len = match self.receive(&mut buf) {
+        Ok(len) => len,
+        Err(Error::Spi(super::Error::BufferSize(_))) => continue,
+        Err(e) => return Err(e),
+      };
+      if len == 0 {
+        continue;
+      }
+      match Event::parse(&buf[..len]) {
+        Ok(Event::CommandComplete { opcode: op, status, params }) if op == opcode => {
+          if status != 0 {
+            return Err(Error::Status(status));
+          }
+          let len = cmp::min(params.len(), ret.len());
+          ret[..len].copy_from_slice(&params[..len]);
+          return Ok(len);
+        },
+        Ok(Event::CommandStatus { opcode: op, status }) if op == opcode => {
+          if status != 0 {
+            return Err(Error::Status(status));
+          }
+          return Ok(0);
+        },
+        Ok(_) => self.queue.borrow_mut().push(&buf[..len]),
+        Err(_) => {},
+      }
+    }
+    Err(Error::Timeout)
+  }
+
+  /// Returns the oldest event queued by `command`, or reads a new one if
+  /// there's one. `buf` should hold `MAX_EVENT_LEN` bytes, longer events are
+  /// dropped with `Error::Spi(BufferSize)`.
+  pub fn next_event<'b>(&self, buf: &'b mut [u8]) -> Result<Option<Event<'b>>, Error> {
+    let len = self.queue.borrow_mut().pop(buf);
+    if len == 0 {
+      self.read_event(buf)
+    } else {
+      Event::parse(&buf[..len]).map(Some)
+    }
+  }
+
+  /// Reads an event from the controller if there's one, bypassing the queue.
+  /// `buf` should hold `MAX_EVENT_LEN` bytes, longer events are dropped with
+  /// `Error::Spi(BufferSize)`.
+  pub fn read_event<'b>(&self, buf: &'b mut [u8]) -> Result<Option<Event<'b>>, Error> {
+    let len = try!(self.receive(buf));
+    if len == 0 {
+      Ok(None)
+    } else {
+      Event::parse(&buf[..len]).map(Some)
+    }
+  }
+
+  /// Returns the number of events dropped because the queue was full.
+  pub fn dropped_events(&self) -> u32 {
+    self.queue.borrow().dropped
+  }
+
+  /// Reads a packet into `buf` and returns its length, 0 if there's none.
+  fn receive(&self, buf: &mut [u8]) -> Result<usize, Error> {
+    match self.nrg.receive_packet(buf) {
+      Ok(len) => Ok(len),
+      Err(super::Error::Sleeping) | Err(super::Error::Allocating) => Ok(0),
+      Err(e) => Err(Error::Spi(e)),
+    }
+  }
+
+  /// Resets the controller.
+  pub fn reset(&self) -> Result<(), Error> {
+    self.command(opcode::RESET, &Params::new(), &mut []).map(|_| ())
+  }
+
+  /// Initialises the GATT server, must be called before `gap_init`.
+  pub fn gatt_init(&self) -> Result<(), Error> {
+    self.command(opcode::GATT_INIT, &Params::new(), &mut []).map(|_| ())
+  }
+
+  /// Initialises GAP and creates the GAP service.
+  pub fn gap_init(&self, role: Role) -> Result<GapHandles, Error> {
+    let mut ret = [0u8; 6];
+    let len = try!(self.command(opcode::GAP_INIT, Params::new().u8(role as u8), &mut ret));
+    if len < 6 {
+      return Err(Error::BadPacket);
+    }
+    Ok(GapHandles {
+      service: read_u16(&ret[0..]),
+      device_name: read_u16(&ret[2..]),
+      appearance: read_u16(&ret[4..]),
+    })
+  }
+
+  /// Sets the raw advertising data, up to 31 bytes of AD structures.
+  pub fn set_advertising_data(&self, data: &[u8]) -> Result<(), Error> {
+    if data.len() > 31 {
+      return Err(Error::TooLong);
+    }
+    let mut padded = [0u8; 31];
+    padded[..data.len()].copy_from_slice(data);
+    let mut params = Params::new();
+    params.u8(data.len() as u8).bytes(&padded);
+    self.command(opcode::LE_SET_ADVERTISING_DATA, &params, &mut []).map(|_| ())
+  }
+
+  /// Starts connectable advertising with the complete local `name`.
+  pub fn set_discoverable(&self, adv: &Advertising, name: &[u8]) -> Result<(), Error> {
+    let mut params = Params::new();
+    params
+      .u8(0x00)                          // connectable undirected advertising
+      .u16(adv.interval_min)
+      .u16(adv.interval_max)
+      .u8(0x00)                          // public address
+      .u8(0x00)                          // no white list
+      .u8(name.len() as u8 + 1)
+      .u8(0x09)                          // complete local name AD type
+      .bytes(name)
+      .u8(0)                             // no service UUIDs
+      .u16(adv.connection_interval_min)
+      .u16(adv.connection_interval_max);
+    self.command(opcode::GAP_SET_DISCOVERABLE, &params, &mut []).map(|_| ())
+  }
+
+  /// Stops advertising.
+  pub fn set_non_discoverable(&self) -> Result<(), Error> {
+    self.command(opcode::GAP_SET_NON_DISCOVERABLE, &Params::new(), &mut []).map(|_| ())
+  }
+}
+
+#[cfg(test)]
+pub mod test {
+  use std::cell::RefCell;
+  use std::vec::Vec;
+
+  use hal::pin::{Gpio, GpioLevel, GpioDirection};
+  use hal::spi::Spi;
+  use drivers::bluenrg::{self, BlueNrg};
+  use super::{Hci, Event, Error, Role, GapHandles, Advertising};
+
+  pub struct Pin;
+
+  impl Gpio for Pin {
+    fn set_high(&self) {}
+    fn set_low(&self) {}
+    fn level(&self) -> GpioLevel { GpioLevel::High }
+    fn set_direction(&self, _: GpioDirection) {}
+  }
+
+  /// Replays a recorded SPI transcript: MISO bytes are played back, MOSI
+  /// bytes recorded.
+  pub struct Replay {
+    miso: RefCell<Vec<u8>>,
+    pub mosi: RefCell<Vec<u8>>,
+  }
+
+  impl Replay {
+    pub fn new() -> Replay {
+      Replay { miso: RefCell::new(Vec::new()), mosi: RefCell::new(Vec::new()) }
+    }
+
+    /// Expects a packet write.
+    pub fn accept_write(&self, len: usize) {
+      let mut miso = self.miso.borrow_mut();
+      miso.extend_from_slice(&[0x02, 0xff, 0x00, 0x00, 0x00]);
+      for _ in 0..len {
+        miso.push(0);
+      }
+    }
+
+    /// Returns a packet on the next read.
+    pub fn packet(&self, packet: &[u8]) {
+      let mut miso = self.miso.borrow_mut();
+      miso.extend_from_slice(&[0x02, 0x00, 0x00, packet.len() as u8, 0x00]);
+      miso.extend_from_slice(packet);
+    }
+
+    /// Returns everything written and clears it.
+    pub fn take_written(&self) -> Vec<u8> {
+      let written = self.mosi.borrow().clone();
+      self.mosi.borrow_mut().clear();
+      written
+    }
+  }
+
+  impl<'a> Spi for &'a Replay {
+    fn write(&self, value: u8) {
+      self.mosi.borrow_mut().push(value);
+    }
+
+    fn read(&self) -> u8 {
+      let mut miso = self.miso.borrow_mut();
+      if miso.is_empty() { 0 } else { miso.remove(0) }
+    }
+  }
+
+  /// Returns the MOSI bytes of a command packet write.
+  pub fn command_frame(packet: &[u8]) -> Vec<u8> {
+    let mut frame = vec_of(&[0x0a, 0, 0, 0, 0]);
+    frame.extend_from_slice(packet);
+    frame
+  }
+
+  /// Returns the MOSI bytes of a packet read.
+  pub fn read_frame(len: usize) -> Vec<u8> {
+    let mut frame = vec_of(&[0x0b, 0, 0, 0, 0]);
+    for _ in 0..len {
+      frame.push(0);
+    }
+    frame
+  }
+
+  fn vec_of(bytes: &[u8]) -> Vec<u8> {
+    let mut v = Vec::new();
+    v.extend_from_slice(bytes);
+    v
+  }
+
+  #[test]
+  fn should_parse_events() {
+    let complete = [0x04, 0x0e, 0x06, 0x01, 0x02, 0xfd, 0x00, 0x0c, 0x00];
+    assert!(Event::parse(&complete).ok() == Some(Event::CommandComplete {
+      opcode: 0xfd02, status: 0, params: &[0x0c, 0x00],
+    }));
+
+    let connected = [0x04, 0x3e, 0x13, 0x01, 0x00, 0x01, 0x08, 0x01, 0x00,
+                     1, 2, 3, 4, 5, 6, 0x28, 0x00, 0x00, 0x00, 0xd0, 0x07, 0x05];
+    assert!(Event::parse(&connected).ok() == Some(Event::ConnectionComplete {
+      status: 0, handle: 0x0801, peer: [1, 2, 3, 4, 5, 6],
+    }));
+
+    let written = [0x04, 0xff, 0x09, 0x01, 0x0c, 0x01, 0x08, 0x0e, 0x00, 0x02, 0xaa, 0xbb];
+    assert!(Event::parse(&written).ok() == Some(Event::AttributeModified {
+      connection: 0x0801, attribute: 0x000e, data: &[0xaa, 0xbb],
+    }));
+
+    assert!(Event::parse(&[0x04, 0x05, 0x04, 0x00, 0x01, 0x08, 0x13]).ok() ==
+            Some(Event::DisconnectionComplete { handle: 0x0801, reason: 0x13 }));
+    assert!(Event::parse(&[0x04, 0x0e, 0x05, 0x01]).is_err());
+  }
+
+  #[test]
+  fn gap_init_should_follow_transcript() {
+    let spi = Replay::new();
+    let nrg = BlueNrg::new(Pin, &spi);
+    let hci = Hci::new(&nrg);
+
+    let command = [0x01, 0x8a, 0xfc, 0x01, 0x01];
+    let response = [0x04, 0x0e, 0x0a, 0x01, 0x8a, 0xfc, 0x00,
+                    0x0c, 0x00, 0x0d, 0x00, 0x0f, 0x00];
+    spi.accept_write(command.len());
+    // An unrelated event before the response is queued for next_event.
+    spi.packet(&[0x04, 0x10, 0x01, 0x00]);
+    spi.packet(&response);
+
+    let handles = hci.gap_init(Role::Peripheral).ok();
+    assert!(handles == Some(GapHandles { service: 0x0c, device_name: 0x0d, appearance: 0x0f }));
+
+    let mut expected = command_frame(&command);
+    expected.extend(read_frame(4));
+    expected.extend(read_frame(response.len()));
+    assert!(spi.take_written() == expected);
+  }
+
+  #[test]
+  fn set_discoverable_should_encode_name() {
+    let spi = Replay::new();
+    let nrg = BlueNrg::new(Pin, &spi);
+    let hci = Hci::new(&nrg);
+
+    let command = [0x01, 0x83, 0xfc, 0x11,
+                   0x00, 0x00, 0x08, 0x00, 0x09, 0x00, 0x00,
+                   0x04, 0x09, b'Z', b'n', b'c',
+                   0x00, 0x00, 0x00, 0x00, 0x00];
+    spi.accept_write(command.len());
+    spi.packet(&[0x04, 0x0e, 0x04, 0x01, 0x83, 0xfc, 0x00]);
+    assert!(hci.set_discoverable(&Advertising::default(), b"Znc").is_ok());
+    assert!(spi.take_written()[..5 + command.len()] == command_frame(&command)[..]);
+  }
+
+  #[test]
+  fn failed_commands_should_return_status() {
+    let spi = Replay::new();
+    let nrg = BlueNrg::new(Pin, &spi);
+    let mut hci = Hci::new(&nrg);
+
+    spi.accept_write(4);
+    spi.packet(&[0x04, 0x0e, 0x04, 0x01, 0x03, 0x0c, 0x12]);
+    match hci.reset() {
+      Err(Error::Status(0x12)) => {},
+      _ => panic!(),
+    }
+
+    hci.set_max_polls(3);
+    spi.accept_write(4);
+    match hci.reset() {
+      Err(Error::Timeout) => {},
+      _ => panic!(),
+    }
+  }
+
+  #[test]
+  fn events_received_while_waiting_should_be_queued() {
+    let spi = Replay::new();
+    let nrg = BlueNrg::new(Pin, &spi);
+    let hci = Hci::new(&nrg);
+
+    let mut long = vec_of(&[0x04, 0x10, 100]);
+    for i in 0..100 {
+      long.push(i);
+    }
+    spi.accept_write(4);
+    spi.packet(&long);
+    // An attribute write whose data runs past the packet is skipped.
+    spi.packet(&[0x04, 0xff, 0x08, 0x01, 0x0c, 0x01, 0x08, 0x0e, 0x00, 0x05, 0x2a]);
+    spi.packet(&[0x04, 0x0e, 0x04, 0x01, 0x03, 0x0c, 0x00]);
+    assert!(hci.reset().is_ok());
+
+    let mut buf = [0u8; super::MAX_EVENT_LEN];
+    assert!(hci.next_event(&mut buf).ok() == Some(Some(Event::Other {
+      code: 0x10, params: &long[3..],
+    })));
+    let mut buf = [0u8; super::MAX_EVENT_LEN];
+    assert!(hci.next_event(&mut buf).ok() == Some(None));
+    assert!(hci.dropped_events() == 0);
+  }
+
+  #[test]
+  fn oversized_events_should_be_drained_and_reported() {
+    let spi = Replay::new();
+    let nrg = BlueNrg::new(Pin, &spi);
+    let hci = Hci::new(&nrg);
+
+    spi.packet(&[0x04, 0x10, 0x01, 0x00]);
+    spi.packet(&[0x04, 0x10, 0x00]);
+    let mut buf = [0u8; 3];
+    match hci.read_event(&mut buf) {
+      Err(Error::Spi(bluenrg::Error::BufferSize(4))) => {},
+      _ => panic!(),
+    }
+    assert!(hci.read_event(&mut buf).ok() ==
+            Some(Some(Event::Other { code: 0x10, params: &[] })));
+  }
+}
@@ -14,6 +14,9 @@
 // limitations under the License.
 
 //! BlueNRG low-level SPI communication.
+//!
+//! `hci` builds typed commands and events on top of it, and `gatt` a GATT
+//! server.
 // http://www.st.com/st-web-ui/static/active/en/resource/technical/document/user_manual/DM00114498.pdf
 
 use core::result::Result::{Ok, Err};
@@ -23,6 +26,9 @@ use core::slice::SliceExt;
 use hal::pin::Gpio;
 use hal::spi::Spi;
 
+pub mod gatt;
+pub mod hci;
+
 #[repr(u8)]
 enum Control {
   Write = 0x0A,
@@ -147,4 +153,36 @@ impl<G: Gpio, S: Spi> BlueNrg<G, S> {
       Ok(())
     }
   }
+
+  /// Receive a whole packet into the given buffer and return its size, or 0
+  /// if there's nothing to read. A packet that doesn't fit in `buf` is read
+  /// out and dropped, and `BufferSize` is returned with its size.
+  pub fn receive_packet(&self, buf: &mut [u8]) -> Result<usize, Error> {
+    self.active.set_low();
+    let status = self.spi.transfer(Control::Read as u8);
+    self.spi.transfer(0);
+    self.spi.transfer(0);
+    let r0 = self.spi.transfer(0);
+    let r1 = self.spi.transfer(0);
+    let size = (((r1 as u16) << 8) | (r0 as u16)) as usize;
+    let result = match status {
+      0x02 => {
+        for i in 0..size {
+          let b = self.spi.transfer(0);
+          if i < buf.len() {
+            buf[i] = b;
+          }
+        }
+        if size > buf.len() {
+          Err(Error::BufferSize(size as u16))
+        } else {
+          Ok(size)
+        }
+      },
+      0x00 | 0xFF => Err(Error::Sleeping),
+      other => Err(Error::Unknown(other)),
+    };
+    self.active.set_high();
+    result
+  }
 }
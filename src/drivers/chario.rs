@@ -14,13 +14,20 @@
 // limitations under the License.
 
 //! Generic char output trait.
+//!
+//! Any `CharIO` can be used with `core::fmt` through a `Writer`, and one of
+//! them can be selected as the console for the `print!` and `println!` macros:
+//!
+//! ```ignore
+//! chario::set_console(&UART);
+//! println!("adc: {}", sample);
+//! ```
 
 use core::slice::SliceExt;
 use core::convert::AsRef;
+use core::fmt;
 
-use core::mem::zeroed;
-
-use util::strconv;
+use util::strconv::{self, Format};
 
 /// CharIO provides interface for outputting characters.
 ///
@@ -41,15 +48,29 @@ pub trait CharIO {
 
   /// Outputs an integer with given base.
   fn putint(&self, i: u32, base: u32) {
-    let mut buf : [u8; 32] = unsafe { zeroed() };
-    let bsl : &mut [u8] = &mut buf;
-    strconv::itoa(i, bsl, base);
+    let mut buf = [0u8; 32];
+    let len = strconv::itoa(i, &mut buf, base);
+    self.putbytes(&buf[..len]);
+  }
 
-    for &i in bsl.iter() {
-      if i == 0 {
-        break;
-      }
-      self.putc(i as char);
+  /// Outputs a signed integer with given format.
+  fn putnum(&self, i: i64, format: &Format) {
+    let mut buf = [0u8; 80];
+    let len = strconv::format_i64(i, &mut buf, format).unwrap_or(0);
+    self.putbytes(&buf[..len]);
+  }
+
+  /// Outputs a float with given format.
+  fn putfloat(&self, f: f32, format: &Format) {
+    let mut buf = [0u8; 80];
+    let len = strconv::format_f32(f, &mut buf, format).unwrap_or(0);
+    self.putbytes(&buf[..len]);
+  }
+
+  /// Outputs raw bytes as characters.
+  fn putbytes(&self, bytes: &[u8]) {
+    for &b in bytes.iter() {
+      self.putc(b as char);
     }
   }
 
@@ -64,11 +85,75 @@ pub trait CharIO {
   }
 }
 
+/// Adapter implementing `core::fmt::Write` for any `CharIO`.
+pub struct Writer<'a, C: CharIO + ?Sized + 'a> {
+  io: &'a C,
+}
+
+impl<'a, C: CharIO + ?Sized> Writer<'a, C> {
+  /// Creates a writer outputting to `io`.
+  pub fn new(io: &'a C) -> Writer<'a, C> {
+    Writer { io: io }
+  }
+}
+
+impl<'a, C: CharIO + ?Sized> fmt::Write for Writer<'a, C> {
+  fn write_str(&mut self, s: &str) -> fmt::Result {
+    self.io.puts(s);
+    Ok(())
+  }
+}
+
+/// Output of `print!` and `println!`.
+static mut Console: Option<&'static CharIO> = None;
+
+/// Selects the console for `print!` and `println!`. Output is discarded until
+/// it's set.
+///
+/// The console shouldn't be changed while an interrupt handler may be printing.
+pub fn set_console(io: &'static CharIO) {
+  unsafe { Console = Some(io) };
+}
+
+/// Deselects the console, discarding `print!` and `println!` output again.
+pub fn clear_console() {
+  unsafe { Console = None };
+}
+
+/// Returns the current console.
+pub fn console() -> Option<&'static CharIO> {
+  unsafe { Console }
+}
+
+/// Formats to the console, used by `print!` and `println!`.
+pub fn print(args: fmt::Arguments) {
+  if let Some(io) = console() {
+    let _ = fmt::write(&mut Writer::new(io), args);
+  }
+}
+
+/// Prints to the console.
+#[macro_export]
+macro_rules! print {
+  ($($arg:tt)*) => ($crate::drivers::chario::print(format_args!($($arg)*)))
+}
+
+/// Prints to the console, with a newline.
+#[macro_export]
+macro_rules! println {
+  () => (print!("\n"));
+  ($fmt:expr) => (print!(concat!($fmt, "\n")));
+  ($fmt:expr, $($arg:tt)*) => (print!(concat!($fmt, "\n"), $($arg)*));
+}
+
 #[cfg(test)]
 pub mod test {
   use core::cell::RefCell;
+  use core::fmt::Write;
+  use std::boxed::Box;
 
-  use drivers::chario::CharIO;
+  use drivers::chario::{self, CharIO, Writer};
+  use util::strconv::Format;
 
   #[derive(Clone, Copy)]
   pub struct TestCharIOData {
@@ -179,4 +264,44 @@ pub mod test {
     assert!(io.get_last_char() == '\t');
     assert!(io.get_and_reset_putc_calls() == 2);
   }
+
+  #[test]
+  fn putnum_should_output_formatted_number() {
+    let io = TestCharIO::new();
+    io.putnum(-7, &Format::decimal().width(4).zero_pad());
+    assert!(io.get_last_char() == '7');
+    assert!(io.get_and_reset_putc_calls() == 4);
+  }
+
+  #[test]
+  fn writer_should_format_to_chario() {
+    let io = TestCharIO::new();
+    let _ = write!(Writer::new(&io), "{}-{:x}", 42, 255);
+    assert!(io.get_last_char() == 'f');
+    assert!(io.get_and_reset_putc_calls() == 5);
+  }
+
+  struct ConsoleSink;
+
+  static mut ConsoleOutput: [u8; 4] = [0; 4];
+  static mut ConsoleOutputLen: usize = 0;
+  static CONSOLE_SINK: ConsoleSink = ConsoleSink;
+
+  impl CharIO for ConsoleSink {
+    fn putc(&self, value: char) {
+      unsafe {
+        ConsoleOutput[ConsoleOutputLen] = value as u8;
+        ConsoleOutputLen += 1;
+      }
+    }
+  }
+
+  #[test]
+  fn println_should_print_to_console() {
+    chario::set_console(&CONSOLE_SINK);
+    println!("{}", 7);
+    chario::clear_console();
+    println!("{}", 8);
+    assert!(unsafe { &ConsoleOutput[..ConsoleOutputLen] } == b"7\n");
+  }
 }
@@ -13,9 +13,16 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-//! Driver for DHT22.
+//! Driver for DHT11 and DHT22.
+//!
+//! `read` times the bits by polling the data line, so an interrupt firing
+//! mid-read can corrupt it. `read_captured` takes the timings from an
+//! `EdgeCapture` instead, backed by timer input capture or GPIO edge
+//! interrupts, such as `hal::lpc17xx::edge_capture::GpioEdgeCapture`.
 
+use core::cell::Cell;
 use core::option::Option::{self, Some, None};
+use core::result::Result::{self, Ok, Err};
 
 use hal::pin::Gpio;
 use hal::pin::GpioLevel::Low;
@@ -25,10 +32,55 @@ use hal::pin::GpioDirection::Out;
 use hal::pin::GpioLevel;
 use hal::timer::Timer;
 
-/// Basic DHT22 driver ported over from Arduino example.
+/// Sensor model.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Model {
+  /// DHT11, 1°C/1% resolution, at most one read per second.
+  Dht11,
+  /// DHT22 (AM2302), 0.1°C/0.1% resolution, at most one read every 2 seconds.
+  Dht22,
+}
+
+impl Model {
+  fn min_interval_us(self) -> u32 {
+    match self {
+      Model::Dht11 => 1_000_000,
+      Model::Dht22 => 2_000_000,
+    }
+  }
+}
+
+/// Read errors.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Error {
+  /// The sensor didn't answer the start signal, it may be missing or
+  /// unpowered.
+  NoResponse,
+  /// The sensor stopped sending after the given number of bits.
+  Timeout(u8),
+  /// A captured bit had an impossible length, an edge was likely missed.
+  BadTiming,
+  /// The data doesn't match its checksum.
+  Checksum,
+}
+
+/// Records the times of falling edges on the data line.
+pub trait EdgeCapture {
+  /// Starts recording, dropping previously recorded edges.
+  fn start(&self);
+
+  /// Stops recording, copies the edge times in microseconds to `edges` and
+  /// returns how many were copied. The times must use the same clock as the
+  /// driver's timer, and include the edge made by the start signal.
+  fn stop(&self, edges: &mut [u32]) -> usize;
+}
+
+/// Basic DHT22 driver ported over from Arduino example, also handling DHT11.
 pub struct DHT22<'a, T:'a, P:'a> {
   gpio: &'a P,
   timer: &'a T,
+  model: Model,
+  last_read: Cell<Option<u32>>,
 }
 
 /// Measurement data from the DHT22.
@@ -42,71 +94,115 @@ pub struct Measurements {
 impl<'a, T: Timer, P: Gpio> DHT22<'a, T, P> {
   /// Creates a new DHT22 driver based on I/O GPIO and a timer with 10us resolution.
   pub fn new(timer: &'a T, gpio: &'a P) -> DHT22<'a, T, P> {
+    DHT22::with_model(timer, gpio, Model::Dht22)
+  }
+
+  /// Creates a new driver for the given sensor model.
+  pub fn with_model(timer: &'a T, gpio: &'a P, model: Model) -> DHT22<'a, T, P> {
     DHT22 {
       gpio: gpio,
       timer: timer,
+      model: model,
+      last_read: Cell::new(None),
     }
   }
 
-  /// Returns previous sensor measurements or None if synchronization failed.
-  pub fn read(&self) -> Option<Measurements> {
-    let buffer: &mut [u8; 5] = &mut [0; 5];
-    let mut idx: usize = 0;
-    let mut mask: u8 = 128;
+  /// Returns the sensor model.
+  pub fn model(&self) -> Model {
+    self.model
+  }
 
-    self.gpio.set_direction(Out);
-    self.gpio.set_low();
-    self.timer.wait_ms(20);
-    self.gpio.set_high();
-    self.timer.wait_us(40);
-    self.gpio.set_direction(In);
+  /// Returns previous sensor measurements. Waits first if the sensor was read
+  /// too recently.
+  pub fn read(&self) -> Result<Measurements, Error> {
+    let mut data = [0u8; 5];
+
+    self.start();
 
     if !self.wait_sync() {
-      return None
+      return Err(Error::NoResponse)
     }
 
-    for _ in 0..40 {
+    for bit in 0..40u8 {
       if !self.wait_while(Low, 80) {
-        return None
+        return Err(Error::Timeout(bit))
       }
 
       let t = self.timer.get_counter();
 
       if !self.wait_while(High, 80) {
-        return None
+        return Err(Error::Timeout(bit))
       }
 
       if self.timer.get_counter() - t > 40 {
-        buffer[idx] |= mask;
+        data[bit as usize / 8] |= 0x80 >> (bit % 8);
       }
+    }
 
-      mask >>= 1;
-      if mask == 0 {
-        mask = 128;
-        idx += 1;
+    self.decode(&data)
+  }
+
+  /// Like `read`, but with bit timings recorded by `capture`.
+  pub fn read_captured<C: EdgeCapture>(&self, capture: &C) -> Result<Measurements, Error> {
+    let mut edges = [0u32; 48];
+
+    capture.start();
+    self.start();
+    // The response and 40 bits take at most 5.5ms.
+    self.timer.wait_us(6000);
+    let count = capture.stop(&mut edges);
+
+    let data = try!(decode_edges(&edges[..count]));
+    self.decode(&data)
+  }
+
+  fn start(&self) {
+    if let Some(last) = self.last_read.get() {
+      let elapsed = self.timer.get_counter().wrapping_sub(last);
+      let interval = self.model.min_interval_us();
+      if elapsed < interval {
+        self.timer.wait_us(interval - elapsed);
       }
     }
+    self.last_read.set(Some(self.timer.get_counter()));
 
-    let humidity: f32 = (((buffer[0] as u16) << 8) | buffer[1] as u16) as f32 * 0.1;
-    let temperature: f32 = if buffer[2] & 0x80 != 0 {
-      -0.1 * (((buffer[2] as u16 & 0x7F) << 8) | buffer[3] as u16) as f32
-    } else {
-      0.1 * (((buffer[2] as u16) << 8) | buffer[3] as u16) as f32
-    };
-    let checksum: u8 = buffer[0] + buffer[1] + buffer[2] + buffer[3];
+    self.gpio.set_direction(Out);
+    self.gpio.set_low();
+    self.timer.wait_ms(20);
+    self.gpio.set_high();
+    self.timer.wait_us(40);
+    self.gpio.set_direction(In);
+  }
 
-    if checksum != buffer[4] {
-      None
-    } else {
-      Some(Measurements {
-        humidity: humidity,
-        temperature: temperature,
-      })
+  fn decode(&self, data: &[u8; 5]) -> Result<Measurements, Error> {
+    let checksum = data[0].wrapping_add(data[1]).wrapping_add(data[2]).wrapping_add(data[3]);
+    if checksum != data[4] {
+      return Err(Error::Checksum)
     }
+
+    let (humidity, temperature) = match self.model {
+      Model::Dht11 => {
+        let temperature = data[2] as f32 + (data[3] & 0x7F) as f32 * 0.1;
+        (data[0] as f32 + data[1] as f32 * 0.1,
+         if data[3] & 0x80 != 0 { -temperature } else { temperature })
+      },
+      Model::Dht22 => {
+        let temperature = (((data[2] as u16 & 0x7F) << 8) | data[3] as u16) as f32 * 0.1;
+        ((((data[0] as u16) << 8) | data[1] as u16) as f32 * 0.1,
+         if data[2] & 0x80 != 0 { -temperature } else { temperature })
+      },
+    };
+
+    Ok(Measurements {
+      humidity: humidity,
+      temperature: temperature,
+    })
   }
 
   fn wait_sync(&self) -> bool {
-    if !self.wait_while(Low, 80) {
+    if !self.wait_while(High, 60) {
+      false
+    } else if !self.wait_while(Low, 100) {
       false
     } else if !self.wait_while(High, 100) {
       false
@@ -126,3 +222,176 @@ impl<'a, T: Timer, P: Gpio> DHT22<'a, T, P> {
     false
   }
 }
+
+/// Decodes falling edge times. Each bit starts with a 50us low, followed by a
+/// 26-28us high for 0 or a 70us high for 1, so the edges are either ~77us or
+/// ~120us apart.
+fn decode_edges(edges: &[u32]) -> Result<[u8; 5], Error> {
+  // The host start signal, the response and 41 bit boundaries.
+  if edges.len() < 2 {
+    return Err(Error::NoResponse)
+  } else if edges.len() < 43 {
+    return Err(Error::Timeout(edges.len().saturating_sub(3) as u8))
+  }
+
+  let mut data = [0u8; 5];
+  let bits = &edges[edges.len() - 41..];
+  for bit in 0..40 {
+    match bits[bit + 1].wrapping_sub(bits[bit]) {
+      60...99 => {},
+      100...160 => data[bit / 8] |= 0x80 >> (bit % 8),
+      _ => return Err(Error::BadTiming),
+    }
+  }
+  Ok(data)
+}
+
+#[cfg(test)]
+mod test {
+  use core::cell::{Cell, RefCell};
+  use std::vec::Vec;
+
+  use hal::pin::{Gpio, GpioLevel, GpioDirection};
+  use hal::timer::Timer;
+  use super::{DHT22, Model, Error, EdgeCapture};
+
+  /// Simulates a sensor on the data line. Every timer read takes 1us.
+  struct Sensor {
+    time: Cell<u32>,
+    released: Cell<Option<u32>>,
+    starts: RefCell<Vec<u32>>,
+    data: [u8; 5],
+    present: bool,
+  }
+
+  impl Sensor {
+    fn new(data: [u8; 5]) -> Sensor {
+      Sensor {
+        time: Cell::new(0),
+        released: Cell::new(None),
+        starts: RefCell::new(Vec::new()),
+        data: data,
+        present: true,
+      }
+    }
+
+    /// Returns the levels after the host releases the line, as (length, high).
+    fn waveform(&self) -> Vec<(u32, bool)> {
+      let mut wave = Vec::new();
+      wave.push((30, true));
+      wave.push((80, false));
+      wave.push((80, true));
+      for bit in 0..40 {
+        let one = self.data[bit / 8] & (0x80 >> (bit % 8)) != 0;
+        wave.push((50, false));
+        wave.push((if one { 70 } else { 27 }, true));
+      }
+      wave.push((50, false));
+      wave
+    }
+  }
+
+  impl Timer for Sensor {
+    fn get_counter(&self) -> u32 {
+      self.time.set(self.time.get() + 1);
+      self.time.get()
+    }
+  }
+
+  impl Gpio for Sensor {
+    fn set_high(&self) {}
+
+    fn set_low(&self) {
+      self.starts.borrow_mut().push(self.time.get());
+    }
+
+    fn level(&self) -> GpioLevel {
+      let released = match self.released.get() {
+        Some(t) if self.present => t,
+        _ => return GpioLevel::High,
+      };
+      let mut t = self.time.get() - released;
+      for (len, high) in self.waveform() {
+        if t < len {
+          return if high { GpioLevel::High } else { GpioLevel::Low };
+        }
+        t -= len;
+      }
+      GpioLevel::High
+    }
+
+    fn set_direction(&self, mode: GpioDirection) {
+      match mode {
+        GpioDirection::In => self.released.set(Some(self.time.get())),
+        GpioDirection::Out => self.released.set(None),
+      }
+    }
+  }
+
+  impl EdgeCapture for Sensor {
+    fn start(&self) {}
+
+    fn stop(&self, edges: &mut [u32]) -> usize {
+      let mut falling = Vec::new();
+      falling.push(*self.starts.borrow().last().unwrap());
+      if self.present {
+        let mut t = self.released.get().unwrap();
+        for (len, high) in self.waveform() {
+          if !high {
+            falling.push(t);
+          }
+          t += len;
+        }
+      }
+      for (edge, &t) in edges.iter_mut().zip(falling.iter()) {
+        *edge = t;
+      }
+      falling.len()
+    }
+  }
+
+  #[test]
+  fn should_read_dht22() {
+    // 65.2%, -10.1°C
+    let sensor = Sensor::new([0x02, 0x8c, 0x80, 0x65, 0x73]);
+    let dht = DHT22::new(&sensor, &sensor);
+    let m = dht.read().ok().unwrap();
+    assert!(m.humidity > 65.15 && m.humidity < 65.25);
+    assert!(m.temperature > -10.15 && m.temperature < -10.05);
+  }
+
+  #[test]
+  fn should_read_dht11_captured() {
+    // 40%, -2.5°C
+    let sensor = Sensor::new([40, 0, 2, 0x85, 0xaf]);
+    let dht = DHT22::with_model(&sensor, &sensor, Model::Dht11);
+    let m = dht.read_captured(&sensor).ok().unwrap();
+    assert!(m.humidity > 39.95 && m.humidity < 40.05);
+    assert!(m.temperature > -2.55 && m.temperature < -2.45);
+  }
+
+  #[test]
+  fn should_report_errors() {
+    let mut sensor = Sensor::new([1, 2, 3, 4, 5]);
+    {
+      let dht = DHT22::new(&sensor, &sensor);
+      assert!(dht.read().err() == Some(Error::Checksum));
+      assert!(dht.read_captured(&sensor).err() == Some(Error::Checksum));
+    }
+
+    sensor.present = false;
+    let dht = DHT22::new(&sensor, &sensor);
+    assert!(dht.read().err() == Some(Error::NoResponse));
+    assert!(dht.read_captured(&sensor).err() == Some(Error::NoResponse));
+  }
+
+  #[test]
+  fn should_wait_between_reads() {
+    let sensor = Sensor::new([0, 0, 0, 0, 0]);
+    let dht = DHT22::with_model(&sensor, &sensor, Model::Dht11);
+    assert!(dht.read().is_ok());
+    assert!(dht.read().is_ok());
+    let starts = sensor.starts.borrow();
+    assert!(starts[1] - starts[0] >= 1_000_000);
+  }
+}
@@ -50,6 +50,21 @@ fn build_dht22(builder: &mut Builder, cx: &mut ExtCtxt, node: Rc<node::Node>) {
   let pin_node_name = node.get_ref_attr("pin").unwrap();
   let timer_node_name = node.get_ref_attr("timer").unwrap();
 
+  let model_str = match node.get_string_attr("model") {
+    None => None,
+    Some(model) => match model.as_str() {
+      "dht11" => Some("zinc::drivers::dht22::Model::Dht11"),
+      "dht22" => Some("zinc::drivers::dht22::Model::Dht22"),
+      other => {
+        let attr = node.get_attr("model");
+        cx.parse_sess().span_diagnostic.span_err(attr.value_span,
+            format!("unknown model `{}`, allowed values: `dht11`, `dht22`",
+                other).as_str());
+        return;
+      }
+    },
+  };
+
   let pin = TokenString(pin_node_name);
   let timer = TokenString(timer_node_name);
   let name = TokenString(node.name.clone().unwrap());
@@ -62,9 +77,17 @@ fn build_dht22(builder: &mut Builder, cx: &mut ExtCtxt, node: Rc<node::Node>) {
       "zinc::hal::pin::Gpio".to_string());
   node.set_type_params(ty_params);
 
-  let st = quote_stmt!(&*cx,
-      let $name = zinc::drivers::dht22::DHT22::new(&$timer, &$pin);
-  ).unwrap();
+  let st = match model_str {
+    None => quote_stmt!(&*cx,
+        let $name = zinc::drivers::dht22::DHT22::new(&$timer, &$pin);
+    ),
+    Some(model_str) => {
+      let model = TokenString(model_str.to_string());
+      quote_stmt!(&*cx,
+          let $name = zinc::drivers::dht22::DHT22::with_model(&$timer, &$pin, $model);
+      )
+    },
+  }.unwrap();
   builder.add_main_statement(st);
 }
 
@@ -99,4 +122,25 @@ mod test {
           is(equal_to("out".to_string())));
     });
   }
+
+  #[test]
+  fn builds_dht11_pt() {
+    with_parsed("
+      timer@timer;
+      pin@pin;
+      dht@dht22 {
+        pin = &pin;
+        timer = &timer;
+        model = \"dht11\";
+      }", |cx, failed, pt| {
+      let mut builder = Builder::new(pt.clone(), cx);
+      super::build_dht22(&mut builder, cx, pt.get_by_name("dht").unwrap());
+      assert_that(unsafe{*failed}, is(equal_to(false)));
+      assert_that(builder.main_stmts().len(), is(equal_to(1usize)));
+
+      assert_equal_source(&builder.main_stmts()[0],
+          "let dht = zinc::drivers::dht22::DHT22::with_model(&timer, &pin,
+              zinc::drivers::dht22::Model::Dht11);");
+    });
+  }
 }
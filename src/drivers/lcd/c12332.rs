@@ -16,17 +16,18 @@
 /*!
 Driver for C12332 LCD.
 
-C12332 is black&white LCD, the only supported color value is `1`. The LCD is
-buffered in driver memory.
+C12332 is black&white LCD with `Monochrome` colors. The LCD is buffered in
+driver memory.
 
 The driver uses SPI bus for output only, it never reads back from SPI, which
 might be an issue for any other peripheral sharing the same SPI bus.
 */
 
 use core::cell;
-use core::slice::SliceExt;
 use core::mem::zeroed;
 
+use super::color::Monochrome;
+use super::font::TextCursor;
 use super::font_small_7;
 use super::LCD;
 use drivers::chario::CharIO;
@@ -45,9 +46,7 @@ pub struct C12332<'a, S:'a, T:'a, P:'a> {
 
   videobuf: [cell::Cell<u8>; 512],
 
-  font: &'static [u8],
-  char_x: cell::Cell<u32>,
-  char_y: cell::Cell<u32>,
+  cursor: TextCursor<Monochrome>,
 }
 
 impl<'a, S: Spi, T: Timer, P: Gpio> C12332<'a, S, T, P> {
@@ -63,9 +62,8 @@ impl<'a, S: Spi, T: Timer, P: Gpio> C12332<'a, S, T, P> {
 
       videobuf: unsafe { zeroed() },
 
-      font: font_small_7::FONT,
-      char_x: cell::Cell::new(0),
-      char_y: cell::Cell::new(0),
+      cursor: TextCursor::new(&font_small_7::SMALL_7, Monochrome(true),
+                              Some(Monochrome(false))),
     };
 
     lcd.configure();
@@ -98,7 +96,7 @@ impl<'a, S: Spi, T: Timer, P: Gpio> C12332<'a, S, T, P> {
 
     self.wr_cmd(0xA6);     // display normal
 
-    (self as &LCD).flush();
+    LCD::flush(self);
   }
 
   fn wr_cmd(&self, cmd: u8) {
@@ -116,13 +114,13 @@ impl<'a, S: Spi, T: Timer, P: Gpio> C12332<'a, S, T, P> {
   }
 
   /// Sets an individual pixel.
-  pub fn set_pixel(&self, x: u32, y: u32, color: u16) {
+  pub fn set_pixel(&self, x: u32, y: u32, color: Monochrome) {
     if x > 127 || y > 31 {
       return
     }
 
     let index = (x + (y/8) * 128) as usize;
-    if color == 0 {
+    if !color.0 {
       self.videobuf[index].set(
         self.videobuf[index].get() & !(1u8 << (y%8u32) as usize) as u8);
     } else {
@@ -131,58 +129,15 @@ impl<'a, S: Spi, T: Timer, P: Gpio> C12332<'a, S, T, P> {
     }
   }
 
-  /// Prints a character to the display.
-  pub fn character(&self, x: u32, y: u32, c: u8) {
-    let width: u32 = 128;
-    let height: u32 = 32;
-
-    if (c < 31) || (c > 127) {
-      return;
-    }
-
-    // read font parameter from start of array
-    let offset = self.font[0];                    // bytes / char
-    let hor = self.font[1];                       // get hor size of font
-    let vert = self.font[2];                      // get vert size of font
-    let bpl = self.font[3];                       // bytes per line
-
-    let mut char_x: u32 = self.char_x.get();
-    let mut char_y: u32 = self.char_y.get();
-
-    if char_x + hor as u32 > width {
-      char_x = 0;
-      char_y = char_y + vert as u32;
-      if char_y >= height - self.font[2] as u32 {
-        char_y = 0;
-      }
-    }
-
-    let start: usize = ((c - 32) as usize * offset as usize) + 4;
-    let end: usize = start + offset as usize;
-    let zeichen = &self.font[start..end];
-    // zeichen = &self.font[]; // start of char bitmap
-    let w = zeichen[0];                          // width of actual char
-    // construct the char into the buffer
-    for j in 0..vert {
-      for i in 0..hor {
-        let z: u8 =  zeichen[(bpl * i + ((j & 0xF8) >> 3)+1) as usize];
-        let b: u8 = 1 << ((j & 0x07) as usize);
-        if ( z & b ) == 0x00 {
-          self.set_pixel(x+i as u32, y+j as u32, 0);
-        } else {
-          self.set_pixel(x+i as u32, y+j as u32, 1);
-        }
-      }
-    }
-
-    char_x += w as u32;
-
-    self.char_x.set(char_x);
-    self.char_y.set(char_y);
+  /// Returns the text cursor used for `CharIO` output.
+  pub fn cursor(&self) -> &TextCursor<Monochrome> {
+    &self.cursor
   }
 }
 
 impl<'a, S: Spi, T: Timer, P: Gpio> LCD for C12332<'a, S, T, P> {
+  type Color = Monochrome;
+
   fn flush(&self) {
     let mut i: usize = 0;
 
@@ -233,22 +188,17 @@ impl<'a, S: Spi, T: Timer, P: Gpio> LCD for C12332<'a, S, T, P> {
     }
   }
 
-  fn pixel(&self, x: u32, y: u32, color: u16) {
+  fn pixel(&self, x: u32, y: u32, color: Monochrome) {
     self.set_pixel(x, y, color);
   }
+
+  fn size(&self) -> (u32, u32) {
+    (128, 32)
+  }
 }
 
 impl<'a, S: Spi, T: Timer, P: Gpio> CharIO for C12332<'a, S, T, P> {
   fn putc(&self, value: char) {
-    let height: u32 = 32;
-    if value == '\n' {
-      self.char_x.set(0);
-      self.char_y.set(self.char_y.get() + self.font[2] as u32);
-      if self.char_y.get() >= height - self.font[2] as u32 {
-          self.char_y.set(0);
-      }
-    } else {
-      self.character(self.char_x.get(), self.char_y.get(), value as u8);
-    }
+    self.cursor.putc(self, value);
   }
 }
@@ -0,0 +1,146 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2014 Vladimir "farcaller" Pouzanov <farcaller@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Pixel color formats.
+//!
+//! Every `LCD` has a native `PixelColor` type. Code that should work on any
+//! display picks colors as `Rgb888` and converts them with `from_rgb`:
+//!
+//! ```ignore
+//! fn draw_button<L: LCD>(lcd: &L) {
+//!   let red = L::Color::from_rgb(Rgb888::new(0xff, 0, 0));
+//!   lcd.fill_round_rect(10, 10, 60, 30, 4, red);
+//! }
+//! ```
+
+/// A color in a display's native format.
+pub trait PixelColor: Copy + PartialEq {
+  /// Converts from 24-bit RGB, picking the nearest color.
+  fn from_rgb(color: Rgb888) -> Self;
+
+  /// Converts to 24-bit RGB.
+  fn to_rgb(self) -> Rgb888;
+
+  /// Converts to another color format.
+  fn convert<C: PixelColor>(self) -> C {
+    C::from_rgb(self.to_rgb())
+  }
+}
+
+/// A monochrome pixel, `true` if it's lit.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Monochrome(pub bool);
+
+/// A 4-bit grayscale pixel, from 0 (black) to 15 (white).
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Gray4(pub u8);
+
+/// A 16-bit color with 5 bits of red, 6 bits of green and 5 bits of blue,
+/// red in the most significant bits.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Rgb565(pub u16);
+
+/// A 24-bit color.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Rgb888 {
+  /// Red component.
+  pub r: u8,
+  /// Green component.
+  pub g: u8,
+  /// Blue component.
+  pub b: u8,
+}
+
+impl Rgb888 {
+  /// Creates a color from its components.
+  pub const fn new(r: u8, g: u8, b: u8) -> Rgb888 {
+    Rgb888 { r: r, g: g, b: b }
+  }
+
+  /// Returns the perceived brightness, from 0 to 255.
+  pub fn luma(&self) -> u8 {
+    ((self.r as u32 * 77 + self.g as u32 * 150 + self.b as u32 * 29) >> 8) as u8
+  }
+}
+
+impl Rgb565 {
+  /// Creates a color from 8-bit components, dropping the low bits.
+  pub const fn new(r: u8, g: u8, b: u8) -> Rgb565 {
+    Rgb565((((r as u16) >> 3) << 11) | (((g as u16) >> 2) << 5) | ((b as u16) >> 3))
+  }
+}
+
+impl PixelColor for Rgb888 {
+  fn from_rgb(color: Rgb888) -> Rgb888 { color }
+  fn to_rgb(self) -> Rgb888 { self }
+}
+
+impl PixelColor for Rgb565 {
+  fn from_rgb(color: Rgb888) -> Rgb565 {
+    Rgb565::new(color.r, color.g, color.b)
+  }
+
+  fn to_rgb(self) -> Rgb888 {
+    let r = (self.0 >> 11) as u8;
+    let g = ((self.0 >> 5) & 0x3f) as u8;
+    let b = (self.0 & 0x1f) as u8;
+    // Repeat the high bits, so that full intensity maps to 0xff.
+    Rgb888::new((r << 3) | (r >> 2), (g << 2) | (g >> 4), (b << 3) | (b >> 2))
+  }
+}
+
+impl PixelColor for Gray4 {
+  fn from_rgb(color: Rgb888) -> Gray4 {
+    Gray4(color.luma() >> 4)
+  }
+
+  fn to_rgb(self) -> Rgb888 {
+    let level = (self.0 & 0xf) * 0x11;
+    Rgb888::new(level, level, level)
+  }
+}
+
+impl PixelColor for Monochrome {
+  fn from_rgb(color: Rgb888) -> Monochrome {
+    Monochrome(color.luma() >= 0x80)
+  }
+
+  fn to_rgb(self) -> Rgb888 {
+    if self.0 { Rgb888::new(0xff, 0xff, 0xff) } else { Rgb888::new(0, 0, 0) }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{PixelColor, Monochrome, Gray4, Rgb565, Rgb888};
+
+  #[test]
+  fn rgb565_should_round_trip_extremes() {
+    let white = Rgb888::new(0xff, 0xff, 0xff);
+    assert!(Rgb565::from_rgb(white) == Rgb565(0xffff));
+    assert!(Rgb565(0xffff).to_rgb() == white);
+    assert!(Rgb565::new(0xff, 0, 0) == Rgb565(0xf800));
+    assert!(Rgb565(0x07e0).to_rgb() == Rgb888::new(0, 0xff, 0));
+  }
+
+  #[test]
+  fn should_convert_between_formats() {
+    let gray: Gray4 = Rgb565(0xffff).convert();
+    assert!(gray == Gray4(15));
+    assert!(Gray4(8).convert::<Monochrome>() == Monochrome(true));
+    assert!(Gray4(7).convert::<Monochrome>() == Monochrome(false));
+    assert!(Monochrome(true).convert::<Rgb565>() == Rgb565(0xffff));
+  }
+}
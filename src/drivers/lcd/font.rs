@@ -0,0 +1,175 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2014 Vladimir "farcaller" Pouzanov <farcaller@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Bitmap fonts for `LCD::draw_text`.
+//!
+//! Font data uses the format described in `font_small_7`: a header with the
+//! glyph size, followed by one proportional-width glyph per character, stored
+//! as vertical lines of pixels. The same data can be drawn at integer scales.
+
+use core::cell::Cell;
+use core::slice::SliceExt;
+
+use super::LCD;
+use super::color::PixelColor;
+
+/// A bitmap font at a given scale.
+pub struct Font {
+  /// Font data, see `font_small_7` for the format.
+  pub data: &'static [u8],
+  /// First character defined in `data`.
+  pub first: u8,
+  /// Every font pixel is drawn as a `scale` by `scale` square.
+  pub scale: u32,
+}
+
+/// Metrics and bitmap of a single character.
+pub struct Glyph {
+  lines: &'static [u8],
+  bytes_per_line: u32,
+  /// Width of the character in pixels, including spacing.
+  pub width: u32,
+  /// Height of the character in pixels.
+  pub height: u32,
+  scale: u32,
+}
+
+impl Font {
+  /// Creates a font from data in the `font_small_7` format.
+  pub const fn new(data: &'static [u8], first: u8) -> Font {
+    Font { data: data, first: first, scale: 1 }
+  }
+
+  /// Returns the same font drawn `scale` times larger.
+  pub fn scaled(&self, scale: u32) -> Font {
+    Font { data: self.data, first: self.first, scale: scale }
+  }
+
+  /// Returns the line height in pixels.
+  pub fn height(&self) -> u32 {
+    self.data[2] as u32 * self.scale
+  }
+
+  /// Returns the maximum glyph width in pixels.
+  pub fn max_width(&self) -> u32 {
+    self.data[1] as u32 * self.scale
+  }
+
+  /// Returns the glyph for `c`, if the font defines it.
+  pub fn glyph(&self, c: char) -> Option<Glyph> {
+    let glyph_size = self.data[0] as usize;
+    let code = c as u32;
+    if code < self.first as u32 {
+      return None;
+    }
+    let start = 4 + (code - self.first as u32) as usize * glyph_size;
+    if start + glyph_size > self.data.len() {
+      return None;
+    }
+    let glyph = &self.data[start..start + glyph_size];
+    Some(Glyph {
+      lines: &glyph[1..],
+      bytes_per_line: self.data[3] as u32,
+      width: glyph[0] as u32 * self.scale,
+      height: self.height(),
+      scale: self.scale,
+    })
+  }
+
+  /// Returns the width of `text` in pixels, without wrapping.
+  pub fn text_width(&self, text: &str) -> u32 {
+    text.chars().map(|c| self.glyph(c).map(|g| g.width).unwrap_or(0)).sum()
+  }
+}
+
+impl Glyph {
+  /// Returns true if the pixel at `x`, `y` is set.
+  pub fn pixel(&self, x: u32, y: u32) -> bool {
+    let (x, y) = (x / self.scale, y / self.scale);
+    let index = (x * self.bytes_per_line + y / 8) as usize;
+    index < self.lines.len() && self.lines[index] & (1 << (y % 8)) != 0
+  }
+}
+
+/// Text position, font and colors for `CharIO` output on an LCD.
+///
+/// Drivers keep one and forward `CharIO::putc` to `TextCursor::putc`. Text
+/// wraps at the right edge and restarts from the top at the bottom edge.
+pub struct TextCursor<C: PixelColor> {
+  x: Cell<u32>,
+  y: Cell<u32>,
+  font: Cell<&'static Font>,
+  fg: Cell<C>,
+  bg: Cell<Option<C>>,
+}
+
+impl<C: PixelColor> TextCursor<C> {
+  /// Creates a cursor at the origin.
+  pub const fn new(font: &'static Font, fg: C, bg: Option<C>) -> TextCursor<C> {
+    TextCursor {
+      x: Cell::new(0),
+      y: Cell::new(0),
+      font: Cell::new(font),
+      fg: Cell::new(fg),
+      bg: Cell::new(bg),
+    }
+  }
+
+  /// Moves the cursor.
+  pub fn set_position(&self, x: u32, y: u32) {
+    self.x.set(x);
+    self.y.set(y);
+  }
+
+  /// Returns the cursor position.
+  pub fn position(&self) -> (u32, u32) {
+    (self.x.get(), self.y.get())
+  }
+
+  /// Changes the font.
+  pub fn set_font(&self, font: &'static Font) {
+    self.font.set(font);
+  }
+
+  /// Changes the colors, `None` background leaves it untouched.
+  pub fn set_colors(&self, fg: C, bg: Option<C>) {
+    self.fg.set(fg);
+    self.bg.set(bg);
+  }
+
+  /// Draws a character at the cursor and advances it.
+  pub fn putc<L: LCD<Color=C> + ?Sized>(&self, lcd: &L, c: char) {
+    let font = self.font.get();
+    let (width, height) = lcd.size();
+    let (mut x, mut y) = self.position();
+
+    // Wrap before drawing, so that the glyph isn't drawn below the screen.
+    if let Some(glyph) = font.glyph(c) {
+      if x + glyph.width > width && x > 0 {
+        x = 0;
+        y += font.height();
+      }
+    }
+    if y + font.height() > height {
+      y = 0;
+    }
+
+    let (x, mut y) = lcd.draw_char(0, x, y, c, font, self.fg.get(), self.bg.get());
+    if y + font.height() > height {
+      y = 0;
+    }
+    self.set_position(x, y);
+  }
+}
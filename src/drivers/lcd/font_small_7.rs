@@ -26,6 +26,14 @@
 //!   - Remaining bytes describe vertical lines from left to right with the
 //!     LSB defining the top of the line
 
+use super::font::Font;
+
+/// The font at its native size.
+pub static SMALL_7: Font = Font { data: FONT, first: 32, scale: 1 };
+
+/// The font at double size.
+pub static SMALL_7_X2: Font = Font { data: FONT, first: 32, scale: 2 };
+
 /// Definition of a 9x9 font.
 pub static FONT: &'static [u8] = &[
 19,9,9,2,                                    // Length,horz,vert,byte/vert
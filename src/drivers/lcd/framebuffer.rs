@@ -0,0 +1,400 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2014 Vladimir "farcaller" Pouzanov <farcaller@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/*!
+Off-screen framebuffer.
+
+`FrameBuffer` implements `LCD` in RAM and remembers which rectangles changed
+since the last `flush`. Flushing copies only those to the backing display,
+through its `write_area`:
+
+```ignore
+let fb = FrameBuffer::new(&ili9341, [[Rgb565(0); 240]; 64]);
+fb.fill_circle(120, 32, 20, Rgb565::new(0xff, 0, 0));
+fb.flush();
+```
+
+The buffer size is the size of the `[[Color; W]; H]` storage array. Both
+dimensions can be any of 8, 16, 32, 48, 64, 84, 96, 128, 160, 176, 240 and
+320.
+*/
+
+use core::cell::{Cell, UnsafeCell};
+use core::cmp;
+use core::ptr;
+use core::slice;
+
+use drivers::chario::CharIO;
+use super::LCD;
+use super::color::{PixelColor, Rgb888};
+use super::font::TextCursor;
+use super::font_small_7;
+
+/// A row of pixels in a `FrameBuffer`.
+///
+/// Implemented for `PixelColor` arrays of the supported widths.
+pub unsafe trait Row {
+  /// Pixel color.
+  type Color: PixelColor;
+  /// Number of pixels.
+  fn width() -> u32;
+}
+
+/// Backing storage of a `FrameBuffer`.
+///
+/// Implemented for arrays of `Row`s of the supported heights.
+pub unsafe trait Pixels {
+  /// Pixel color.
+  type Color: PixelColor;
+  /// Width in pixels.
+  fn width() -> u32;
+  /// Height in pixels.
+  fn height() -> u32;
+}
+
+macro_rules! impl_pixels {
+  ($($n:expr)*) => ($(
+    unsafe impl<C: PixelColor> Row for [C; $n] {
+      type Color = C;
+      #[inline(always)]
+      fn width() -> u32 { $n }
+    }
+
+    unsafe impl<R: Row> Pixels for [R; $n] {
+      type Color = R::Color;
+      #[inline(always)]
+      fn width() -> u32 { R::width() }
+      #[inline(always)]
+      fn height() -> u32 { $n }
+    }
+  )*)
+}
+
+impl_pixels!(8 16 32 48 64 84 96 128 160 176 240 320);
+
+/// Number of separately tracked dirty rectangles.
+const DIRTY_SLOTS: usize = 4;
+
+/// Inclusive pixel bounds.
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct Rect {
+  x0: u32,
+  y0: u32,
+  x1: u32,
+  y1: u32,
+}
+
+impl Rect {
+  fn union(&self, other: &Rect) -> Rect {
+    Rect {
+      x0: cmp::min(self.x0, other.x0),
+      y0: cmp::min(self.y0, other.y0),
+      x1: cmp::max(self.x1, other.x1),
+      y1: cmp::max(self.y1, other.y1),
+    }
+  }
+
+  /// Returns true if the rectangles overlap or share an edge.
+  fn touches(&self, other: &Rect) -> bool {
+    self.x0 <= other.x1 + 1 && other.x0 <= self.x1 + 1 &&
+      self.y0 <= other.y1 + 1 && other.y0 <= self.y1 + 1
+  }
+
+  fn area(&self) -> u32 {
+    (self.x1 - self.x0 + 1) * (self.y1 - self.y0 + 1)
+  }
+}
+
+/// An `LCD` in RAM, flushed to another `LCD`.
+pub struct FrameBuffer<'a, B: Pixels, D: LCD<Color=B::Color> + 'a> {
+  display: &'a D,
+  pixels: UnsafeCell<B>,
+  dirty: [Cell<Option<Rect>>; DIRTY_SLOTS],
+  cursor: TextCursor<B::Color>,
+}
+
+impl<'a, B: Pixels, D: LCD<Color=B::Color>> FrameBuffer<'a, B, D> {
+  /// Creates a framebuffer with initial contents `pixels`, flushed to
+  /// `display`. Nothing is dirty until drawn over or `invalidate`d.
+  pub fn new(display: &'a D, pixels: B) -> FrameBuffer<'a, B, D> {
+    FrameBuffer {
+      display: display,
+      pixels: UnsafeCell::new(pixels),
+      dirty: [Cell::new(None), Cell::new(None), Cell::new(None), Cell::new(None)],
+      cursor: TextCursor::new(&font_small_7::SMALL_7,
+                              B::Color::from_rgb(Rgb888::new(0xff, 0xff, 0xff)),
+                              Some(B::Color::from_rgb(Rgb888::new(0, 0, 0)))),
+    }
+  }
+
+  /// Returns the text cursor used for `CharIO` output.
+  pub fn cursor(&self) -> &TextCursor<B::Color> {
+    &self.cursor
+  }
+
+  /// Returns the color of the pixel at `x`, `y`.
+  pub fn get_pixel(&self, x: u32, y: u32) -> Option<B::Color> {
+    if x < B::width() && y < B::height() {
+      Some(unsafe { *self.at(x, y) })
+    } else {
+      None
+    }
+  }
+
+  /// Marks the whole buffer as changed, so that the next `flush` redraws the
+  /// display.
+  pub fn invalidate(&self) {
+    for slot in self.dirty.iter() {
+      slot.set(None);
+    }
+    let all = Rect { x0: 0, y0: 0, x1: B::width() - 1, y1: B::height() - 1 };
+    self.dirty[0].set(Some(all));
+  }
+
+  /// Returns true if anything changed since the last `flush`.
+  pub fn is_dirty(&self) -> bool {
+    self.dirty.iter().any(|slot| slot.get().is_some())
+  }
+
+  /// Returns a pointer to the pixel at `x`, `y`, which must be in bounds.
+  ///
+  /// Pixels are only accessed through raw pointers, never through `&mut`, so
+  /// that no two live references into the buffer alias.
+  fn at(&self, x: u32, y: u32) -> *mut B::Color {
+    unsafe {
+      (self.pixels.get() as *mut B::Color).offset((y * B::width() + x) as isize)
+    }
+  }
+
+  fn mark_dirty(&self, rect: Rect) {
+    // Grow a rectangle that's next to this one, or use a free slot.
+    for slot in self.dirty.iter() {
+      if let Some(dirty) = slot.get() {
+        if dirty.touches(&rect) {
+          slot.set(Some(dirty.union(&rect)));
+          return;
+        }
+      }
+    }
+    for slot in self.dirty.iter() {
+      if slot.get().is_none() {
+        slot.set(Some(rect));
+        return;
+      }
+    }
+
+    // Out of slots, merge into the one that grows least.
+    let mut best = 0;
+    let mut best_growth = u32::max_value();
+    for (i, slot) in self.dirty.iter().enumerate() {
+      let dirty = slot.get().unwrap();
+      let growth = dirty.union(&rect).area() - dirty.area();
+      if growth < best_growth {
+        best = i;
+        best_growth = growth;
+      }
+    }
+    let dirty = self.dirty[best].get().unwrap();
+    self.dirty[best].set(Some(dirty.union(&rect)));
+  }
+
+  fn flush_rect(&self, rect: Rect) {
+    let width = rect.x1 - rect.x0 + 1;
+    if width == B::width() {
+      // Full rows are contiguous and go out in one write.
+      let height = rect.y1 - rect.y0 + 1;
+      let pixels = unsafe {
+        slice::from_raw_parts(self.at(0, rect.y0), (width * height) as usize)
+      };
+      self.display.write_area(0, rect.y0, width, height, pixels);
+    } else {
+      for y in rect.y0..(rect.y1 + 1) {
+        let row = unsafe {
+          slice::from_raw_parts(self.at(rect.x0, y), width as usize)
+        };
+        self.display.write_area(rect.x0, y, width, 1, row);
+      }
+    }
+  }
+}
+
+impl<'a, B: Pixels, D: LCD<Color=B::Color>> LCD for FrameBuffer<'a, B, D> {
+  type Color = B::Color;
+
+  fn clear(&self) {
+    let (width, height) = (B::width(), B::height());
+    self.fill_area(0, 0, width, height, B::Color::from_rgb(Rgb888::new(0, 0, 0)));
+  }
+
+  /// Writes the changed areas to the display and flushes it.
+  fn flush(&self) {
+    for slot in self.dirty.iter() {
+      if let Some(rect) = slot.get() {
+        self.flush_rect(rect);
+        slot.set(None);
+      }
+    }
+    self.display.flush();
+  }
+
+  fn pixel(&self, x: u32, y: u32, color: B::Color) {
+    if x < B::width() && y < B::height() {
+      unsafe { ptr::write(self.at(x, y), color) };
+      self.mark_dirty(Rect { x0: x, y0: y, x1: x, y1: y });
+    }
+  }
+
+  fn size(&self) -> (u32, u32) {
+    (B::width(), B::height())
+  }
+
+  fn fill_area(&self, x: u32, y: u32, width: u32, height: u32, color: B::Color) {
+    if width == 0 || height == 0 {
+      return;
+    }
+    for j in y..(y + height) {
+      for i in x..(x + width) {
+        unsafe { ptr::write(self.at(i, j), color) };
+      }
+    }
+    self.mark_dirty(Rect { x0: x, y0: y, x1: x + width - 1, y1: y + height - 1 });
+  }
+
+  fn write_area(&self, x: u32, y: u32, width: u32, height: u32, pixels: &[B::Color]) {
+    if width == 0 || height == 0 {
+      return;
+    }
+    for j in 0..height {
+      let src = &pixels[(j * width) as usize..((j + 1) * width) as usize];
+      // `pixels` may point into this buffer, ptr::copy handles the overlap.
+      unsafe { ptr::copy(src.as_ptr(), self.at(x, y + j), width as usize) };
+    }
+    self.mark_dirty(Rect { x0: x, y0: y, x1: x + width - 1, y1: y + height - 1 });
+  }
+}
+
+impl<'a, B: Pixels, D: LCD<Color=B::Color>> CharIO for FrameBuffer<'a, B, D> {
+  fn putc(&self, value: char) {
+    self.cursor.putc(self, value);
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use std::cell::RefCell;
+  use std::vec::Vec;
+
+  use drivers::chario::CharIO;
+  use drivers::lcd::LCD;
+  use drivers::lcd::color::Gray4;
+  use super::FrameBuffer;
+
+  /// Records the areas written to it.
+  struct Recorder {
+    writes: RefCell<Vec<(u32, u32, u32, u32)>>,
+    pixels: RefCell<[[u8; 16]; 8]>,
+  }
+
+  impl Recorder {
+    fn new() -> Recorder {
+      Recorder { writes: RefCell::new(Vec::new()), pixels: RefCell::new([[0; 16]; 8]) }
+    }
+
+    fn take_writes(&self) -> Vec<(u32, u32, u32, u32)> {
+      let mut writes = self.writes.borrow_mut();
+      let taken = writes.clone();
+      writes.clear();
+      taken
+    }
+  }
+
+  impl CharIO for Recorder {
+    fn putc(&self, _: char) {}
+  }
+
+  impl LCD for Recorder {
+    type Color = Gray4;
+
+    fn clear(&self) {}
+    fn flush(&self) {}
+    fn pixel(&self, x: u32, y: u32, color: Gray4) {
+      self.pixels.borrow_mut()[y as usize][x as usize] = color.0;
+    }
+    fn size(&self) -> (u32, u32) { (16, 8) }
+
+    fn write_area(&self, x: u32, y: u32, width: u32, height: u32, pixels: &[Gray4]) {
+      self.writes.borrow_mut().push((x, y, width, height));
+      for j in 0..height {
+        for i in 0..width {
+          self.pixel(x + i, y + j, pixels[(i + j * width) as usize]);
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn flush_should_write_only_changed_area() {
+    let display = Recorder::new();
+    let fb = FrameBuffer::new(&display, [[Gray4(0); 16]; 8]);
+    assert!(!fb.is_dirty());
+
+    fb.fillrect(2, 1, 4, 2, Gray4(5));
+    fb.pixel(5, 3, Gray4(6));
+    assert!(display.take_writes().is_empty());
+
+    fb.flush();
+    assert!(display.take_writes()[..] == [(2, 1, 4, 1), (2, 2, 4, 1), (2, 3, 4, 1)]);
+    assert!(display.pixels.borrow()[1][2] == 5);
+    assert!(display.pixels.borrow()[3][5] == 6);
+    assert!(display.pixels.borrow()[3][2] == 0);
+    assert!(fb.get_pixel(4, 2) == Some(Gray4(5)));
+
+    fb.flush();
+    assert!(display.take_writes().is_empty());
+  }
+
+  #[test]
+  fn full_rows_should_be_written_at_once() {
+    let display = Recorder::new();
+    let fb = FrameBuffer::new(&display, [[Gray4(0); 16]; 8]);
+    fb.fill_box(-3, 4, 30, 2, Gray4(1));
+    fb.flush();
+    assert!(display.take_writes()[..] == [(0, 4, 16, 2)]);
+
+    fb.invalidate();
+    fb.flush();
+    assert!(display.take_writes()[..] == [(0, 0, 16, 8)]);
+  }
+
+  #[test]
+  fn distant_changes_should_be_tracked_separately() {
+    let display = Recorder::new();
+    let fb = FrameBuffer::new(&display, [[Gray4(0); 16]; 8]);
+    for &(x, y) in &[(0, 0), (15, 0), (0, 7), (15, 7)] {
+      fb.pixel(x, y, Gray4(1));
+    }
+    fb.flush();
+    assert!(display.take_writes()[..] == [(0, 0, 1, 1), (15, 0, 1, 1), (0, 7, 1, 1),
+                                          (15, 7, 1, 1)]);
+
+    // A fifth area is merged into the nearest one.
+    for &(x, y) in &[(0, 0), (15, 0), (0, 7), (15, 7), (2, 6)] {
+      fb.pixel(x, y, Gray4(2));
+    }
+    fb.flush();
+    assert!(display.take_writes()[..] == [(0, 0, 1, 1), (15, 0, 1, 1), (0, 6, 3, 1),
+                                          (0, 7, 3, 1), (15, 7, 1, 1)]);
+  }
+}
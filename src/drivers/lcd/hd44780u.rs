@@ -14,23 +14,72 @@
 // limitations under the License.
 
 //! Driver for the Hitachi HD44780U LCD driver
+//!
+//! The controller is connected through a `Bus`: directly to GPIOs in 4bit
+//! (`Gpio4Bus`) or 8bit (`Gpio8Bus`) mode, or through a PCF8574 I2C port
+//! expander (`Pcf8574Bus`), as found on most I2C "backpacks".
+
+use core::cell::Cell;
 
 use drivers::chario::CharIO;
+use hal::i2c::I2c;
 use hal::pin::Gpio;
 use hal::timer::Timer;
 
-/// HD44780U driver context
-pub struct Hd44780u<'a> {
-  /// Timer used for protocol delays
+/// Connection to the HD44780U controller.
+pub trait Bus {
+  /// Returns true if all 8 data lines are connected, false in 4bit mode.
+  fn eight_bit(&self) -> bool;
+
+  /// Sets the data lines to `value` and pulses EN. RS is high if `rs` is true
+  /// (data) and low otherwise (instruction). In 4bit mode only the low nibble
+  /// of `value` is used.
+  fn write(&self, rs: bool, value: u8);
+
+  /// Turns the backlight on or off, where the bus controls it.
+  fn set_backlight(&self, _on: bool) {}
+}
+
+/// 4bit parallel bus on GPIO pins.
+pub struct Gpio4Bus<'a> {
+  /// Timer used for the EN pulse
   timer: &'a (Timer + 'a),
   /// Register Selector wire
   rs:    &'a (Gpio + 'a),
   /// Enable wire
   en:    &'a (Gpio + 'a),
-  /// The 4 data wires. Those must be wired to [d4, d5, d6, d7] in 4bit mode.
+  /// The 4 data wires. Those must be wired to [d4, d5, d6, d7].
   data: [&'a (Gpio + 'a); 4],
 }
 
+/// 8bit parallel bus on GPIO pins.
+pub struct Gpio8Bus<'a> {
+  /// Timer used for the EN pulse
+  timer: &'a (Timer + 'a),
+  /// Register Selector wire
+  rs:    &'a (Gpio + 'a),
+  /// Enable wire
+  en:    &'a (Gpio + 'a),
+  /// The 8 data wires, [d0, d1, ..., d7].
+  data: [&'a (Gpio + 'a); 8],
+}
+
+/// PCF8574 I2C port expander, wired as P0 = RS, P1 = RW, P2 = EN,
+/// P3 = backlight and P4..P7 = d4..d7.
+pub struct Pcf8574Bus<'a, I: 'a> {
+  i2c: &'a I,
+  address: u8,
+  backlight: Cell<bool>,
+}
+
+/// HD44780U driver context
+pub struct Hd44780u<'a, B = Gpio4Bus<'a>> {
+  /// Timer used for protocol delays
+  timer: &'a (Timer + 'a),
+  /// Connection to the controller
+  bus: B,
+}
+
 /// The controller supports writing in either direction to accomodate various
 /// languages.
 #[derive(Clone, Copy)]
@@ -51,45 +100,64 @@ pub enum Font {
 }
 
 impl<'a> Hd44780u<'a> {
-  /// Construct an Hd44780u instance
+  /// Construct an Hd44780u instance on a 4bit GPIO bus
   pub fn new(timer: &'a (Timer + 'a),
                  rs:    &'a (Gpio  + 'a),
                  en:    &'a (Gpio  + 'a),
                  data: [&'a (Gpio  + 'a); 4]) -> Hd44780u<'a> {
-    Hd44780u { timer: timer, rs: rs, en: en, data: data }
+    Hd44780u::with_bus(timer, Gpio4Bus::new(timer, rs, en, data))
   }
+}
 
-  /// Power up sequence for 4bit mode as detailed in page 46 of the datasheet.
+impl<'a, B: Bus> Hd44780u<'a, B> {
+  /// Construct an Hd44780u instance on any bus
+  pub fn with_bus(timer: &'a (Timer + 'a), bus: B) -> Hd44780u<'a, B> {
+    Hd44780u { timer: timer, bus: bus }
+  }
+
+  /// Returns the bus the controller is connected to.
+  pub fn bus(&self) -> &B {
+    &self.bus
+  }
+
+  /// Turns the backlight on or off, if the bus controls it.
+  pub fn set_backlight(&self, on: bool) {
+    self.bus.set_backlight(on);
+  }
+
+  /// Power up sequence as detailed in pages 45 and 46 of the datasheet.
   /// The two_lines and font parameters are used to set the LCD parameters. See
   /// `function_set` for more details.
   pub fn init(&self, two_lines: bool, font: Font) {
-    // Start by pulling RS and EN low
-    self.rs.set_low();
-    self.en.set_low();
-
     // We need to wait at least 40ms after the chip is powered before we can
     // talk to it. Assume that the power just went on and wait.
     self.timer.wait_ms(50);
 
+    // In 4bit mode only the high nibble of the instruction is sent.
+    let eight_bit = self.bus.eight_bit();
+    let set_8bit = if eight_bit { 0b00110000 } else { 0b0011 };
+
     // Set interface to 8bit
-    self.write_nibble(0b0011);
+    self.bus.write(false, set_8bit);
 
     // Wait 4.1ms
     self.timer.wait_us(4100);
 
     // Set interface to 8bit
-    self.write_nibble(0b0011);
+    self.bus.write(false, set_8bit);
 
     // Wait 100us
     self.timer.wait_us(100);
 
     // Set interface to 8bit (for the last time...)
-    self.write_nibble(0b0011);
+    self.bus.write(false, set_8bit);
     self.timer.wait_us(100);
 
-    // We can now finally switch to 4bit
-    self.write_nibble(0b0010);
-    self.timer.wait_us(100);
+    if !eight_bit {
+      // We can now finally switch to 4bit
+      self.bus.write(false, 0b0010);
+      self.timer.wait_us(100);
+    }
 
     // Set function
     self.function_set(two_lines, font);
@@ -179,7 +247,7 @@ impl<'a> Hd44780u<'a> {
   /// Set the bus width, number of lines and character font size. This function
   /// can only be called once in the init sequence.
   ///
-  /// Only 4bit wide interfaces are supported for now.
+  /// The bus width is taken from the bus.
   ///
   /// `two_lines` should be
   /// `false` if the display only has one line, true otherwise.
@@ -190,8 +258,7 @@ impl<'a> Hd44780u<'a> {
     use self::Font::*;
     let mut cmd = 0b100000;
 
-    // Only 4bit interface is supported at the moment
-    cmd |= 0                 << 4;
+    cmd |= (self.bus.eight_bit() as u8) << 4;
     cmd |= (two_lines as u8) << 3;
     cmd |= match font {
       Font5x8  => 0,
@@ -261,10 +328,7 @@ impl<'a> Hd44780u<'a> {
 
   /// Send a 8bit command code
   fn instruction(&self, cmd: u8) {
-    self.rs.set_low();
-
-    self.write_nibble(cmd >> 4);
-    self.write_nibble(cmd);
+    self.write(false, cmd);
     // Commands take *at least* 37us to execute, so default to that. If the
     // current instruction needs to delay for more than that the calling
     // function will wait some more.
@@ -273,47 +337,184 @@ impl<'a> Hd44780u<'a> {
 
   /// Send 8bit of data
   fn data(&self, data: u8) {
-    self.rs.set_high();
-
-    self.write_nibble(data >> 4);
-    self.write_nibble(data);
+    self.write(true, data);
     // Data access take 37us
     self.timer.wait_us(40);
   }
 
-  /// Write a 4bit value to the parallel interface.
-  fn write_nibble(&self, v: u8) {
-    // Set the 4 data lines in the parallel interface
-    match v & 1 {
-      0 => self.data[0].set_low(),
-      _ => self.data[0].set_high(),
+  /// Write a byte, as two nibbles in 4bit mode.
+  fn write(&self, rs: bool, v: u8) {
+    if self.bus.eight_bit() {
+      self.bus.write(rs, v);
+    } else {
+      self.bus.write(rs, v >> 4);
+      self.bus.write(rs, v & 0xf);
     }
+  }
+}
 
-    match v & 2 {
-      0 => self.data[1].set_low(),
-      _ => self.data[1].set_high(),
-    }
+impl<'a, B: Bus> CharIO for Hd44780u<'a, B> {
+  fn putc(&self, value: char) {
+    self.data(value as u8);
+  }
+}
 
-    match v & 4 {
-      0 => self.data[2].set_low(),
-      _ => self.data[2].set_high(),
-    }
+/// Sets the data lines and pulses EN on a GPIO bus.
+fn write_pins(timer: &Timer, rs: &Gpio, en: &Gpio, data: &[&Gpio], rs_high: bool,
+              v: u8) {
+  if rs_high {
+    rs.set_high();
+  } else {
+    rs.set_low();
+  }
 
-    match v & 8 {
-      0 => self.data[3].set_low(),
-      _ => self.data[3].set_high(),
+  // Set the data lines in the parallel interface
+  for (i, pin) in data.iter().enumerate() {
+    match v & (1 << i) {
+      0 => pin.set_low(),
+      _ => pin.set_high(),
     }
+  }
 
-    // Pulse the EN wire to notify the controller.
-    self.en.set_high();
-    // We need to assert EN for at least 450ns
-    self.timer.wait_us(1);
-    self.en.set_low();
+  // Pulse the EN wire to notify the controller.
+  en.set_high();
+  // We need to assert EN for at least 450ns
+  timer.wait_us(1);
+  en.set_low();
+}
+
+impl<'a> Gpio4Bus<'a> {
+  /// Construct a 4bit bus. `data` must be wired to [d4, d5, d6, d7].
+  pub fn new(timer: &'a (Timer + 'a),
+             rs:    &'a (Gpio + 'a),
+             en:    &'a (Gpio + 'a),
+             data: [&'a (Gpio + 'a); 4]) -> Gpio4Bus<'a> {
+    // Start by pulling RS and EN low
+    rs.set_low();
+    en.set_low();
+    Gpio4Bus { timer: timer, rs: rs, en: en, data: data }
   }
 }
 
-impl<'a> CharIO for Hd44780u<'a> {
-  fn putc(&self, value: char) {
-    self.data(value as u8);
+impl<'a> Bus for Gpio4Bus<'a> {
+  fn eight_bit(&self) -> bool {
+    false
+  }
+
+  fn write(&self, rs: bool, value: u8) {
+    write_pins(self.timer, self.rs, self.en, &self.data, rs, value);
+  }
+}
+
+impl<'a> Gpio8Bus<'a> {
+  /// Construct an 8bit bus. `data` must be wired to [d0, d1, ..., d7].
+  pub fn new(timer: &'a (Timer + 'a),
+             rs:    &'a (Gpio + 'a),
+             en:    &'a (Gpio + 'a),
+             data: [&'a (Gpio + 'a); 8]) -> Gpio8Bus<'a> {
+    rs.set_low();
+    en.set_low();
+    Gpio8Bus { timer: timer, rs: rs, en: en, data: data }
+  }
+}
+
+impl<'a> Bus for Gpio8Bus<'a> {
+  fn eight_bit(&self) -> bool {
+    true
+  }
+
+  fn write(&self, rs: bool, value: u8) {
+    write_pins(self.timer, self.rs, self.en, &self.data, rs, value);
+  }
+}
+
+impl<'a, I: I2c> Pcf8574Bus<'a, I> {
+  /// Construct a bus on the PCF8574 at `address`, usually 0x27 (or 0x3f for
+  /// the PCF8574A). The backlight starts on.
+  pub fn new(i2c: &'a I, address: u8) -> Pcf8574Bus<'a, I> {
+    Pcf8574Bus { i2c: i2c, address: address, backlight: Cell::new(true) }
+  }
+
+  fn backlight_bit(&self) -> u8 {
+    if self.backlight.get() { 0b1000 } else { 0 }
+  }
+}
+
+impl<'a, I: I2c> Bus for Pcf8574Bus<'a, I> {
+  fn eight_bit(&self) -> bool {
+    false
+  }
+
+  fn write(&self, rs: bool, value: u8) {
+    let port = (value << 4) | self.backlight_bit() | rs as u8;
+    // A single I2C byte takes longer than the 450ns EN pulse.
+    self.i2c.write(self.address, &[port | 0b100, port]);
+  }
+
+  fn set_backlight(&self, on: bool) {
+    self.backlight.set(on);
+    self.i2c.write(self.address, &[self.backlight_bit()]);
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use std::cell::RefCell;
+  use std::vec::Vec;
+
+  use drivers::chario::CharIO;
+  use hal::i2c::I2c;
+  use hal::timer::Timer;
+  use super::{Hd44780u, Pcf8574Bus};
+
+  struct NoDelay;
+
+  impl Timer for NoDelay {
+    fn get_counter(&self) -> u32 { 0 }
+    fn wait_us(&self, _: u32) {}
+  }
+
+  struct Recorder {
+    writes: RefCell<Vec<(u8, Vec<u8>)>>,
+  }
+
+  impl I2c for Recorder {
+    fn write(&self, address: u8, bytes: &[u8]) {
+      self.writes.borrow_mut().push((address, bytes.to_vec()));
+    }
+
+    fn write_prefixed(&self, address: u8, prefix: u8, bytes: &[u8]) {
+      let mut frame = Vec::new();
+      frame.push(prefix);
+      frame.extend_from_slice(bytes);
+      self.writes.borrow_mut().push((address, frame));
+    }
+  }
+
+  #[test]
+  fn pcf8574_should_send_nibbles_with_backlight() {
+    let i2c = Recorder { writes: RefCell::new(Vec::new()) };
+    let timer = NoDelay;
+    let lcd = Hd44780u::with_bus(&timer, Pcf8574Bus::new(&i2c, 0x27));
+
+    lcd.putc('A');
+    lcd.set_pos(1, 1);
+    lcd.set_backlight(false);
+    lcd.putc('\x01');
+
+    let writes = i2c.writes.borrow();
+    let expected: [&[u8]; 7] = [
+      // 'A' = 0x41 as data, backlight on
+      &[0x4d, 0x49], &[0x1d, 0x19],
+      // DDRAM address 0x41
+      &[0xcc, 0xc8], &[0x1c, 0x18],
+      // Backlight off, then data 0x01
+      &[0x00],
+      &[0x05, 0x01], &[0x15, 0x11],
+    ];
+    assert!(writes.len() == expected.len());
+    for (&(address, ref bytes), exp) in writes.iter().zip(expected.iter()) {
+      assert!(address == 0x27 && &bytes[..] == *exp);
+    }
   }
 }
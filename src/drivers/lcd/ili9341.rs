@@ -16,6 +16,9 @@
 //! Driver for the ILI9341 LCD.
 
 use super::LCD;
+use super::color::Rgb565;
+use super::font::TextCursor;
+use super::font_small_7;
 use drivers::chario::CharIO;
 use hal::timer::Timer;
 use hal::pin::Gpio;
@@ -29,6 +32,7 @@ pub struct ILI9341<'a, S:'a, T:'a, P:'a> {
   cs: &'a P,
   reset: &'a P,
   // backlight: gpio::OutGPIO,
+  cursor: TextCursor<Rgb565>,
 }
 
 impl<'a, S: Spi, T: Timer, P: Gpio> ILI9341<'a, S, T, P> {
@@ -41,6 +45,8 @@ impl<'a, S: Spi, T: Timer, P: Gpio> ILI9341<'a, S, T, P> {
       dc: dc,
       cs: cs,
       reset:reset,
+      cursor: TextCursor::new(&font_small_7::SMALL_7_X2, Rgb565(0xffff),
+                              Some(Rgb565(0))),
       // dc: gpio::OutGPIO::new(0, 24),
       // cs: gpio::OutGPIO::new(0, 16),
       // reset: gpio::OutGPIO::new(0, 23),
@@ -285,26 +291,62 @@ impl<'a, S: Spi, T: Timer, P: Gpio> ILI9341<'a, S, T, P> {
     self.cs.set_high();
   }
 
-  fn do_pixel(&self, x: u32, y: u32, color: u16) {
+  /// Returns the text cursor used for `CharIO` output.
+  pub fn cursor(&self) -> &TextCursor<Rgb565> {
+    &self.cursor
+  }
+
+  fn do_pixel(&self, x: u32, y: u32, color: Rgb565) {
     self.set_col(x as u16, x as u16);
     self.set_page(y as u16, y as u16);
     self.send_cmd(0x2c);
-    self.send_data(color);
+    self.send_data(color.0);
+  }
+
+  /// Selects a window and starts a memory write into it.
+  fn start_area(&self, x: u32, y: u32, width: u32, height: u32) {
+    self.set_col(x as u16, (x + width - 1) as u16);
+    self.set_page(y as u16, (y + height - 1) as u16);
+    self.send_cmd(0x2c);
+
+    self.dc.set_high();
+    self.cs.set_low();
   }
 }
 
 impl<'a, S: Spi, T: Timer, P: Gpio> LCD for ILI9341<'a, S, T, P> {
+  type Color = Rgb565;
+
   fn clear(&self) {
     self.do_clear();
   }
   fn flush(&self) {}
-  fn pixel(&self, x: u32, y: u32, color: u16) {
+  fn pixel(&self, x: u32, y: u32, color: Rgb565) {
     self.do_pixel(x, y, color);
   }
+  fn size(&self) -> (u32, u32) {
+    (240, 320)
+  }
+  fn fill_area(&self, x: u32, y: u32, width: u32, height: u32, color: Rgb565) {
+    self.start_area(x, y, width, height);
+    for _ in 0..(width * height) {
+      self.spi.transfer((color.0 >> 8) as u8);
+      self.spi.transfer((color.0 & 0xff) as u8);
+    }
+    self.cs.set_high();
+  }
+  fn write_area(&self, x: u32, y: u32, width: u32, height: u32, pixels: &[Rgb565]) {
+    self.start_area(x, y, width, height);
+    for &color in &pixels[..(width * height) as usize] {
+      self.spi.transfer((color.0 >> 8) as u8);
+      self.spi.transfer((color.0 & 0xff) as u8);
+    }
+    self.cs.set_high();
+  }
 }
 
 impl<'a, S: Spi, T: Timer, P: Gpio> CharIO for ILI9341<'a, S, T, P> {
-  fn putc(&self, _: char) {
-    // TODO(farcaller): implement
+  fn putc(&self, value: char) {
+    self.cursor.putc(self, value);
   }
 }
@@ -15,32 +15,47 @@
 
 //! Drivers for TFT LCDs.
 
+use core::cmp;
+
 use drivers::chario::CharIO;
 
+use self::color::PixelColor;
+use self::font::Font;
+
 pub mod c12332;
+pub mod color;
 pub mod ili9341;
+pub mod font;
 pub mod font_small_7;
+pub mod framebuffer;
 pub mod hd44780u;
+pub mod ssd1306;
 
 /// LCD provides a generic interface to a TFT LCD peripheral.
 ///
 /// It provides generic methods for drawing primitives and bitmaps based on
-/// `pixel` to set a pixel.
+/// `pixel` to set a pixel, in the display's native `Color` format.
 ///
 /// LCD does not flush buffers automatically, user must call `flush` after the
 /// drawing sequence to actually display the data on screen.
 pub trait LCD : CharIO {
+  /// Native pixel color format.
+  type Color: PixelColor;
+
   /// Clears the screen.
   fn clear(&self);
 
   /// Flushes the internal buffer to screen, where applicable.
   fn flush(&self);
 
-  /// Sets one pixel color. The actual color bits are driver-specific.
-  fn pixel(&self, x: u32, y: u32, color: u16);
+  /// Sets one pixel color.
+  fn pixel(&self, x: u32, y: u32, color: Self::Color);
+
+  /// Returns the screen width and height in pixels.
+  fn size(&self) -> (u32, u32);
 
   /// Draws a line from xy0 to xy1.
-  fn line(&self, x0_b: u32, y0_b: u32, x1: u32, y1: u32, color: u16) {
+  fn line(&self, x0_b: u32, y0_b: u32, x1: u32, y1: u32, color: Self::Color) {
     let (mut x0, mut y0) = (x0_b as i32, y0_b as i32);
 
     let (dx, dy) = ((x1 as i32) - (x0), (y1 as i32) - (y0));
@@ -93,7 +108,7 @@ pub trait LCD : CharIO {
   }
 
   /// Draws a rectangle.
-  fn rect(&self, x0: u32, y0: u32, x1: u32, y1: u32, color: u16) {
+  fn rect(&self, x0: u32, y0: u32, x1: u32, y1: u32, color: Self::Color) {
     if x1 > x0 {
       self.line(x0,y0,x1,y0,color);
     } else {
@@ -120,7 +135,7 @@ pub trait LCD : CharIO {
   }
 
   /// Draws a filled rectangle.
-  fn fillrect(&self, x0_b: u32, y0_b: u32, x1_b: u32, y1_b: u32, color: u16) {
+  fn fillrect(&self, x0_b: u32, y0_b: u32, x1_b: u32, y1_b: u32, color: Self::Color) {
 
     let (x0, x1) =
       if x0_b > x1_b {
@@ -136,21 +151,375 @@ pub trait LCD : CharIO {
         (y0_b, y1_b)
       };
 
-    for l in x0..(x1 + 1) {
-      for c in y0..(y1 + 1) {
-        self.pixel(l as u32, c as u32, color);
+    self.fill_box(x0 as i32, y0 as i32, x1 - x0 + 1, y1 - y0 + 1, color);
+  }
+
+  /// Draws an image from a buffer.
+  fn image(&self, width: u32, height: u32, data: &[Self::Color]) {
+    self.blit(0, 0, width, height, data, None);
+  }
+
+  /// Fills an area that is fully on screen.
+  ///
+  /// Drivers that can write a window of pixels at once should override this
+  /// and `write_area`, all the filled primitives are drawn through them.
+  fn fill_area(&self, x: u32, y: u32, width: u32, height: u32, color: Self::Color) {
+    for j in y..(y + height) {
+      for i in x..(x + width) {
+        self.pixel(i, j, color);
       }
     }
   }
 
-  /// Draws an image from a buffer.
-  fn image(&self, width: u32, height: u32, data: &[u16]) {
-    for x in 0..width {
-      for y in 0..height {
-        self.pixel(x, y, data[(x+y*width) as usize]);
+  /// Writes row-major `pixels` to an area that is fully on screen.
+  fn write_area(&self, x: u32, y: u32, width: u32, height: u32, pixels: &[Self::Color]) {
+    for j in 0..height {
+      for i in 0..width {
+        self.pixel(x + i, y + j, pixels[(i + j * width) as usize]);
       }
     }
   }
+
+  /// Sets one pixel, ignoring it if it's off screen.
+  fn plot(&self, x: i32, y: i32, color: Self::Color) {
+    let (width, height) = self.size();
+    if x >= 0 && y >= 0 && (x as u32) < width && (y as u32) < height {
+      self.pixel(x as u32, y as u32, color);
+    }
+  }
+
+  /// Fills a rectangle, clipped to the screen.
+  fn fill_box(&self, x: i32, y: i32, width: u32, height: u32, color: Self::Color) {
+    if let Some((x, y, width, height)) = clip(self.size(), x, y, width, height) {
+      self.fill_area(x, y, width, height, color);
+    }
+  }
+
+  /// Draws a line, clipped to the screen.
+  fn draw_line(&self, x0: i32, y0: i32, x1: i32, y1: i32, color: Self::Color) {
+    self.thick_line(x0, y0, x1, y1, 1, color);
+  }
+
+  /// Draws a line `width` pixels thick, clipped to the screen.
+  fn thick_line(&self, x0: i32, y0: i32, x1: i32, y1: i32, width: u32,
+                color: Self::Color) {
+    let (dx, dy) = ((x1 - x0).abs(), -(y1 - y0).abs());
+    let (sx, sy) = (if x0 < x1 { 1 } else { -1 }, if y0 < y1 { 1 } else { -1 });
+    let offset = (width / 2) as i32;
+    let (mut x, mut y, mut err) = (x0, y0, dx + dy);
+    loop {
+      if width == 1 {
+        self.plot(x, y, color);
+      } else {
+        self.fill_box(x - offset, y - offset, width, width, color);
+      }
+      if x == x1 && y == y1 {
+        break;
+      }
+      let e2 = err * 2;
+      if e2 >= dy {
+        err += dy;
+        x += sx;
+      }
+      if e2 <= dx {
+        err += dx;
+        y += sy;
+      }
+    }
+  }
+
+  /// Draws a circle outline.
+  fn circle(&self, cx: i32, cy: i32, r: u32, color: Self::Color) {
+    rounded(self, cx, cy, cx, cy, r as i32, r as i32, color, false);
+  }
+
+  /// Draws a filled circle.
+  fn fill_circle(&self, cx: i32, cy: i32, r: u32, color: Self::Color) {
+    rounded(self, cx, cy, cx, cy, r as i32, r as i32, color, true);
+  }
+
+  /// Draws the part of a circle outline from `start` to `end` degrees,
+  /// counterclockwise from 3 o'clock. Angles may be negative, a sweep of 360
+  /// degrees or more draws the whole circle.
+  fn arc(&self, cx: i32, cy: i32, r: u32, start: i32, end: i32, color: Self::Color) {
+    if end - start >= 360 {
+      self.circle(cx, cy, r, color);
+      return;
+    }
+    let (start, sweep) = (normalize_angle(start), normalize_angle(end - start));
+    let r = r as i32;
+    for dy in 0..(r + 1) {
+      let w = half_width(r, r, dy);
+      let next = if dy == r { -1 } else { half_width(r, r, dy + 1) };
+      // Same pixels as circle(), checked one by one against the angle range.
+      for dx in cmp::min(next + 1, w)..(w + 1) {
+        for &(x, y) in [(dx, dy), (-dx, dy), (dx, -dy), (-dx, -dy)].iter() {
+          if normalize_angle(angle_of(x, y) - start) <= sweep {
+            self.plot(cx + x, cy - y, color);
+          }
+        }
+      }
+    }
+  }
+
+  /// Draws an ellipse outline with radii `rx` and `ry`.
+  fn ellipse(&self, cx: i32, cy: i32, rx: u32, ry: u32, color: Self::Color) {
+    rounded(self, cx, cy, cx, cy, rx as i32, ry as i32, color, false);
+  }
+
+  /// Draws a filled ellipse with radii `rx` and `ry`.
+  fn fill_ellipse(&self, cx: i32, cy: i32, rx: u32, ry: u32, color: Self::Color) {
+    rounded(self, cx, cy, cx, cy, rx as i32, ry as i32, color, true);
+  }
+
+  /// Draws a rectangle outline with corners of radius `r`.
+  fn round_rect(&self, x0: i32, y0: i32, x1: i32, y1: i32, r: u32, color: Self::Color) {
+    let (left, top, right, bottom, r) = round_rect_bounds(x0, y0, x1, y1, r);
+    rounded(self, left, top, right, bottom, r, r, color, false);
+  }
+
+  /// Draws a filled rectangle with corners of radius `r`.
+  fn fill_round_rect(&self, x0: i32, y0: i32, x1: i32, y1: i32, r: u32,
+                     color: Self::Color) {
+    let (left, top, right, bottom, r) = round_rect_bounds(x0, y0, x1, y1, r);
+    rounded(self, left, top, right, bottom, r, r, color, true);
+  }
+
+  /// Draws a triangle outline.
+  fn triangle(&self, x0: i32, y0: i32, x1: i32, y1: i32, x2: i32, y2: i32,
+              color: Self::Color) {
+    self.polygon(&[(x0, y0), (x1, y1), (x2, y2)], color);
+  }
+
+  /// Draws a filled triangle.
+  fn fill_triangle(&self, x0: i32, y0: i32, x1: i32, y1: i32, x2: i32, y2: i32,
+                   color: Self::Color) {
+    // Sort the vertices by y.
+    let mut v = [(x0, y0), (x1, y1), (x2, y2)];
+    if v[0].1 > v[1].1 { v.swap(0, 1); }
+    if v[1].1 > v[2].1 { v.swap(1, 2); }
+    if v[0].1 > v[1].1 { v.swap(0, 1); }
+    let ((ax, ay), (bx, by), (cx, cy)) = (v[0], v[1], v[2]);
+
+    if ay == cy {
+      let from = cmp::min(ax, cmp::min(bx, cx));
+      let to = cmp::max(ax, cmp::max(bx, cx));
+      self.fill_box(from, ay, (to - from + 1) as u32, 1, color);
+      return;
+    }
+    for y in ay..(cy + 1) {
+      let long = edge_x(ax, ay, cx, cy, y);
+      let short = if y < by || by == cy {
+        edge_x(ax, ay, bx, by, y)
+      } else {
+        edge_x(bx, by, cx, cy, y)
+      };
+      let (from, to) = if long < short { (long, short) } else { (short, long) };
+      self.fill_box(from, y, (to - from + 1) as u32, 1, color);
+    }
+  }
+
+  /// Draws a closed polygon outline.
+  fn polygon(&self, points: &[(i32, i32)], color: Self::Color) {
+    for i in 0..points.len() {
+      let (x0, y0) = points[i];
+      let (x1, y1) = points[(i + 1) % points.len()];
+      self.draw_line(x0, y0, x1, y1, color);
+    }
+  }
+
+  /// Draws a row-major image at `x`, `y`, clipped to the screen. Pixels of the
+  /// `transparent` color are skipped.
+  fn blit(&self, x: i32, y: i32, width: u32, height: u32, data: &[Self::Color],
+          transparent: Option<Self::Color>) {
+    let (vx, vy, vw, vh) = match clip(self.size(), x, y, width, height) {
+      Some(area) => area,
+      None => return,
+    };
+    for row in vy..(vy + vh) {
+      let start = ((row as i32 - y) as u32 * width + (vx as i32 - x) as u32) as usize;
+      let line = &data[start..start + vw as usize];
+      match transparent {
+        None => self.write_area(vx, row, vw, 1, line),
+        Some(key) => {
+          // Write runs of opaque pixels.
+          let mut i = 0;
+          while i < line.len() {
+            if line[i] == key {
+              i += 1;
+              continue;
+            }
+            let run = line[i..].iter().position(|&c| c == key).unwrap_or(line.len() - i);
+            self.write_area(vx + i as u32, row, run as u32, 1, &line[i..i + run]);
+            i += run;
+          }
+        },
+      }
+    }
+  }
+
+  /// Draws a character at `x`, `y` and returns the position of the next one.
+  ///
+  /// Moves to a new line starting at `left` on `'\n'` or if the character
+  /// doesn't fit at the right edge. Background pixels are left untouched if
+  /// `bg` is `None`.
+  fn draw_char(&self, left: u32, x: u32, y: u32, c: char, font: &Font,
+               fg: Self::Color, bg: Option<Self::Color>) -> (u32, u32) {
+    match c {
+      '\n' => return (left, y + font.height()),
+      '\r' => return (left, y),
+      _ => {},
+    }
+    let glyph = match font.glyph(c) {
+      Some(glyph) => glyph,
+      None => return (x, y),
+    };
+
+    let (width, _) = self.size();
+    let (x, y) = if x + glyph.width > width && x > left {
+      (left, y + font.height())
+    } else {
+      (x, y)
+    };
+
+    for j in 0..glyph.height {
+      for i in 0..glyph.width {
+        if glyph.pixel(i, j) {
+          self.pixel(x + i, y + j, fg);
+        } else if let Some(bg) = bg {
+          self.pixel(x + i, y + j, bg);
+        }
+      }
+    }
+    (x + glyph.width, y)
+  }
+
+  /// Draws text starting at `x`, `y`, wrapping lines back to `x`. Returns the
+  /// position after the last character.
+  fn draw_text(&self, x: u32, y: u32, text: &str, font: &Font, fg: Self::Color,
+               bg: Option<Self::Color>) -> (u32, u32) {
+    let mut pos = (x, y);
+    for c in text.chars() {
+      pos = self.draw_char(x, pos.0, pos.1, c, font, fg, bg);
+    }
+    pos
+  }
+}
+
+/// Clips a rectangle to the screen, returning the visible part.
+fn clip(screen: (u32, u32), x: i32, y: i32, width: u32,
+        height: u32) -> Option<(u32, u32, u32, u32)> {
+  let (x0, y0) = (if x < 0 { 0 } else { x as i64 }, if y < 0 { 0 } else { y as i64 });
+  let x1 = cmp::min(x as i64 + width as i64, screen.0 as i64);
+  let y1 = cmp::min(y as i64 + height as i64, screen.1 as i64);
+  if x0 >= x1 || y0 >= y1 {
+    None
+  } else {
+    Some((x0 as u32, y0 as u32, (x1 - x0) as u32, (y1 - y0) as u32))
+  }
+}
+
+/// Integer square root.
+fn isqrt(n: u64) -> u64 {
+  if n < 2 {
+    return n;
+  }
+  let (mut x, mut y) = (n, (n + 1) / 2);
+  while y < x {
+    x = y;
+    y = (x + n / x) / 2;
+  }
+  x
+}
+
+/// Half-width of an ellipse `dy` rows away from its center.
+fn half_width(rx: i32, ry: i32, dy: i32) -> i32 {
+  if ry == 0 {
+    return rx;
+  }
+  let (rx, ry, dy) = (rx as u64, ry as u64, dy as u64);
+  // Points are inside if they're within half a pixel of the true curve.
+  let w = isqrt(rx * rx * (ry * ry + ry - dy * dy) / (ry * ry));
+  cmp::min(w, rx) as i32
+}
+
+/// Maps an angle in degrees into `0..360`.
+fn normalize_angle(angle: i32) -> i32 {
+  (angle % 360 + 360) % 360
+}
+
+/// Angle of a point relative to the origin, in degrees counterclockwise from
+/// the positive x axis, with y growing up.
+fn angle_of(x: i32, y: i32) -> i32 {
+  let (ax, ay) = (x.abs() as i64, y.abs() as i64);
+  if ax == 0 && ay == 0 {
+    return 0;
+  }
+  // atan(z) ~ 45z + 15.64z(1 - z) degrees for z in 0..1, within 0.25 degrees.
+  let (a, b) = if ay <= ax { (ay, ax) } else { (ax, ay) };
+  let z = a * 1024 / b;
+  let octant = ((4500 * z + 1564 * z * (1024 - z) / 1024) / 1024 + 50) / 100;
+  let quadrant = (if ay <= ax { octant } else { 90 - octant }) as i32;
+  match (x >= 0, y >= 0) {
+    (true, true)   => quadrant,
+    (false, true)  => 180 - quadrant,
+    (false, false) => 180 + quadrant,
+    (true, false)  => normalize_angle(360 - quadrant),
+  }
+}
+
+/// Draws an ellipse with radii `rx`, `ry` split into four quarters centered at
+/// the corners of `left`, `top`, `right`, `bottom`, joined with straight
+/// lines. This is a plain ellipse if the corners are at the same point.
+fn rounded<L: LCD + ?Sized>(lcd: &L, left: i32, top: i32, right: i32, bottom: i32,
+                            rx: i32, ry: i32, color: L::Color, fill: bool) {
+  let band = (bottom - top + 1) as u32;
+  if fill {
+    lcd.fill_box(left - rx, top, (right - left + 2 * rx + 1) as u32, band, color);
+    for dy in 1..(ry + 1) {
+      let w = half_width(rx, ry, dy);
+      let span = (right - left + 2 * w + 1) as u32;
+      lcd.fill_box(left - w, top - dy, span, 1, color);
+      lcd.fill_box(left - w, bottom + dy, span, 1, color);
+    }
+    return;
+  }
+
+  lcd.fill_box(left - rx, top, 1, band, color);
+  lcd.fill_box(right + rx, top, 1, band, color);
+  if right > left {
+    lcd.fill_box(left, top - ry, (right - left + 1) as u32, 1, color);
+    lcd.fill_box(left, bottom + ry, (right - left + 1) as u32, 1, color);
+  }
+  for dy in 0..(ry + 1) {
+    let w = half_width(rx, ry, dy);
+    let next = if dy == ry { -1 } else { half_width(rx, ry, dy + 1) };
+    // Horizontal run connecting this row of the curve to the next one.
+    let from = cmp::min(next + 1, w);
+    let len = (w - from + 1) as u32;
+    lcd.fill_box(left - w, top - dy, len, 1, color);
+    lcd.fill_box(right + from, top - dy, len, 1, color);
+    lcd.fill_box(left - w, bottom + dy, len, 1, color);
+    lcd.fill_box(right + from, bottom + dy, len, 1, color);
+  }
+}
+
+/// Normalizes a rounded rectangle into corner centers and a radius that fits.
+fn round_rect_bounds(x0: i32, y0: i32, x1: i32, y1: i32,
+                     r: u32) -> (i32, i32, i32, i32, i32) {
+  let (x0, x1) = (cmp::min(x0, x1), cmp::max(x0, x1));
+  let (y0, y1) = (cmp::min(y0, y1), cmp::max(y0, y1));
+  let r = cmp::min(r as i32, cmp::min(x1 - x0, y1 - y0) / 2);
+  (x0 + r, y0 + r, x1 - r, y1 - r, r)
+}
+
+/// X coordinate of the edge from `x0`, `y0` to `x1`, `y1` at row `y`.
+fn edge_x(x0: i32, y0: i32, x1: i32, y1: i32, y: i32) -> i32 {
+  if y1 == y0 {
+    x1
+  } else {
+    x0 + ((x1 - x0) as i64 * (y - y0) as i64 / (y1 - y0) as i64) as i32
+  }
 }
 
 #[cfg(test)]
@@ -161,34 +530,47 @@ mod test {
   use core::ops::Range;
 
   use drivers::chario::CharIO;
+  use std::vec::Vec;
+
   use drivers::lcd::LCD;
+  use drivers::lcd::color::Rgb565;
+  use drivers::lcd::font::TextCursor;
+  use drivers::lcd::font_small_7::SMALL_7;
 
   pub struct TestLCD {
     pixbuf: [[Cell<u16>; 16]; 16],
+    cursor: TextCursor<Rgb565>,
   }
 
   impl CharIO for TestLCD {
-    fn putc(&self, _: char) { }
+    fn putc(&self, value: char) {
+      self.cursor.putc(self, value);
+    }
   }
 
   impl LCD for TestLCD {
+    type Color = Rgb565;
+
     fn flush(&self) { }
 
     fn clear(&self) { self.set_fill(0); }
 
-    fn pixel(&self, x: u32, y: u32, color: u16) {
+    fn pixel(&self, x: u32, y: u32, color: Rgb565) {
       if x >= 16 || y >= 16 {
         return
       }
 
-      self.pixbuf[x as usize][y as usize].set(color);
+      self.pixbuf[x as usize][y as usize].set(color.0);
     }
+
+    fn size(&self) -> (u32, u32) { (16, 16) }
   }
 
   impl TestLCD {
     fn new() -> TestLCD {
       TestLCD {
         pixbuf: unsafe { zeroed() },
+        cursor: TextCursor::new(&SMALL_7, Rgb565(1), Some(Rgb565(0))),
       }
     }
 
@@ -277,9 +659,9 @@ mod test {
       [0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1],
     ];
 
-    io.line(0, 0, 15, 15, 1);
+    io.line(0, 0, 15, 15, Rgb565(1));
 
-    io.line(15, 15, 0, 0, 1);
+    io.line(15, 15, 0, 0, Rgb565(1));
 
     io.for_each(|(x, y), v| {
       assert!(v == diagonal[y as usize][x as usize]);
@@ -307,11 +689,11 @@ mod test {
       [0,0,0,0,0,0,0,0,0,0,0,0,0,0,4,4],
     ];
 
-    io.line(0, 0, 0, 1, 2);
-    io.line(4, 2, 4, 4, 3);
-    io.line(14, 14, 14, 15, 4);
-    io.line(15, 14, 15, 15, 4);
-    io.line(11, 11, 13, 13, 5);
+    io.line(0, 0, 0, 1, Rgb565(2));
+    io.line(4, 2, 4, 4, Rgb565(3));
+    io.line(14, 14, 14, 15, Rgb565(4));
+    io.line(15, 14, 15, 15, Rgb565(4));
+    io.line(11, 11, 13, 13, Rgb565(5));
 
     io.for_each(|(x, y), v| {
       assert!(v == non_symetric[x as usize][y as usize]);
@@ -346,8 +728,8 @@ mod test {
     assert!(overlapping[7][4] == 7);
     assert!(overlapping[10][10] == 7);
 
-    io.rect(4, 2, 12, 7, 6);
-    io.rect(10, 10, 7, 4, 7);
+    io.rect(4, 2, 12, 7, Rgb565(6));
+    io.rect(10, 10, 7, 4, Rgb565(7));
 
     io.for_each(|(x, y), v| {
       assert!(v == overlapping[x as usize][y as usize]);
@@ -377,7 +759,7 @@ mod test {
       [0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0],
     ];
 
-    io.fillrect(4, 4, 11, 11, 8);
+    io.fillrect(4, 4, 11, 11, Rgb565(8));
 
     io.for_each(|(x, y), v| {
       assert!(v == eights[x as usize][y as usize]);
@@ -458,10 +840,121 @@ mod test {
        0xed, 0x10, 0x01, 0xed, 0xad, 0xff, 0xff, 0xff],
     ];
 
-    io.image(16, 16, i1);
+    let i1: Vec<Rgb565> = i1.iter().map(|&c| Rgb565(c)).collect();
+    io.image(16, 16, &i1);
 
     io.for_each(|(y, x), v| {
       assert!(v == i2[x as usize][y as usize]);
     });
   }
+
+  #[test]
+  fn should_draw_text() {
+    let io = TestLCD::new();
+
+    // '!' is two pixels wide, with a line at x = 1.
+    assert!(io.draw_text(0, 0, "!!", &SMALL_7, Rgb565(9), Some(Rgb565(0))) == (4, 0));
+    io.for_each(|(x, y), v| {
+      let lit = (x == 1 || x == 3) && (y >= 1 && y <= 4 || y == 7);
+      assert!(v == if lit { 9 } else { 0 });
+    });
+
+    assert!(io.draw_text(2, 0, "!!!!!!!!", &SMALL_7, Rgb565(9), None) == (4, 9));
+    assert!(io.draw_text(0, 0, "!\n!", &SMALL_7, Rgb565(9), None) == (2, 9));
+  }
+
+  #[test]
+  fn should_wrap_chario_output() {
+    let io = TestLCD::new();
+    io.puts("!!!!!!!!");
+    assert!(io.cursor.position() == (16, 0));
+    // There's only room for one line, so wrapping goes back to the top.
+    io.puts("!");
+    assert!(io.cursor.position() == (2, 0));
+    io.puts("\n");
+    assert!(io.cursor.position() == (0, 0));
+  }
+
+  #[test]
+  fn should_draw_circle() {
+    let io = TestLCD::new();
+    io.circle(7, 7, 3, Rgb565(1));
+    io.for_each(|(x, y), v| {
+      let (dx, dy) = (x as i32 - 7, y as i32 - 7);
+      let d = dx * dx + dy * dy;
+      // Everything drawn is close to the radius, and the extremes are drawn.
+      assert!(v == 0 || (d >= 5 && d <= 12));
+      if (dx == 0 || dy == 0) && d == 9 {
+        assert!(v == 1);
+      }
+    });
+    assert!(io.pixbuf[7][7].get() == 0);
+  }
+
+  #[test]
+  fn should_draw_arc() {
+    let circle = TestLCD::new();
+    circle.circle(7, 7, 5, Rgb565(1));
+
+    let io = TestLCD::new();
+    io.arc(7, 7, 5, 0, 90, Rgb565(1));
+    io.for_each(|(x, y), v| {
+      let (dx, dy) = (x as i32 - 7, 7 - y as i32);
+      // The upper right quarter of the circle, and nothing else.
+      if dx >= 0 && dy >= 0 {
+        assert!(v == circle.pixbuf[x as usize][y as usize].get());
+      } else {
+        assert!(v == 0);
+      }
+    });
+
+    io.clear();
+    io.arc(7, 7, 5, -45, 45, Rgb565(1));
+    io.for_each(|(x, y), v| {
+      let (dx, dy) = (x as i32 - 7, 7 - y as i32);
+      assert!(v == 0 || dx >= dy.abs());
+    });
+    assert!(io.pixbuf[12][7].get() == 1);
+    assert!(io.pixbuf[2][7].get() == 0);
+  }
+
+  #[test]
+  fn should_draw_fill_circle() {
+    let io = TestLCD::new();
+    io.fill_circle(2, 2, 4, Rgb565(1));
+    io.for_each(|(x, y), v| {
+      let (dx, dy) = (x as i32 - 2, y as i32 - 2);
+      assert!(v == if dx * dx + dy * dy <= 4 * 4 + 4 { 1 } else { 0 });
+    });
+  }
+
+  #[test]
+  fn should_draw_fill_triangle() {
+    let io = TestLCD::new();
+    io.fill_triangle(0, 0, 15, 15, 0, 15, Rgb565(1));
+    io.for_each(|(x, y), v| {
+      assert!(v == if x <= y { 1 } else { 0 });
+    });
+  }
+
+  #[test]
+  fn should_blit_with_clipping_and_transparency() {
+    let io = TestLCD::new();
+    io.set_fill(5);
+    let image = [
+      Rgb565(1), Rgb565(2), Rgb565(0),
+      Rgb565(3), Rgb565(0), Rgb565(4),
+    ];
+    io.blit(-1, 14, 3, 2, &image, Some(Rgb565(0)));
+    io.for_each(|(x, y), v| {
+      let expected = match (x, y) {
+        (0, 14) => 2,
+        (1, 14) => 5,
+        (0, 15) => 5,
+        (1, 15) => 4,
+        _ => 5,
+      };
+      assert!(v == expected);
+    });
+  }
 }
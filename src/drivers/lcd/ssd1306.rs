@@ -0,0 +1,382 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2014 Vladimir "farcaller" Pouzanov <farcaller@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/*!
+Driver for 128x64 SSD1306 and SH1106 OLED displays.
+
+The display is buffered in driver memory, organised in 8 pages of 8 pixel rows
+like the controller RAM. `flush` sends only the pages that changed.
+
+The controller is connected either over 4-wire SPI (`SpiInterface`, with `dc`
+and `cs` pins) or I2C (`I2cInterface`). If the module has a reset pin, it must
+be pulsed low before creating the driver.
+
+```ignore
+let oled = Ssd1306::new(I2cInterface::new(&twi::TWI, 0x3c), Controller::Ssd1306);
+oled.set_rotation(Rotation::Deg180);
+oled.puts("Hello");
+oled.flush();
+```
+*/
+
+use core::cell::Cell;
+use core::mem::zeroed;
+
+use super::color::Monochrome;
+use super::font::TextCursor;
+use super::font_small_7;
+use super::LCD;
+use drivers::chario::CharIO;
+use hal::i2c::I2c;
+use hal::pin::Gpio;
+use hal::spi::Spi;
+
+const WIDTH: u32 = 128;
+const HEIGHT: u32 = 64;
+const PAGES: usize = (HEIGHT / 8) as usize;
+
+/// Connection to the display controller.
+pub trait Interface {
+  /// Sends command bytes.
+  fn command(&self, bytes: &[u8]);
+
+  /// Sends display RAM data.
+  fn data(&self, bytes: &[u8]);
+}
+
+/// 4-wire SPI connection.
+pub struct SpiInterface<'a, S: 'a, P: 'a> {
+  spi: &'a S,
+  dc: &'a P,
+  cs: &'a P,
+}
+
+impl<'a, S: Spi, P: Gpio> SpiInterface<'a, S, P> {
+  /// Creates an SPI connection, with `dc` selecting data (high) or commands
+  /// (low) and `cs` as an active low chip select.
+  pub fn new(spi: &'a S, dc: &'a P, cs: &'a P) -> SpiInterface<'a, S, P> {
+    cs.set_high();
+    SpiInterface { spi: spi, dc: dc, cs: cs }
+  }
+
+  fn send(&self, bytes: &[u8]) {
+    self.cs.set_low();
+    for &b in bytes {
+      self.spi.write(b);
+    }
+    self.cs.set_high();
+  }
+}
+
+impl<'a, S: Spi, P: Gpio> Interface for SpiInterface<'a, S, P> {
+  fn command(&self, bytes: &[u8]) {
+    self.dc.set_low();
+    self.send(bytes);
+  }
+
+  fn data(&self, bytes: &[u8]) {
+    self.dc.set_high();
+    self.send(bytes);
+  }
+}
+
+/// I2C connection.
+pub struct I2cInterface<'a, I: 'a> {
+  i2c: &'a I,
+  address: u8,
+}
+
+impl<'a, I: I2c> I2cInterface<'a, I> {
+  /// Creates an I2C connection to the controller at `address`, usually
+  /// `0x3c` or `0x3d`.
+  pub fn new(i2c: &'a I, address: u8) -> I2cInterface<'a, I> {
+    I2cInterface { i2c: i2c, address: address }
+  }
+}
+
+impl<'a, I: I2c> Interface for I2cInterface<'a, I> {
+  fn command(&self, bytes: &[u8]) {
+    self.i2c.write_prefixed(self.address, 0x00, bytes);
+  }
+
+  fn data(&self, bytes: &[u8]) {
+    self.i2c.write_prefixed(self.address, 0x40, bytes);
+  }
+}
+
+/// Display controller model.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Controller {
+  /// SSD1306, with 128 columns of RAM.
+  Ssd1306,
+  /// SH1106, with 132 columns of RAM of which the middle 128 are visible.
+  Sh1106,
+}
+
+/// Display orientation, clockwise.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Rotation {
+  /// Landscape, 128x64.
+  Deg0,
+  /// Portrait, 64x128.
+  Deg90,
+  /// Landscape upside down, 128x64.
+  Deg180,
+  /// Portrait upside down, 64x128.
+  Deg270,
+}
+
+/// SSD1306/SH1106 driver.
+pub struct Ssd1306<I> {
+  interface: I,
+  controller: Controller,
+  rotation: Cell<Rotation>,
+
+  videobuf: [Cell<u8>; 1024],
+  // Bit n is set if page n changed since the last flush.
+  dirty: Cell<u8>,
+
+  cursor: TextCursor<Monochrome>,
+}
+
+impl<I: Interface> Ssd1306<I> {
+  /// Creates a new driver instance and turns the display on.
+  pub fn new(interface: I, controller: Controller) -> Ssd1306<I> {
+    let oled = Ssd1306 {
+      interface: interface,
+      controller: controller,
+      rotation: Cell::new(Rotation::Deg0),
+
+      videobuf: unsafe { zeroed() },
+      dirty: Cell::new(0xff),
+
+      cursor: TextCursor::new(&font_small_7::SMALL_7, Monochrome(true),
+                              Some(Monochrome(false))),
+    };
+
+    oled.configure();
+
+    oled
+  }
+
+  fn configure(&self) {
+    self.interface.command(&[
+      0xae,         // display off
+      0xd5, 0x80,   // clock divider
+      0xa8, 0x3f,   // multiplex ratio, 64 rows
+      0xd3, 0x00,   // display offset
+      0x40,         // start line = 0
+      0xa1,         // segment remap, column 127 is SEG0
+      0xc8,         // COM scan from COM63
+      0xda, 0x12,   // alternative COM pins
+      0x81, 0xcf,   // contrast
+      0xdb, 0x40,   // VCOMH deselect level
+      0xa4,         // display RAM content
+      0xa6,         // display normal
+    ]);
+    match self.controller {
+      Controller::Ssd1306 => self.interface.command(&[
+        0x8d, 0x14, // charge pump on
+        0xd9, 0xf1, // pre-charge period
+      ]),
+      Controller::Sh1106 => self.interface.command(&[
+        0xad, 0x8b, // DC-DC on
+        0xd9, 0x22, // pre-charge period
+      ]),
+    }
+
+    self.clear();
+    LCD::flush(self);
+    self.interface.command(&[0xaf]); // display on
+  }
+
+  /// Sets the contrast, brighter with higher values.
+  pub fn set_contrast(&self, contrast: u8) {
+    self.interface.command(&[0x81, contrast]);
+  }
+
+  /// Turns the display on or off, keeping its contents.
+  pub fn set_display_on(&self, on: bool) {
+    self.interface.command(&[if on { 0xaf } else { 0xae }]);
+  }
+
+  /// Sets the orientation used by further drawing. The buffer isn't redrawn.
+  pub fn set_rotation(&self, rotation: Rotation) {
+    self.rotation.set(rotation);
+  }
+
+  /// Returns the orientation.
+  pub fn rotation(&self) -> Rotation {
+    self.rotation.get()
+  }
+
+  /// Returns the text cursor used for `CharIO` output.
+  pub fn cursor(&self) -> &TextCursor<Monochrome> {
+    &self.cursor
+  }
+
+  /// Maps a rotated position to display RAM coordinates.
+  fn physical(&self, x: u32, y: u32) -> (u32, u32) {
+    match self.rotation.get() {
+      Rotation::Deg0 => (x, y),
+      Rotation::Deg90 => (WIDTH - 1 - y, x),
+      Rotation::Deg180 => (WIDTH - 1 - x, HEIGHT - 1 - y),
+      Rotation::Deg270 => (y, HEIGHT - 1 - x),
+    }
+  }
+}
+
+impl<I: Interface> LCD for Ssd1306<I> {
+  type Color = Monochrome;
+
+  fn flush(&self) {
+    let offset = match self.controller {
+      Controller::Ssd1306 => 0,
+      Controller::Sh1106 => 2,
+    };
+    let mut page_data = [0u8; 128];
+    for page in 0..PAGES {
+      if self.dirty.get() & (1 << page) == 0 {
+        continue;
+      }
+      for (i, byte) in page_data.iter_mut().enumerate() {
+        *byte = self.videobuf[page * 128 + i].get();
+      }
+      self.interface.command(&[
+        0xb0 | page as u8,     // page address
+        offset & 0xf,          // column low nibble
+        0x10 | (offset >> 4),  // column high nibble
+      ]);
+      self.interface.data(&page_data);
+    }
+    self.dirty.set(0);
+  }
+
+  fn clear(&self) {
+    for byte in self.videobuf.iter() {
+      byte.set(0);
+    }
+    self.dirty.set(0xff);
+  }
+
+  fn pixel(&self, x: u32, y: u32, color: Monochrome) {
+    let (width, height) = self.size();
+    if x >= width || y >= height {
+      return
+    }
+
+    let (x, y) = self.physical(x, y);
+    let index = (x + (y / 8) * 128) as usize;
+    let mask = 1u8 << (y % 8);
+    let byte = &self.videobuf[index];
+    if color.0 {
+      byte.set(byte.get() | mask);
+    } else {
+      byte.set(byte.get() & !mask);
+    }
+    self.dirty.set(self.dirty.get() | (1 << (y / 8)));
+  }
+
+  fn size(&self) -> (u32, u32) {
+    match self.rotation.get() {
+      Rotation::Deg0 | Rotation::Deg180 => (WIDTH, HEIGHT),
+      Rotation::Deg90 | Rotation::Deg270 => (HEIGHT, WIDTH),
+    }
+  }
+}
+
+impl<I: Interface> CharIO for Ssd1306<I> {
+  fn putc(&self, value: char) {
+    self.cursor.putc(self, value);
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use std::cell::RefCell;
+  use std::vec::Vec;
+
+  use drivers::lcd::LCD;
+  use drivers::lcd::color::Monochrome;
+  use super::{Ssd1306, Interface, Controller, Rotation};
+
+  enum Sent {
+    Command(Vec<u8>),
+    Data(Vec<u8>),
+  }
+
+  struct Recorder {
+    sent: RefCell<Vec<Sent>>,
+  }
+
+  impl<'a> Interface for &'a Recorder {
+    fn command(&self, bytes: &[u8]) {
+      self.sent.borrow_mut().push(Sent::Command(bytes.to_vec()));
+    }
+
+    fn data(&self, bytes: &[u8]) {
+      self.sent.borrow_mut().push(Sent::Data(bytes.to_vec()));
+    }
+  }
+
+  #[test]
+  fn flush_should_send_changed_pages() {
+    let bus = Recorder { sent: RefCell::new(Vec::new()) };
+    let oled = Ssd1306::new(&bus, Controller::Sh1106);
+    bus.sent.borrow_mut().clear();
+
+    oled.pixel(3, 17, Monochrome(true));
+    oled.flush();
+    {
+      let sent = bus.sent.borrow();
+      assert!(sent.len() == 2);
+      match sent[0] {
+        Sent::Command(ref cmd) => assert!(cmd[..] == [0xb2, 0x02, 0x10]),
+        _ => panic!(),
+      }
+      match sent[1] {
+        Sent::Data(ref data) => {
+          assert!(data.len() == 128);
+          assert!(data[3] == 1 << 1);
+          assert!(data.iter().filter(|&&b| b != 0).count() == 1);
+        },
+        _ => panic!(),
+      }
+    }
+
+    bus.sent.borrow_mut().clear();
+    oled.flush();
+    assert!(bus.sent.borrow().is_empty());
+  }
+
+  #[test]
+  fn rotation_should_map_coordinates() {
+    let bus = Recorder { sent: RefCell::new(Vec::new()) };
+    let oled = Ssd1306::new(&bus, Controller::Ssd1306);
+
+    oled.set_rotation(Rotation::Deg90);
+    assert!(oled.size() == (64, 128));
+    oled.pixel(0, 0, Monochrome(true));
+    assert!(oled.videobuf[127].get() == 1);
+
+    oled.set_rotation(Rotation::Deg270);
+    oled.pixel(0, 0, Monochrome(true));
+    assert!(oled.videobuf[7 * 128].get() == 0x80);
+
+    oled.set_rotation(Rotation::Deg180);
+    oled.pixel(0, 0, Monochrome(true));
+    assert!(oled.videobuf[7 * 128 + 127].get() == 0x80);
+  }
+}
@@ -15,7 +15,8 @@
 
 //! Drivers for peripherals commonly found outside MCUs.
 
+#[macro_use] pub mod chario;
 pub mod lcd;
 pub mod bluenrg;
-pub mod chario;
 pub mod dht22;
+pub mod xpt2046;
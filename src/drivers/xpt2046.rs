@@ -0,0 +1,481 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2014 Vladimir "farcaller" Pouzanov <farcaller@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/*!
+Driver for XPT2046 and ADS7846 resistive touch controllers.
+
+These are found on most ILI9341 panels, usually sharing the SPI bus with the
+display. The controller is limited to a 2MHz SPI clock.
+
+Raw readings are mapped to display pixels with a `Calibration`, computed from
+three touches at known positions:
+
+```ignore
+let touch = Xpt2046::new(&spi, &touch_cs, &pen_irq);
+let targets = Calibration::targets(ili9341.size());
+let mut raw = [(0, 0); 3];
+for i in 0..3 {
+  // Draw a cross at targets[i] and wait for a touch.
+  raw[i] = touch.read_raw_blocking();
+}
+let cal = Calibration::compute(raw, targets).unwrap();
+let bytes = cal.to_bytes(); // store in flash, restore with `from_bytes`
+```
+
+`read_raw()` returns single readings, which flicker while the pen lands or
+lifts. `poll()` and `read()` debounce them: a touch or a release is only
+reported after `set_debounce()` consecutive readings agree.
+*/
+
+use core::cell::Cell;
+use core::slice::SliceExt;
+
+use hal::pin::{Gpio, GpioLevel};
+use hal::spi::Spi;
+
+/// Number of samples taken for each coordinate.
+const SAMPLES: usize = 7;
+/// Maximum spread of the middle samples for a stable reading.
+const MAX_SPREAD: u16 = 40;
+/// Default number of consecutive readings that change the touch state.
+const DEBOUNCE: u8 = 3;
+
+// Control bytes: start bit, 12-bit differential mode, PENIRQ enabled.
+const READ_X: u8 = 0xd0;
+const READ_Y: u8 = 0x90;
+const READ_Z1: u8 = 0xb0;
+const READ_Z2: u8 = 0xc0;
+
+/// A raw touch reading.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct RawTouch {
+  /// X plate reading, 0 to 4095.
+  pub x: u16,
+  /// Y plate reading, 0 to 4095.
+  pub y: u16,
+  /// Touch pressure, higher is harder.
+  pub pressure: u16,
+}
+
+/// XPT2046 driver.
+pub struct Xpt2046<'a, S: 'a, P: 'a> {
+  spi: &'a S,
+  cs: &'a P,
+  irq: &'a P,
+  threshold: Cell<u16>,
+  debounce: Cell<u8>,
+  /// Readings in a row that disagree with the debounced state.
+  streak: Cell<u8>,
+  /// Debounced state, the last accepted reading while touched.
+  touch: Cell<Option<RawTouch>>,
+}
+
+impl<'a, S: Spi, P: Gpio> Xpt2046<'a, S, P> {
+  /// Creates a new driver with an active low chip select `cs` and the pen
+  /// interrupt input `irq`, which is low while the screen is touched.
+  pub fn new(spi: &'a S, cs: &'a P, irq: &'a P) -> Xpt2046<'a, S, P> {
+    cs.set_high();
+    Xpt2046 {
+      spi: spi,
+      cs: cs,
+      irq: irq,
+      threshold: Cell::new(400),
+      debounce: Cell::new(DEBOUNCE),
+      streak: Cell::new(0),
+      touch: Cell::new(None),
+    }
+  }
+
+  /// Sets the minimum pressure that counts as a touch.
+  pub fn set_pressure_threshold(&self, threshold: u16) {
+    self.threshold.set(threshold);
+  }
+
+  /// Sets the number of consecutive readings needed to report a touch or a
+  /// release, 1 disables debouncing.
+  pub fn set_debounce(&self, readings: u8) {
+    self.debounce.set(readings);
+  }
+
+  /// Returns true if the pen interrupt signals a touch.
+  pub fn is_touched(&self) -> bool {
+    self.irq.level() == GpioLevel::Low
+  }
+
+  /// Returns a filtered reading, or `None` if the screen isn't touched firmly
+  /// or the readings were unstable (e.g. the pen was moving or lifting).
+  ///
+  /// Single readings aren't debounced, see `poll()`.
+  pub fn read_raw(&self) -> Option<RawTouch> {
+    if !self.is_touched() {
+      return None;
+    }
+
+    self.cs.set_low();
+    let z1 = self.measure(READ_Z1) as i32;
+    let z2 = self.measure(READ_Z2) as i32;
+    let pressure = (z1 + 4095 - z2) as u16;
+    let xy = if pressure >= self.threshold.get() {
+      match (self.sample(READ_X), self.sample(READ_Y)) {
+        (Some(x), Some(y)) => Some((x, y)),
+        _ => None,
+      }
+    } else {
+      None
+    };
+    self.cs.set_high();
+
+    // Drop readings where the pen lifted while sampling.
+    match xy {
+      Some((x, y)) if self.is_touched() => {
+        Some(RawTouch { x: x, y: y, pressure: pressure })
+      },
+      _ => None,
+    }
+  }
+
+  /// Takes a reading and returns the debounced touch.
+  ///
+  /// A touch is reported once `set_debounce()` readings in a row were
+  /// accepted, and lasts until as many in a row were rejected. In between,
+  /// the last accepted reading is returned.
+  pub fn poll(&self) -> Option<RawTouch> {
+    let reading = self.read_raw();
+    let touched = self.touch.get().is_some();
+    if reading.is_some() == touched {
+      self.streak.set(0);
+      if touched {
+        self.touch.set(reading);
+      }
+    } else {
+      let streak = self.streak.get() + 1;
+      if streak >= self.debounce.get() {
+        self.streak.set(0);
+        self.touch.set(reading);
+      } else {
+        self.streak.set(streak);
+      }
+    }
+    self.touch.get()
+  }
+
+  /// Waits for a stable touch and returns its raw position.
+  pub fn read_raw_blocking(&self) -> (u16, u16) {
+    loop {
+      if let Some(touch) = self.poll() {
+        return (touch.x, touch.y);
+      }
+    }
+  }
+
+  /// Returns the debounced touch position in display pixels, clamped to a
+  /// display of `size`, or `None` if it isn't touched.
+  pub fn read(&self, cal: &Calibration, size: (u32, u32)) -> Option<(u32, u32)> {
+    self.poll().map(|touch| {
+      let (x, y) = cal.map(touch.x, touch.y);
+      (clamp(x, size.0), clamp(y, size.1))
+    })
+  }
+
+  /// Averages the middle of several readings, if they're close enough.
+  fn sample(&self, control: u8) -> Option<u16> {
+    let mut samples = [0u16; SAMPLES];
+    // Insertion sort, there's no sort in core.
+    for i in 0..SAMPLES {
+      let value = self.measure(control);
+      let mut j = i;
+      while j > 0 && samples[j - 1] > value {
+        samples[j] = samples[j - 1];
+        j -= 1;
+      }
+      samples[j] = value;
+    }
+
+    let middle = &samples[SAMPLES / 4..SAMPLES - SAMPLES / 4];
+    if middle[middle.len() - 1] - middle[0] > MAX_SPREAD {
+      return None;
+    }
+    let sum = middle.iter().fold(0u32, |sum, &s| sum + s as u32);
+    Some((sum / middle.len() as u32) as u16)
+  }
+
+  /// Runs one 12-bit conversion.
+  fn measure(&self, control: u8) -> u16 {
+    self.spi.transfer(control);
+    let high = self.spi.transfer(0) as u16;
+    let low = self.spi.transfer(0) as u16;
+    ((high << 8) | low) >> 3
+  }
+}
+
+fn clamp(value: i32, limit: u32) -> u32 {
+  if value < 0 {
+    0
+  } else if value as u32 >= limit {
+    limit - 1
+  } else {
+    value as u32
+  }
+}
+
+/// Affine transform from raw readings to display pixels.
+///
+/// Pixels are `x = (a * raw_x + b * raw_y + c) / div` and
+/// `y = (d * raw_x + e * raw_y + f) / div`, which handles scaling, offset,
+/// rotation and swapped axes.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Calibration {
+  a: i64,
+  b: i64,
+  c: i64,
+  d: i64,
+  e: i64,
+  f: i64,
+  div: i64,
+}
+
+/// Marks serialised calibration data.
+const MAGIC: [u8; 4] = *b"XPTc";
+
+/// Size of serialised calibration data.
+pub const CALIBRATION_BYTES: usize = 4 + 7 * 8;
+
+impl Calibration {
+  /// Returns three well spread touch targets for a display of `size`.
+  pub fn targets(size: (u32, u32)) -> [(i32, i32); 3] {
+    let (w, h) = (size.0 as i32, size.1 as i32);
+    [(w / 10, h / 10), (w / 2, h * 9 / 10), (w * 9 / 10, h / 2)]
+  }
+
+  /// Computes the transform from raw readings at three display positions.
+  /// Returns `None` if the points are on one line.
+  pub fn compute(raw: [(u16, u16); 3], screen: [(i32, i32); 3]) -> Option<Calibration> {
+    let (x0, y0) = (raw[0].0 as i64, raw[0].1 as i64);
+    let (x1, y1) = (raw[1].0 as i64, raw[1].1 as i64);
+    let (x2, y2) = (raw[2].0 as i64, raw[2].1 as i64);
+    let (sx0, sy0) = (screen[0].0 as i64, screen[0].1 as i64);
+    let (sx1, sy1) = (screen[1].0 as i64, screen[1].1 as i64);
+    let (sx2, sy2) = (screen[2].0 as i64, screen[2].1 as i64);
+
+    let div = (x0 - x2) * (y1 - y2) - (x1 - x2) * (y0 - y2);
+    if div == 0 {
+      return None;
+    }
+    Some(Calibration {
+      a: (sx0 - sx2) * (y1 - y2) - (sx1 - sx2) * (y0 - y2),
+      b: (x0 - x2) * (sx1 - sx2) - (sx0 - sx2) * (x1 - x2),
+      c: y0 * (x2 * sx1 - x1 * sx2) + y1 * (x0 * sx2 - x2 * sx0) +
+         y2 * (x1 * sx0 - x0 * sx1),
+      d: (sy0 - sy2) * (y1 - y2) - (sy1 - sy2) * (y0 - y2),
+      e: (x0 - x2) * (sy1 - sy2) - (sy0 - sy2) * (x1 - x2),
+      f: y0 * (x2 * sy1 - x1 * sy2) + y1 * (x0 * sy2 - x2 * sy0) +
+         y2 * (x1 * sy0 - x0 * sy1),
+      div: div,
+    })
+  }
+
+  /// Maps a raw reading to display pixels.
+  pub fn map(&self, x: u16, y: u16) -> (i32, i32) {
+    let (x, y) = (x as i64, y as i64);
+    (((self.a * x + self.b * y + self.c) / self.div) as i32,
+     ((self.d * x + self.e * y + self.f) / self.div) as i32)
+  }
+
+  /// Serialises the calibration, e.g. to store it in flash.
+  pub fn to_bytes(&self) -> [u8; CALIBRATION_BYTES] {
+    let mut bytes = [0u8; CALIBRATION_BYTES];
+    bytes[..4].copy_from_slice(&MAGIC);
+    let values = [self.a, self.b, self.c, self.d, self.e, self.f, self.div];
+    for (i, &v) in values.iter().enumerate() {
+      for j in 0..8 {
+        bytes[4 + i * 8 + j] = (v >> (j * 8)) as u8;
+      }
+    }
+    bytes
+  }
+
+  /// Restores a calibration from `to_bytes` data. Returns `None` if `bytes`
+  /// doesn't hold a calibration, e.g. it's erased flash.
+  pub fn from_bytes(bytes: &[u8]) -> Option<Calibration> {
+    if bytes.len() < CALIBRATION_BYTES || bytes[..4] != MAGIC {
+      return None;
+    }
+    let mut values = [0i64; 7];
+    for (i, v) in values.iter_mut().enumerate() {
+      for j in 0..8 {
+        *v |= (bytes[4 + i * 8 + j] as i64) << (j * 8);
+      }
+    }
+    if values[6] == 0 {
+      return None;
+    }
+    Some(Calibration {
+      a: values[0], b: values[1], c: values[2],
+      d: values[3], e: values[4], f: values[5],
+      div: values[6],
+    })
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use core::cell::Cell;
+
+  use hal::pin::{Gpio, GpioLevel, GpioDirection};
+  use hal::spi::Spi;
+  use super::{Xpt2046, Calibration, RawTouch};
+
+  struct Pin(Cell<GpioLevel>);
+
+  impl Gpio for Pin {
+    fn set_high(&self) { self.0.set(GpioLevel::High); }
+    fn set_low(&self) { self.0.set(GpioLevel::Low); }
+    fn level(&self) -> GpioLevel { self.0.get() }
+    fn set_direction(&self, _: GpioDirection) {}
+  }
+
+  /// Answers conversions with fixed values plus a repeating noise pattern.
+  struct Panel {
+    values: [u16; 4],
+    noise: &'static [u16],
+    control: Cell<u8>,
+    step: Cell<usize>,
+    count: Cell<usize>,
+  }
+
+  impl Spi for Panel {
+    fn write(&self, value: u8) {
+      if value & 0x80 != 0 {
+        self.control.set(value);
+        self.step.set(0);
+      } else {
+        self.step.set(self.step.get() + 1);
+      }
+    }
+
+    fn read(&self) -> u8 {
+      let channel = match self.control.get() {
+        0xd0 => 0,
+        0x90 => 1,
+        0xb0 => 2,
+        _ => 3,
+      };
+      let noise = self.noise[self.count.get() % self.noise.len()];
+      let value = (self.values[channel] + noise) << 3;
+      match self.step.get() {
+        1 => (value >> 8) as u8,
+        2 => {
+          self.count.set(self.count.get() + 1);
+          value as u8
+        },
+        _ => 0,
+      }
+    }
+  }
+
+  fn panel(noise: &'static [u16]) -> Panel {
+    Panel {
+      values: [2000, 1000, 900, 3000],
+      noise: noise,
+      control: Cell::new(0),
+      step: Cell::new(0),
+      count: Cell::new(0),
+    }
+  }
+
+  #[test]
+  fn should_average_stable_readings() {
+    static NOISE: [u16; 7] = [0, 3, 100, 1, 2, 0, 4];
+    let spi = panel(&NOISE);
+    let (cs, irq) = (Pin(Cell::new(GpioLevel::High)), Pin(Cell::new(GpioLevel::Low)));
+    let touch = Xpt2046::new(&spi, &cs, &irq);
+
+    let reading = touch.read_raw().unwrap();
+    assert!(reading.pressure == 900 + 4095 - 3003 || reading.pressure == 900 + 4095 - 3000);
+    assert!(reading.x >= 2000 && reading.x <= 2004);
+    assert!(reading.y >= 1000 && reading.y <= 1004);
+    assert!(cs.level() == GpioLevel::High);
+  }
+
+  #[test]
+  fn should_reject_unstable_or_light_touches() {
+    static NOISY: [u16; 3] = [0, 200, 400];
+    let spi = panel(&NOISY);
+    let (cs, irq) = (Pin(Cell::new(GpioLevel::High)), Pin(Cell::new(GpioLevel::Low)));
+    let touch = Xpt2046::new(&spi, &cs, &irq);
+    assert!(touch.read_raw() == None);
+
+    static QUIET: [u16; 1] = [0];
+    let spi = panel(&QUIET);
+    let touch = Xpt2046::new(&spi, &cs, &irq);
+    assert!(touch.read_raw() == Some(RawTouch { x: 2000, y: 1000, pressure: 1995 }));
+    touch.set_pressure_threshold(2000);
+    assert!(touch.read_raw() == None);
+
+    irq.set_high();
+    touch.set_pressure_threshold(0);
+    assert!(touch.read_raw() == None);
+  }
+
+  #[test]
+  fn should_debounce_touch_and_release() {
+    static QUIET: [u16; 1] = [0];
+    let spi = panel(&QUIET);
+    let (cs, irq) = (Pin(Cell::new(GpioLevel::High)), Pin(Cell::new(GpioLevel::Low)));
+    let touch = Xpt2046::new(&spi, &cs, &irq);
+    let pressed = Some(RawTouch { x: 2000, y: 1000, pressure: 1995 });
+
+    // A bounce restarts the count.
+    assert!(touch.poll() == None);
+    assert!(touch.poll() == None);
+    irq.set_high();
+    assert!(touch.poll() == None);
+    irq.set_low();
+    assert!(touch.poll() == None);
+    assert!(touch.poll() == None);
+    assert!(touch.poll() == pressed);
+
+    // So does a single dropped reading while pressed.
+    irq.set_high();
+    assert!(touch.poll() == pressed);
+    irq.set_low();
+    assert!(touch.poll() == pressed);
+    irq.set_high();
+    assert!(touch.poll() == pressed);
+    assert!(touch.poll() == pressed);
+    assert!(touch.poll() == None);
+
+    touch.set_debounce(1);
+    irq.set_low();
+    assert!(touch.poll() == pressed);
+  }
+
+  #[test]
+  fn calibration_should_map_targets() {
+    // Axes swapped and X inverted, as on a rotated panel.
+    let raw = [(3700, 400), (400, 2000), (2000, 3600)];
+    let targets = Calibration::targets((240, 320));
+    let cal = Calibration::compute(raw, targets).unwrap();
+    for i in 0..3 {
+      let (x, y) = cal.map(raw[i].0, raw[i].1);
+      assert!((x - targets[i].0).abs() <= 1 && (y - targets[i].1).abs() <= 1);
+    }
+
+    let restored = Calibration::from_bytes(&cal.to_bytes()).unwrap();
+    assert!(restored == cal);
+    assert!(Calibration::from_bytes(&[0xff; 60]) == None);
+    assert!(Calibration::compute([(0, 0), (1, 1), (2, 2)], targets) == None);
+  }
+}
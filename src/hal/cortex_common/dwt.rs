@@ -0,0 +1,71 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2014 Vladimir "farcaller" Pouzanov <farcaller@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Interface to Data Watchpoint and Trace unit cycle counter.
+//!
+//! DWT memory location is 0xE000_1000, DEMCR is at 0xE000_EDFC. The cycle
+//! counter is only available on ARMv7-M cores.
+//  Link: http://infocenter.arm.com/help/topic/com.arm.doc.ddi0337e/BABJFFGJ.html
+
+#[inline(always)]
+fn get_reg() -> &'static reg::DWT {
+  unsafe { &*(0xE000_1000 as *mut reg::DWT) }
+}
+
+#[inline(always)]
+fn get_dcb() -> &'static reg::DCB {
+  unsafe { &*(0xE000_EDF0 as *mut reg::DCB) }
+}
+
+/// Enables trace and starts the cycle counter.
+///
+/// Returns false if the core doesn't implement the cycle counter.
+pub fn enable_cycle_counter() -> bool {
+  get_dcb().demcr.set_trcena(true);
+  if get_reg().ctrl.nocyccnt() {
+    return false;
+  }
+  get_reg().cyccnt.set_count(0);
+  get_reg().ctrl.set_cyccntena(true);
+  true
+}
+
+/// Returns the current value of the cycle counter.
+#[inline(always)]
+pub fn cycle_count() -> u32 {
+  get_reg().cyccnt.count()
+}
+
+#[allow(dead_code)]
+mod reg {
+  use volatile_cell::VolatileCell;
+  use core::ops::Drop;
+
+  ioregs!(DWT = {
+    0x0       => reg32 ctrl {      //! Control register
+      0       => cyccntena,
+      25      => nocyccnt: ro,
+    }
+    0x4       => reg32 cyccnt {    //! Cycle count register
+      0..31   => count,
+    }
+  });
+
+  ioregs!(DCB = {
+    0xc       => reg32 demcr {     //! Debug exception and monitor control register
+      24      => trcena,
+    }
+  });
+}
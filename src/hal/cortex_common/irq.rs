@@ -77,3 +77,61 @@ pub unsafe fn enable_irqs() {
 
 // #[cfg(not(target_os = "none"))]
 // unsafe fn enable_irqs() { unimplemented!() }
+
+/// Returns the current BASEPRI value.
+///
+/// Not available on ARMv6-M (Cortex-M0), which has no BASEPRI register.
+#[cfg(all(target_os = "none", not(feature = "cpu_cortex-m0")))]
+#[inline(always)]
+pub fn get_basepri() -> u8 {
+  let basepri: u32;
+  unsafe {
+    asm!("mrs $0, BASEPRI" : "=r"(basepri) ::: "volatile");
+  }
+  basepri as u8
+}
+
+/// Sets BASEPRI, masking all interrupts with a priority value greater than or
+/// equal to `priority`. Zero unmasks everything.
+#[cfg(all(target_os = "none", not(feature = "cpu_cortex-m0")))]
+#[inline(always)]
+pub unsafe fn set_basepri(priority: u8) {
+  write_basepri(priority, false);
+}
+
+/// Raises BASEPRI to `priority`. Does nothing if `priority` is zero or if
+/// interrupts of that priority are already masked.
+#[cfg(all(target_os = "none", not(feature = "cpu_cortex-m0")))]
+#[inline(always)]
+pub unsafe fn set_basepri_max(priority: u8) {
+  write_basepri(priority, true);
+}
+
+#[cfg(all(target_os = "none", not(feature = "cpu_cortex-m0"),
+          not(feature = "cpu_cortex-m7")))]
+#[inline(always)]
+unsafe fn write_basepri(priority: u8, max: bool) {
+  if max {
+    asm!("msr BASEPRI_MAX, $0" :: "r"(priority as u32) : "memory" : "volatile");
+  } else {
+    asm!("msr BASEPRI, $0" :: "r"(priority as u32) : "memory" : "volatile");
+  }
+}
+
+// Cortex-M7 r0p1 erratum 837070: an interrupt masked by a BASEPRI write can
+// still be taken right after it, so the write must be done with PRIMASK set.
+// The previous PRIMASK is restored, not cleared, to keep outer critical
+// sections intact.
+#[cfg(all(target_os = "none", feature = "cpu_cortex-m7"))]
+#[inline(always)]
+unsafe fn write_basepri(priority: u8, max: bool) {
+  let primask: u32;
+  asm!("mrs $0, PRIMASK\n\
+        cpsid i" : "=r"(primask) ::: "volatile");
+  if max {
+    asm!("msr BASEPRI_MAX, $0" :: "r"(priority as u32) : "memory" : "volatile");
+  } else {
+    asm!("msr BASEPRI, $0" :: "r"(priority as u32) : "memory" : "volatile");
+  }
+  asm!("msr PRIMASK, $0" :: "r"(primask) : "memory" : "volatile");
+}
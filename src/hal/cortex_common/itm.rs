@@ -0,0 +1,89 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2014 Vladimir "farcaller" Pouzanov <farcaller@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Interface to Instrumentation Trace Macrocell stimulus ports.
+//!
+//! ITM memory location is 0xE000_0000. Bytes written to a stimulus port are
+//! sent to the debugger over SWO, which the debugger sets up along with the
+//! TPIU. The ITM is only available on ARMv7-M cores.
+//  Link: http://infocenter.arm.com/help/topic/com.arm.doc.ddi0337e/BIIFBHIF.html
+
+use core::intrinsics::{volatile_load, volatile_store};
+
+use drivers::chario::CharIO;
+
+const ITM_BASE: u32 = 0xE000_0000;
+
+#[inline(always)]
+fn get_reg() -> &'static reg::ITM {
+  unsafe { &*(ITM_BASE as *mut reg::ITM) }
+}
+
+/// An ITM stimulus port, usable as a `CharIO` debugging output backend:
+///
+/// ```ignore
+/// static ITM: itm::Stimulus = itm::Stimulus::new(0);
+///
+/// os::debug::add_backend(&ITM);
+/// ```
+pub struct Stimulus {
+  port: u8,
+}
+
+impl Stimulus {
+  /// Creates a handle to a stimulus port, 0 to 31.
+  pub const fn new(port: u8) -> Stimulus {
+    Stimulus { port: port }
+  }
+
+  /// Returns true if the ITM and the port were enabled by the debugger.
+  pub fn enabled(&self) -> bool {
+    get_reg().tcr.itmena() && get_reg().ter.ena(self.port as usize)
+  }
+
+  fn addr(&self) -> u32 {
+    ITM_BASE + 4 * self.port as u32
+  }
+}
+
+impl CharIO for Stimulus {
+  /// Writes a byte to the port, waiting for the FIFO to have room. Output is
+  /// discarded while no debugger has enabled the port, so that it never
+  /// blocks.
+  fn putc(&self, value: char) {
+    if !self.enabled() {
+      return;
+    }
+    unsafe {
+      while volatile_load(self.addr() as *const u32) & 1 == 0 {}
+      volatile_store(self.addr() as *mut u8, value as u8);
+    }
+  }
+}
+
+#[allow(dead_code)]
+mod reg {
+  use volatile_cell::VolatileCell;
+  use core::ops::Drop;
+
+  ioregs!(ITM = {
+    0xe00     => reg32 ter {       //! Trace enable register
+      0..31   => ena[32],
+    }
+    0xe80     => reg32 tcr {       //! Trace control register
+      0       => itmena,
+    }
+  });
+}
@@ -22,3 +22,6 @@ pub mod mpu;
 pub mod nvic;
 pub mod scb;
 pub mod irq;
+pub mod dwt;
+pub mod itm;
+#[cfg(feature = "multitasking")] pub mod sched;
@@ -39,6 +39,25 @@ pub fn set_pendsv(val: bool) {
   }
 }
 
+/// Exception number of PendSV.
+pub const PENDSV: usize = 14;
+
+/// Sets the priority of a system handler (exception number 4 to 15).
+pub fn set_system_priority(exception: usize, prio: u8) {
+  get_reg().shpr[exception / 4 - 1].set_pri(exception % 4, prio as u32);
+}
+
+/// Returns true if the SysTick exception is pending.
+pub fn systick_pending() -> bool {
+  get_reg().icsr.pendstset()
+}
+
+/// Selects deep sleep (as opposed to sleep) as the low power mode entered by
+/// `wfi`.
+pub fn set_sleepdeep(val: bool) {
+  get_reg().scr.set_sleepdeep(val);
+}
+
 mod reg {
   use volatile_cell::VolatileCell;
   use core::ops::Drop;
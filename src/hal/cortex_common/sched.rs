@@ -13,11 +13,13 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-//! Cortex-M3 specific support code for scheduler.
-
-use core::ops::Drop;
-use core::intrinsics::abort;
+//! Cortex-M specific support code for scheduler.
+//!
+//! This is shared between ARMv6-M (Cortex-M0) and ARMv7-M (Cortex-M3, M4 and
+//! M7) cores. The context switch itself lives in `sched.S`, which saves the
+//! same frame layout on both architectures.
 
+use os::syscall;
 use os::task::Task;
 use super::scb;
 
@@ -49,6 +51,31 @@ pub fn get_task_stack_pointer() -> u32 {
 #[cfg(not(target_arch = "arm"))]
 pub fn get_task_stack_pointer() -> u32 { unimplemented!() }
 
+/// Sets the privilege level of thread mode (CONTROL.nPRIV).
+///
+/// Only the privilege bit is changed, the active stack pointer selection is
+/// preserved. ARMv6-M has no unprivileged thread mode, so tasks always run
+/// privileged there.
+#[cfg(all(target_arch = "arm", not(feature = "cpu_cortex-m0")))]
+#[inline(always)]
+pub fn set_thread_privileged(privileged: bool) {
+  let mut control: u32;
+  unsafe { asm!("mrs $0, control" : "=r"(control) ::: "volatile") };
+  if privileged {
+    control &= !1;
+  } else {
+    control |= 1;
+  }
+  unsafe { asm!("msr control, $0\n\tisb" :: "r"(control) :: "volatile") };
+}
+
+#[cfg(all(target_arch = "arm", feature = "cpu_cortex-m0"))]
+#[inline(always)]
+pub fn set_thread_privileged(_: bool) {}
+
+#[cfg(not(target_arch = "arm"))]
+pub fn set_thread_privileged(_: bool) { unimplemented!() }
+
 /// Returns current stack pointer (SP, which may be PSP or MSP).
 #[cfg(target_arch = "arm")]
 #[inline(always)]
@@ -62,6 +89,8 @@ pub fn get_current_stack_pointer() -> u32 {
 pub fn get_current_stack_pointer() -> u32 { unimplemented!() }
 
 /// State, that's saved by hardware upon entering an ISR.
+///
+/// The layout is identical on ARMv6-M and ARMv7-M (without FPU context).
 pub struct SavedState {
   pub r0: u32,
   pub r1: u32,
@@ -89,14 +118,9 @@ impl SavedState {
   }
 }
 
-// TODO(farcaller): this should actually kill the task.
 // TODO(bgamari): It should also unlock anything the task holds
-/// Default handler for task that tries to return.
-#[cfg(target_os = "none")]
-unsafe fn task_finished() {
-  core::intrinsics::breakpoint();
+/// Return address of a task function, exits the task with code 0.
+fn task_finished() {
+  syscall::exit(0);
 }
 
-#[cfg(not(target_os = "none"))]
-unsafe fn task_finished() { unimplemented!() }
-
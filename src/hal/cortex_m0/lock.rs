@@ -13,37 +13,48 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use core::ty::Unsafe;
+//! Locks for ARMv6-M.
+//!
+//! ARMv6-M has no exclusive access instructions (LDREX/STREX), so the lock
+//! state is updated with interrupts disabled instead.
+
+use core::cell::UnsafeCell;
 use core::option::Option::{self, Some, None};
 use core::ops::Drop;
-use core::kinds::Share;
+use core::marker::Sync;
+
+use super::irq::NoInterrupts;
 
 /// A lock. Note that this disables interrupts. Consequently, a task
 /// dying (e.g. by running out of stack space) while holding a lock
 /// may cause a deadlock.
 pub struct Lock {
-  locked: Unsafe<bool>
+  locked: UnsafeCell<bool>
 }
 
+/// A held lock, released on drop.
 #[must_use]
 pub struct Guard<'a>(&'a Lock);
 
-pub static STATIC_LOCK: Lock = Lock { locked: Unsafe { value: false, marker1: InvariantType } };
+/// Static initializer.
+pub static STATIC_LOCK: Lock = Lock { locked: UnsafeCell::new(false) };
 
 impl Lock {
+  /// Create a new lock.
   pub fn new() -> Lock {
-    Lock { locked: Unsafe::new(false) }
+    Lock { locked: UnsafeCell::new(false) }
   }
 
+  /// Try to take the lock, returning `None` if it's already held.
   pub fn try_lock<'a>(&'a self) -> Option<Guard<'a>> {
     unsafe {
-      let crit = NoInterrupts::new();
+      let _crit = NoInterrupts::new();
       let locked = self.locked.get();
       match *locked {
-        true  => return None,
+        true  => None,
         false => {
           *locked = true;
-          return Some(Guard(self));
+          Some(Guard(self))
         }
       }
     }
@@ -51,13 +62,12 @@ impl Lock {
 
   fn unlock<'a>(&'a self) {
     unsafe {
-      let crit = NoInterrupts::new();
+      let _crit = NoInterrupts::new();
       *self.locked.get() = false;
     }
   }
 }
 
-#[unsafe_destructor]
 impl<'a> Drop for Guard<'a> {
   fn drop(&mut self) {
     let &Guard(ref lock) = self;
@@ -65,4 +75,4 @@ impl<'a> Drop for Guard<'a> {
   }
 }
 
-impl Share for Lock { }
+unsafe impl Sync for Lock { }
@@ -24,4 +24,5 @@ pub use super::cortex_common::systick;
 pub use super::cortex_common::scb;
 pub use super::cortex_common::nvic;
 pub use super::cortex_common::irq;
-//pub mod lock;
+#[cfg(feature = "multitasking")] pub use super::cortex_common::sched;
+#[cfg(feature = "multitasking")] pub mod lock;
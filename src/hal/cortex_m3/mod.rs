@@ -25,5 +25,7 @@ pub use super::cortex_common::scb;
 pub use super::cortex_common::nvic;
 pub use super::cortex_common::mpu;
 pub use super::cortex_common::irq;
-#[cfg(feature = "multitasking")] pub mod sched;
+pub use super::cortex_common::dwt;
+pub use super::cortex_common::itm;
+#[cfg(feature = "multitasking")] pub use super::cortex_common::sched;
 #[cfg(feature = "multitasking")] pub mod lock;
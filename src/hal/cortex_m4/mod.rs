@@ -25,3 +25,6 @@ pub use super::cortex_common::scb;
 pub use super::cortex_common::nvic;
 pub use super::cortex_common::mpu;
 pub use super::cortex_common::irq;
+pub use super::cortex_common::dwt;
+pub use super::cortex_common::itm;
+#[cfg(feature = "multitasking")] pub use super::cortex_common::sched;
@@ -0,0 +1,30 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2014 Vladimir "farcaller" Pouzanov <farcaller@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/*!
+I2C interface.
+
+Addresses are 7-bit, without the read/write bit.
+*/
+
+/// I2C master trait.
+pub trait I2c {
+  /// Writes `bytes` to the device at `address` in one transaction.
+  fn write(&self, address: u8, bytes: &[u8]);
+
+  /// Writes `prefix` followed by `bytes` to the device at `address` in one
+  /// transaction, e.g. a register address and the register data.
+  fn write_prefixed(&self, address: u8, prefix: u8, bytes: &[u8]);
+}
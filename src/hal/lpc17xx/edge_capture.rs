@@ -0,0 +1,147 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2014 Vladimir "farcaller" Pouzanov <farcaller@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/*!
+Falling edge capture with GPIO interrupts.
+
+`GpioEdgeCapture` records the counter of a timer on every falling edge of a
+port 0 or port 2 pin, as needed by `drivers::dht22::DHT22::read_captured`.
+The pin stays a GPIO, so the driver can still drive it. All the GPIO
+interrupts share EINT3, which the application forwards to the capture:
+
+```ignore
+static CAPTURE: GpioEdgeCapture = GpioEdgeCapture::new(Port0, 4, Timer1);
+
+#[no_mangle]
+pub unsafe extern fn isr_eint_3() {
+  CAPTURE.on_interrupt();
+}
+```
+*/
+
+use core::cell::UnsafeCell;
+use core::cmp;
+use core::intrinsics::abort;
+use core::marker::Sync;
+
+use drivers::dht22::EdgeCapture;
+use hal::cortex_m3::nvic;
+use hal::irq::NoInterrupts;
+use super::pin::Port;
+use super::timer::{self, TimerPeripheral};
+
+#[path="../../util/ioreg.rs"]
+#[macro_use] mod ioreg;
+
+/// Maximum number of recorded edges, enough for a DHT22 read.
+pub const MaxEdges: usize = 48;
+
+/// GPIO interrupts are reported through EINT3.
+const EINT3_IRQ: usize = 21;
+
+/// Falling edge recorder for a GPIO pin.
+pub struct GpioEdgeCapture {
+  port: Port,
+  pin: u8,
+  timer: TimerPeripheral,
+  edges: UnsafeCell<[u32; MaxEdges]>,
+  count: UnsafeCell<usize>,
+}
+
+impl GpioEdgeCapture {
+  /// Creates a capture of `pin` on `port`, which must be `Port0` or `Port2`.
+  /// Edge times are read from `timer`, which must be started with the same
+  /// resolution as the timer used by the driver.
+  pub const fn new(port: Port, pin: u8, timer: TimerPeripheral) -> GpioEdgeCapture {
+    GpioEdgeCapture {
+      port: port,
+      pin: pin,
+      timer: timer,
+      edges: UnsafeCell::new([0; MaxEdges]),
+      count: UnsafeCell::new(0),
+    }
+  }
+
+  /// Records a falling edge of the pin, call this from the EINT3 interrupt
+  /// handler. Edges beyond `MaxEdges` are dropped.
+  pub fn on_interrupt(&self) {
+    let now = timer::counter(self.timer);
+    let reg = self.reg();
+    let bit = 1u32 << self.pin;
+    if reg.StatF() & bit == 0 {
+      return;
+    }
+    reg.set_Clr(bit);
+    unsafe {
+      let count = *self.count.get();
+      if count < MaxEdges {
+        (*self.edges.get())[count] = now;
+        *self.count.get() = count + 1;
+      }
+    }
+  }
+
+  fn reg(&self) -> &'static reg::GPIOINT {
+    match self.port {
+      Port::Port0 => &reg::GPIOINT0,
+      Port::Port2 => &reg::GPIOINT2,
+      // Only ports 0 and 2 have GPIO interrupts.
+      _ => unsafe { abort() },
+    }
+  }
+}
+
+impl EdgeCapture for GpioEdgeCapture {
+  fn start(&self) {
+    let reg = self.reg();
+    let bit = 1u32 << self.pin;
+    {
+      let _crit = NoInterrupts::new();
+      unsafe { *self.count.get() = 0 };
+      reg.set_Clr(bit);
+      reg.set_EnF(reg.EnF() | bit);
+    }
+    nvic::enable_irq(EINT3_IRQ);
+  }
+
+  fn stop(&self, edges: &mut [u32]) -> usize {
+    let _crit = NoInterrupts::new();
+    let reg = self.reg();
+    reg.set_EnF(reg.EnF() & !(1u32 << self.pin));
+    unsafe {
+      let count = cmp::min(*self.count.get(), edges.len());
+      edges[..count].copy_from_slice(&(*self.edges.get())[..count]);
+      count
+    }
+  }
+}
+
+unsafe impl Sync for GpioEdgeCapture {}
+
+mod reg {
+  use volatile_cell::VolatileCell;
+
+  ioreg_old!(GPIOINT: u32, StatR, StatF, Clr, EnR, EnF);
+  reg_r!(GPIOINT, u32, StatR, StatR);
+  reg_r!(GPIOINT, u32, StatF, StatF);
+  reg_w!(GPIOINT, u32, set_Clr, Clr);
+  reg_rw!(GPIOINT, u32, EnR, set_EnR, EnR);
+  reg_rw!(GPIOINT, u32, EnF, set_EnF, EnF);
+
+  extern {
+    #[link_name="lpc17xx_iomem_GPIOINT0"] pub static GPIOINT0: GPIOINT;
+    #[link_name="lpc17xx_iomem_GPIOINT2"] pub static GPIOINT2: GPIOINT;
+  }
+}
@@ -17,6 +17,7 @@
 
 pub mod system_clock;
 pub mod peripheral_clock;
+pub mod edge_capture;
 pub mod pin;
 pub mod pwm;
 // pub mod ssp;
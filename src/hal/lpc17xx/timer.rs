@@ -78,6 +78,18 @@ impl Timer {
   }
 }
 
+/// Returns the counter of a timer started with `Timer::new`, for interrupt
+/// handlers that have no `Timer` at hand.
+pub fn counter(peripheral: TimerPeripheral) -> u32 {
+  let reg = match peripheral {
+    Timer0 => &reg::TIMER0,
+    Timer1 => &reg::TIMER1,
+    Timer2 => &reg::TIMER2,
+    Timer3 => &reg::TIMER3,
+  };
+  reg.TC()
+}
+
 impl timer::Timer for Timer {
   #[inline(always)]
   fn get_counter(&self) -> u32 {
@@ -45,6 +45,30 @@ pub mod cortex_m4;
 #[cfg(feature = "cpu_cortex-m7")]
 pub mod cortex_m7;
 
+/// Interrupt masking for the configured core.
+#[cfg(any(feature = "cpu_cortex-m0",
+          feature = "cpu_cortex-m3",
+          feature = "cpu_cortex-m4",
+          feature = "cpu_cortex-m7"))]
+pub use self::cortex_common::irq;
+
+/// Host builds have no interrupts to disable.
+#[cfg(not(any(feature = "cpu_cortex-m0",
+              feature = "cpu_cortex-m3",
+              feature = "cpu_cortex-m4",
+              feature = "cpu_cortex-m7")))]
+#[allow(missing_docs)]
+pub mod irq {
+  pub struct NoInterrupts;
+
+  impl NoInterrupts {
+    pub fn new() -> NoInterrupts {
+      NoInterrupts
+    }
+  }
+}
+
+pub mod i2c;
 pub mod mem_init;
 pub mod pin;
 pub mod pwm;
@@ -1,7 +1,17 @@
 #[path="../../util/wait_for.rs"]
 #[macro_use] mod wait_for;
 
+use core::cell::Cell;
+
 use super::regs;
+use hal::cortex_m0::nvic;
+use hal::timer::WakeupTimer;
+
+/// RTC0 interrupt number.
+const RTC0_IRQ: usize = 11;
+
+/// Maximum value of the 24 bit RTC counter.
+const COUNTER_MASK: u32 = 0xFFFFFF;
 
 
 pub struct Rtc {}
@@ -64,4 +74,59 @@ impl Rtc {
 		regs::RTC().stop.set_trigger(true);
 	}
 
-}
\ No newline at end of file
+}
+
+/// Wakeup timer for the scheduler tickless idle, using RTC compare channel 0.
+///
+/// The RTC must be started and its prescaler configured beforehand.
+/// `counts_per_tick` is the number of RTC counts in one scheduler tick.
+pub struct RtcWakeup {
+	counts_per_tick: u32,
+	started_at: Cell<u32>,
+	// Counts past the last whole tick, carried over to the next wake-up.
+	remainder: Cell<u32>,
+}
+
+impl RtcWakeup {
+	pub fn new(counts_per_tick: u32) -> RtcWakeup {
+		RtcWakeup {
+			counts_per_tick: counts_per_tick,
+			started_at: Cell::new(0),
+			remainder: Cell::new(0),
+		}
+	}
+}
+
+impl WakeupTimer for RtcWakeup {
+	fn max_ticks(&self) -> u32 {
+		// Keep to half of the counter range, so that wrapping is unambiguous.
+		(COUNTER_MASK >> 1) / self.counts_per_tick
+	}
+
+	fn start_wakeup(&self, ticks: u32) {
+		let rtc = regs::RTC();
+		let now = rtc.counter.value();
+		self.started_at.set(now);
+
+		rtc.compare0.set_set(false);
+		// Wake up on a tick boundary, accounting for the carried over counts.
+		let counts = ticks * self.counts_per_tick - self.remainder.get();
+		rtc.cc0.set_value((now + counts) & COUNTER_MASK);
+		rtc.evten.set_compare0(true);
+		rtc.inten.set_compare0(true);
+		nvic::enable_irq(RTC0_IRQ);
+	}
+
+	fn stop_wakeup(&self) -> u32 {
+		let rtc = regs::RTC();
+		rtc.inten.set_compare0(false);
+		rtc.evten.set_compare0(false);
+		rtc.compare0.set_set(false);
+		nvic::clear_pending(RTC0_IRQ);
+
+		let elapsed = rtc.counter.value().wrapping_sub(self.started_at.get()) & COUNTER_MASK;
+		let total = elapsed + self.remainder.get();
+		self.remainder.set(total % self.counts_per_tick);
+		total / self.counts_per_tick
+	}
+}
@@ -2,6 +2,7 @@
 use super::regs;
 use drivers::chario::CharIO;
 use ::hal::nrf51822::pin::{SenseMode,GpioDirection,PinDriveMode};
+use ::hal::i2c::I2c;
 
 #[path="../../util/wait_for.rs"]
 #[macro_use] mod wait_for;
@@ -107,4 +108,15 @@ impl Twi {
 		self.write_bytes(data);
 	}
 
-}
\ No newline at end of file
+}
+
+impl I2c for Twi {
+	fn write(&self, address: u8, bytes: &[u8]) {
+		self.send_bytes(address, bytes);
+	}
+
+	fn write_prefixed(&self, address: u8, prefix: u8, bytes: &[u8]) {
+		self.set_address(address);
+		self.write_bytes_prefix(prefix, bytes);
+	}
+}
@@ -15,7 +15,13 @@
 
 //! Stack layout information.
 
-use core::intrinsics::transmute;
+use core::intrinsics::{transmute, volatile_load, volatile_store};
+
+/// Pattern used to fill unused stack memory.
+///
+/// Any word that doesn't match this pattern is considered to be used at some
+/// point in time.
+pub const STACK_PAINT: u32 = 0xDEAD_BEEF;
 
 extern {
   fn __STACK_BASE();
@@ -39,3 +45,33 @@ pub fn stack_limit() -> u32 {
 pub fn set_stack_limit(val: u32) {
   unsafe { __STACK_LIMIT = val }
 }
+
+/// Fills the stack region between `bottom` and `top` with `STACK_PAINT`.
+///
+/// The region must not be in use, that is, if it's a part of the active stack
+/// it must be completely below the current stack pointer. This function is
+/// always inlined, so that it doesn't allocate a frame in the area it paints.
+#[inline(always)]
+pub unsafe fn paint(bottom: u32, top: u32) {
+  let mut addr = bottom & !3;
+  while addr < top {
+    volatile_store(addr as *mut u32, STACK_PAINT);
+    addr += 4;
+  }
+}
+
+/// Returns the maximum number of bytes ever used in a painted stack region
+/// between `bottom` and `top`.
+///
+/// The stack grows down, so the region is scanned from the bottom up to the
+/// first word that doesn't match `STACK_PAINT`.
+pub fn high_water_mark(bottom: u32, top: u32) -> u32 {
+  let mut addr = bottom & !3;
+  while addr < top {
+    if unsafe { volatile_load(addr as *const u32) } != STACK_PAINT {
+      break;
+    }
+    addr += 4;
+  }
+  top - addr
+}
@@ -48,3 +48,23 @@ pub trait Timer {
     self.wait_us(s * 1000000);
   }
 }
+
+/// A low-power timer that can wake the core up from sleep.
+///
+/// Used by the scheduler to implement tickless idle, all the values are
+/// measured in scheduler ticks.
+pub trait WakeupTimer {
+  /// Returns the maximum number of ticks that can be timed in one go.
+  fn max_ticks(&self) -> u32;
+
+  /// Arms the timer to raise an interrupt after the given number of ticks.
+  fn start_wakeup(&self, ticks: u32);
+
+  /// Disarms the timer, clearing any pending interrupt, and returns the number
+  /// of whole ticks elapsed since `start_wakeup`.
+  ///
+  /// Time that doesn't make up a whole tick must be carried over to the next
+  /// `start_wakeup`/`stop_wakeup` pair, or the tick count drifts behind on
+  /// every idle period.
+  fn stop_wakeup(&self) -> u32;
+}
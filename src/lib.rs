@@ -17,6 +17,9 @@
 #![feature(core_intrinsics, core_slice_ext, core_str_ext)]
 #![allow(improper_ctypes)]
 #![feature(const_fn)]
+#![feature(allow_internal_unstable)]
+#![cfg_attr(feature = "heap", feature(allocator))]
+#![cfg_attr(feature = "heap", allocator)]
 #![no_std]
 
 /*!
@@ -57,7 +60,7 @@ extern crate rlibc;
 
 #[cfg(test)] #[macro_use(expect)] extern crate expectest;
 
-pub mod drivers;
+#[macro_use] pub mod drivers;
 pub mod hal;
 pub mod util;
 pub mod os;
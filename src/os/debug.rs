@@ -13,72 +13,523 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-//! Tracing for debugging.
-//
-// Use `set_backend`
+/*!
+Leveled logging for debugging.
+
+The `error!`, `warn!`, `info!`, `debug!` and `trace!` macros format a message
+with `core::fmt`, prefix it with a timestamp, level and module path, and append
+the line to a ring buffer. Logging never blocks: if the buffer is full, the
+line is dropped and counted in `dropped()`. `drain()` writes the buffered
+output to all the backends registered with `add_backend()`, call it from the
+main loop or a low priority task.
+
+`print()` is synchronous, as before leveled logging: it drains the buffer to
+keep the output in order, then writes the string to the backends before
+returning.
+
+On ARMv7-M cores, `hal::cortex_mN::itm::Stimulus` sends the output to the
+debugger over SWO.
+
+Messages are filtered at compile time, both globally with one of the
+`log_level_{off,error,warn,info,debug}` cargo features (everything up to
+`Trace` is compiled in by default), and per module with `log_level!`:
+
+```ignore
+log_level!(Info);
+
+fn poll() {
+  debug!("compiled out");
+  info!("temperature {}.{}C", t / 10, t % 10);
+}
+```
+
+Timestamps are scheduler ticks with `multitasking`. Without it, call `tick()`
+from the SysTick handler.
+
+Output is only compiled in with `--cfg debug`. Without it, or with
+`log_level_off`, the functions below are no-ops and the buffer isn't allocated.
+*/
+
+pub use os::debug::internal::{add_backend, remove_backend, set_backend, print};
+pub use os::debug::internal::{write_raw, log, dropped, drain};
+
+/// Log message severity.
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+pub enum Level {
+  /// Something failed.
+  Error = 1,
+  /// Something unexpected happened, but was handled.
+  Warn = 2,
+  /// Normal operation.
+  Info = 3,
+  /// Details useful when debugging.
+  Debug = 4,
+  /// Very verbose details.
+  Trace = 5,
+}
+
+impl Level {
+  /// Returns the level name, as printed in log lines.
+  pub fn name(&self) -> &'static str {
+    match *self {
+      Level::Error => "ERROR",
+      Level::Warn  => "WARN",
+      Level::Info  => "INFO",
+      Level::Debug => "DEBUG",
+      Level::Trace => "TRACE",
+    }
+  }
+}
+
+/// Most verbose level compiled in, 0 if logging is disabled.
+pub const STATIC_MAX_LEVEL: u8 = max_level::LEVEL;
+
+// The most restrictive of the enabled `log_level_*` features wins, so that
+// they stay additive.
+#[cfg(any(not(debug), feature = "log_level_off"))]
+mod max_level {
+  pub const LEVEL: u8 = 0;
+}
+
+#[cfg(all(debug, not(feature = "log_level_off")))]
+mod max_level {
+  use os::debug::Level;
+
+  #[cfg(feature = "log_level_error")]
+  pub const LEVEL: u8 = Level::Error as u8;
+
+  #[cfg(not(feature = "log_level_error"))]
+  #[cfg(feature = "log_level_warn")]
+  pub const LEVEL: u8 = Level::Warn as u8;
+
+  #[cfg(not(any(feature = "log_level_error", feature = "log_level_warn")))]
+  #[cfg(feature = "log_level_info")]
+  pub const LEVEL: u8 = Level::Info as u8;
+
+  #[cfg(not(any(feature = "log_level_error", feature = "log_level_warn",
+                feature = "log_level_info")))]
+  #[cfg(feature = "log_level_debug")]
+  pub const LEVEL: u8 = Level::Debug as u8;
+
+  #[cfg(not(any(feature = "log_level_error", feature = "log_level_warn",
+                feature = "log_level_info", feature = "log_level_debug")))]
+  pub const LEVEL: u8 = Level::Trace as u8;
+}
+
+/// Most verbose level compiled in for the current module, override it with
+/// `log_level!`.
+#[macro_export]
+macro_rules! module_log_level {
+  () => ($crate::os::debug::Level::Trace)
+}
+
+/// Sets the most verbose level compiled in for the rest of the module.
+#[macro_export]
+macro_rules! log_level {
+  ($level:ident) => (
+    macro_rules! module_log_level {
+      () => ($crate::os::debug::Level::$level)
+    }
+  )
+}
+
+/// Logs a message with a given level.
+#[macro_export]
+macro_rules! log {
+  ($level:expr, $($arg:tt)+) => ({
+    let level: $crate::os::debug::Level = $level;
+    if (level as u8) <= $crate::os::debug::STATIC_MAX_LEVEL &&
+        (level as u8) <= (module_log_level!() as u8) {
+      $crate::os::debug::log(level, module_path!(), format_args!($($arg)+));
+    }
+  })
+}
+
+/// Logs a message with the `Error` level.
+#[macro_export]
+macro_rules! error {
+  ($($arg:tt)+) => (log!($crate::os::debug::Level::Error, $($arg)+))
+}
+
+/// Logs a message with the `Warn` level.
+#[macro_export]
+macro_rules! warn {
+  ($($arg:tt)+) => (log!($crate::os::debug::Level::Warn, $($arg)+))
+}
+
+/// Logs a message with the `Info` level.
+#[macro_export]
+macro_rules! info {
+  ($($arg:tt)+) => (log!($crate::os::debug::Level::Info, $($arg)+))
+}
+
+/// Logs a message with the `Debug` level.
+#[macro_export]
+macro_rules! debug {
+  ($($arg:tt)+) => (log!($crate::os::debug::Level::Debug, $($arg)+))
+}
+
+/// Logs a message with the `Trace` level.
+#[macro_export]
+macro_rules! trace {
+  ($($arg:tt)+) => (log!($crate::os::debug::Level::Trace, $($arg)+))
+}
+
+/// Maximum number of backends.
+pub const MaxBackends: usize = 4;
+
+/// Size of the log ring buffer in bytes.
+pub const LogBufferSize: usize = 512;
+
+/// Maximum length of a single log line, longer lines are truncated.
+pub const LineBufferSize: usize = 96;
+
+#[cfg(not(feature = "multitasking"))]
+mod ticks {
+  /// Ticks counted by os::debug::tick().
+  static mut Ticks: u32 = 0;
+
+  pub fn get() -> u32 {
+    unsafe { Ticks }
+  }
 
-pub use os::debug::internal::{set_backend, print, Token};
+  pub fn advance() {
+    unsafe { Ticks = Ticks.wrapping_add(1) };
+  }
+}
+
+#[cfg(feature = "multitasking")]
+mod ticks {
+  use os::task;
 
-#[cfg(debug)]
+  pub fn get() -> u32 {
+    task::ticks()
+  }
+}
+
+/// Advances the log timestamp, call this from the SysTick handler.
+#[cfg(not(feature = "multitasking"))]
+pub fn tick() {
+  ticks::advance();
+}
+
+/// Returns the current log timestamp.
+pub fn timestamp() -> u32 {
+  ticks::get()
+}
+
+#[cfg(any(test, all(debug, not(feature = "log_level_off"))))]
 mod internal {
-  use core::option::{Some, None, Option};
-  use core::ops::Drop;
-  use core::mem::transmute;
+  use core::cell::UnsafeCell;
+  use core::fmt::{self, Write};
+  use core::marker::Sync;
+  use core::option::Option::{self, Some, None};
+
+  use hal::irq::NoInterrupts;
+
   use drivers::chario::CharIO;
+  use os::debug::{Level, MaxBackends, LogBufferSize, LineBufferSize};
 
-  static mut backend: Option<*const CharIO + 'static> = None;
+  /// Registered debugging output backends.
+  static mut Backends: [Option<&'static CharIO>; MaxBackends] = [None; MaxBackends];
 
-  /// A token to ensure the life of the reference to the debugging output backend
-  /// doesn't outlive the backend itself.
-  #[must_use]
-  pub struct Token<'a> {
-    #[allow(dead_code)]
-    hello: ()
-  }
+  /// Buffered output, waiting for `drain()`.
+  static Buffer: SharedLogBuffer = SharedLogBuffer {
+    buf: UnsafeCell::new(LogBuffer::new()),
+  };
 
-  #[unsafe_destructor]
-  impl<'a> Drop for Token<'a> {
-    fn drop(&mut self) {
-      unsafe {
-        backend = None;
+  /// Registers a debugging output backend. Returns false if there are already
+  /// `MaxBackends` backends.
+  pub fn add_backend(b: &'static CharIO) -> bool {
+    let _crit = NoInterrupts::new();
+    unsafe {
+      for slot in Backends.iter_mut() {
+        if slot.is_none() {
+          *slot = Some(b);
+          return true;
+        }
       }
     }
+    false
   }
 
-  /// Set the debugging output backend (mock)
-  pub fn set_backend<'a>(b: &'a CharIO) -> Token<'a> {
+  /// Unregisters a debugging output backend.
+  pub fn remove_backend(b: &'static CharIO) {
+    let _crit = NoInterrupts::new();
+    let ptr = b as *const CharIO as *const u8;
     unsafe {
-      backend = Some(transmute(b));
-      Token { hello: () }
+      for slot in Backends.iter_mut() {
+        match *slot {
+          Some(s) if s as *const CharIO as *const u8 == ptr => *slot = None,
+          _ => {},
+        }
+      }
     }
   }
 
-  /// Print debugging output backend (mock)
+  /// Replaces all the backends with a single one.
+  pub fn set_backend(b: &'static CharIO) {
+    {
+      let _crit = NoInterrupts::new();
+      unsafe { Backends = [None; MaxBackends] };
+    }
+    add_backend(b);
+  }
+
+  /// Writes a string to the backends as is, after the buffered output.
   pub fn print(s: &str) {
-    unsafe {
-      match backend {
-        Some(b) => (*b).puts(s),
+    drain();
+    let backends = unsafe { Backends };
+    for backend in backends.iter() {
+      match *backend {
+        Some(b) => b.puts(s),
         None => {},
       }
     }
   }
+
+  /// Appends raw bytes to the debugging output, all or nothing. Used by
+  /// `os::deflog` for binary frames.
+  pub fn write_raw(bytes: &[u8]) {
+    write_bytes(bytes);
+  }
+
+  /// Formats and buffers a log line, used by the logging macros.
+  pub fn log(level: Level, module: &str, args: fmt::Arguments) {
+    let mut line = LineWriter::new();
+    let _ = write!(line, "[{}] {} {}: ", super::ticks::get(), level.name(),
+                   module);
+    let _ = line.write_fmt(args);
+    write_bytes(line.finish());
+  }
+
+  /// Returns the number of lines dropped because the buffer was full.
+  pub fn dropped() -> u32 {
+    unsafe { (*Buffer.buf.get()).dropped }
+  }
+
+  /// Writes all the buffered output to the registered backends.
+  ///
+  /// Output is taken out of the buffer in small chunks, so interrupts are only
+  /// disabled for a short time.
+  pub fn drain() {
+    let mut chunk = [0u8; 16];
+    loop {
+      let len = {
+        let _crit = NoInterrupts::new();
+        unsafe { (*Buffer.buf.get()).pop_into(&mut chunk) }
+      };
+      if len == 0 {
+        break;
+      }
+      let backends = unsafe { Backends };
+      for backend in backends.iter() {
+        match *backend {
+          Some(b) => for &c in chunk[..len].iter() { b.putc(c as char) },
+          None => {},
+        }
+      }
+    }
+  }
+
+  fn write_bytes(bytes: &[u8]) {
+    let _crit = NoInterrupts::new();
+    unsafe { (*Buffer.buf.get()).push(bytes) };
+  }
+
+  /// Byte ring buffer, keeping log lines whole.
+  struct LogBuffer {
+    buf: [u8; LogBufferSize],
+    head: usize,
+    len: usize,
+    dropped: u32,
+  }
+
+  impl LogBuffer {
+    const fn new() -> LogBuffer {
+      LogBuffer {
+        buf: [0; LogBufferSize],
+        head: 0,
+        len: 0,
+        dropped: 0,
+      }
+    }
+
+    /// Appends all the bytes, or none of them if they don't fit.
+    fn push(&mut self, bytes: &[u8]) -> bool {
+      if bytes.len() > LogBufferSize - self.len {
+        self.dropped = self.dropped.wrapping_add(1);
+        return false;
+      }
+      for &b in bytes.iter() {
+        let tail = (self.head + self.len) % LogBufferSize;
+        self.buf[tail] = b;
+        self.len += 1;
+      }
+      true
+    }
+
+    /// Moves up to `out.len()` bytes out of the buffer, returns the count.
+    fn pop_into(&mut self, out: &mut [u8]) -> usize {
+      let mut count = 0;
+      while count < out.len() && self.len > 0 {
+        out[count] = self.buf[self.head];
+        self.head = (self.head + 1) % LogBufferSize;
+        self.len -= 1;
+        count += 1;
+      }
+      count
+    }
+  }
+
+  struct SharedLogBuffer {
+    buf: UnsafeCell<LogBuffer>,
+  }
+
+  unsafe impl Sync for SharedLogBuffer {}
+
+  /// Formats a single log line on the stack.
+  struct LineWriter {
+    buf: [u8; LineBufferSize],
+    len: usize,
+  }
+
+  impl LineWriter {
+    fn new() -> LineWriter {
+      LineWriter { buf: [0; LineBufferSize], len: 0 }
+    }
+
+    /// Terminates the line with a newline, replacing the last character of a
+    /// truncated line.
+    fn finish(&mut self) -> &[u8] {
+      if self.len == LineBufferSize {
+        self.len -= 1;
+      }
+      self.buf[self.len] = b'\n';
+      self.len += 1;
+      &self.buf[..self.len]
+    }
+  }
+
+  impl Write for LineWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+      // Keep room for the newline.
+      let room = LineBufferSize - 1 - self.len;
+      let bytes = s.as_bytes();
+      let count = if bytes.len() > room { room } else { bytes.len() };
+      self.buf[self.len..self.len + count].copy_from_slice(&bytes[..count]);
+      self.len += count;
+      Ok(())
+    }
+  }
+  #[cfg(test)]
+  mod test {
+    use core::fmt::Write;
+    use drivers::chario::CharIO;
+    use os::debug::{LogBufferSize, LineBufferSize};
+    use super::{LogBuffer, LineWriter};
+    use super::{set_backend, remove_backend, write_raw, print};
+
+    struct Sink;
+
+    static mut Output: [u8; 8] = [0; 8];
+    static mut OutputLen: usize = 0;
+    static SINK: Sink = Sink;
+
+    impl CharIO for Sink {
+      fn putc(&self, value: char) {
+        unsafe {
+          Output[OutputLen] = value as u8;
+          OutputLen += 1;
+        }
+      }
+    }
+
+    #[test]
+    fn print_should_write_after_buffered_output() {
+      set_backend(&SINK);
+      write_raw(b"ab");
+      print("cd");
+      assert!(unsafe { &Output[..OutputLen] } == b"abcd");
+      remove_backend(&SINK);
+    }
+
+    #[test]
+    fn log_buffer_should_keep_lines_whole() {
+      let mut buf = LogBuffer::new();
+      let line = [b'x'; LogBufferSize - 1];
+      assert!(buf.push(&line));
+      assert!(!buf.push(b"ab"));
+      assert!(buf.dropped == 1);
+      assert!(buf.push(b"a"));
+      assert!(buf.len == LogBufferSize);
+    }
+
+    #[test]
+    fn log_buffer_should_wrap_around() {
+      let mut buf = LogBuffer::new();
+      let mut out = [0u8; LogBufferSize];
+      let line = [b'x'; LogBufferSize - 2];
+      assert!(buf.push(&line));
+      assert!(buf.pop_into(&mut out) == LogBufferSize - 2);
+
+      assert!(buf.push(b"hello"));
+      let mut out = [0u8; 3];
+      assert!(buf.pop_into(&mut out) == 3);
+      assert!(&out == b"hel");
+      assert!(buf.pop_into(&mut out) == 2);
+      assert!(&out[..2] == b"lo");
+      assert!(buf.pop_into(&mut out) == 0);
+    }
+
+    #[test]
+    fn line_writer_should_append_newline() {
+      let mut line = LineWriter::new();
+      let _ = write!(line, "[{}] {}", 42, "INFO");
+      assert!(line.finish() == b"[42] INFO\n");
+    }
+
+    #[test]
+    fn line_writer_should_truncate_long_lines() {
+      let mut line = LineWriter::new();
+      for _ in 0..LineBufferSize {
+        let _ = line.write_str("ab");
+      }
+      let out = line.finish();
+      assert!(out.len() == LineBufferSize);
+      assert!(out[LineBufferSize - 1] == b'\n');
+    }
+  }
 }
 
-#[cfg(not(debug))]
+#[cfg(not(any(test, all(debug, not(feature = "log_level_off")))))]
 mod internal {
+  use core::fmt;
+
   use drivers::chario::CharIO;
+  use os::debug::Level;
+
+  /// Registers a debugging output backend (mock)
+  pub fn add_backend(_: &'static CharIO) -> bool { true }
+
+  /// Unregisters a debugging output backend (mock)
+  pub fn remove_backend(_: &'static CharIO) { }
 
-  /// Set the debugging output backend (mock)
-  pub fn set_backend(_: &CharIO) { }
+  /// Replaces all the backends with a single one (mock)
+  pub fn set_backend(_: &'static CharIO) { }
 
-  /// Print debugging output backend (mock)
+  /// Writes a string to the backends (mock)
   pub fn print(_: &str) { }
 
-  /// A token to ensure the life of the reference to the debugging output backend
-  /// doesn't outlive the backend itself.
-  #[must_use]
-  pub struct Token {
-    #[allow(dead_code)]
-    hello: ()
-  }
+  /// Appends raw bytes to the debugging output (mock)
+  pub fn write_raw(_: &[u8]) { }
+
+  /// Formats and buffers a log line (mock)
+  pub fn log(_: Level, _: &str, _: fmt::Arguments) { }
+
+  /// Returns the number of dropped lines (mock)
+  pub fn dropped() -> u32 { 0 }
+
+  /// Writes the buffered output to the backends (mock)
+  pub fn drain() { }
 }
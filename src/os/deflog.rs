@@ -0,0 +1,305 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2014 Vladimir "farcaller" Pouzanov <farcaller@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/*!
+Deferred-format binary logging.
+
+The `dlog!` family of macros (`derror!`, `dwarn!`, `dinfo!`, `ddebug!` and
+`dtrace!`) works like the `os::debug` logging macros, but the format string
+never reaches flash. It is interned into the `.zinc_log` ELF section, which
+the linker script marks as `INFO` (not loaded), and the target only sends the
+string offset in that section along with binary-encoded arguments:
+
+```ignore
+dinfo!("adc {} vref {}", sample, vref);
+```
+
+Frames are appended to the `os::debug` output buffer and drained to the same
+`CharIO` backends, so text and binary logs can share a UART. Each frame is
+
+```text
+0xFF | payload length | level | string offset (u16 LE) | timestamp (u32 LE) | args
+```
+
+and every argument is a type tag followed by its little-endian value (`str`
+arguments are length-prefixed). Text output is ASCII, so a frame can always be
+told apart by its first byte. The `deflog_decoder` host tool reads the
+`.zinc_log` section of the firmware ELF and turns a captured stream back into
+text.
+
+Format strings must not contain `"`, `\`, `$` or newlines, as they are passed
+to the assembler verbatim. The string offset is 16 bits wide, so all the
+format strings of a firmware must fit into 64KiB; the linker script fails the
+link if `.zinc_log` grows past that. Arguments that don't fit into `MaxFrameSize` are
+dropped and the frame is flagged as truncated.
+*/
+
+use os::debug::{self, Level};
+
+/// First byte of every binary log frame.
+pub const FrameStart: u8 = 0xFF;
+
+/// Maximum size of a frame, including the header.
+pub const MaxFrameSize: usize = 48;
+
+/// Set in the level byte if some of the arguments were dropped.
+pub const TruncatedFlag: u8 = 0x80;
+
+/// Bytes before the arguments: start, length, level, offset and timestamp.
+const HeaderSize: usize = 9;
+
+/// Argument type tags.
+pub mod tag {
+  #![allow(missing_docs)]
+  pub const U8: u8 = 1;
+  pub const U16: u8 = 2;
+  pub const U32: u8 = 3;
+  pub const I8: u8 = 4;
+  pub const I16: u8 = 5;
+  pub const I32: u8 = 6;
+  pub const BOOL: u8 = 7;
+  pub const STR: u8 = 8;
+}
+
+/// Interns a format string into `.zinc_log` and returns its offset.
+#[cfg(target_arch = "arm")]
+#[doc(hidden)]
+#[macro_export]
+#[allow_internal_unstable]
+macro_rules! deflog_intern {
+  ($fmt:tt) => ({
+    let offset: u32;
+    unsafe {
+      asm!(concat!(".pushsection .zinc_log,\"\",%progbits\n",
+                   "1: .asciz \"", $fmt, "\"\n",
+                   ".popsection\n",
+                   "ldr $0, =1b")
+           : "=r"(offset) ::: "volatile");
+    }
+    // .zinc_log starts at address 0, the linker script asserts it fits u16.
+    offset as u16
+  })
+}
+
+#[cfg(not(target_arch = "arm"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! deflog_intern {
+  ($fmt:tt) => ({
+    let _ = $fmt;
+    0u16
+  })
+}
+
+/// Logs a deferred-format message with a given level.
+#[macro_export]
+macro_rules! dlog {
+  ($level:expr, $fmt:tt $(, $arg:expr)*) => ({
+    let level: $crate::os::debug::Level = $level;
+    if (level as u8) <= $crate::os::debug::STATIC_MAX_LEVEL &&
+        (level as u8) <= (module_log_level!() as u8) {
+      let mut frame = $crate::os::deflog::Frame::new(level, deflog_intern!($fmt));
+      $( $crate::os::deflog::Encode::encode(&$arg, &mut frame); )*
+      frame.send();
+    }
+  })
+}
+
+/// Logs a deferred-format message with the `Error` level.
+#[macro_export]
+macro_rules! derror {
+  ($($arg:tt)+) => (dlog!($crate::os::debug::Level::Error, $($arg)+))
+}
+
+/// Logs a deferred-format message with the `Warn` level.
+#[macro_export]
+macro_rules! dwarn {
+  ($($arg:tt)+) => (dlog!($crate::os::debug::Level::Warn, $($arg)+))
+}
+
+/// Logs a deferred-format message with the `Info` level.
+#[macro_export]
+macro_rules! dinfo {
+  ($($arg:tt)+) => (dlog!($crate::os::debug::Level::Info, $($arg)+))
+}
+
+/// Logs a deferred-format message with the `Debug` level.
+#[macro_export]
+macro_rules! ddebug {
+  ($($arg:tt)+) => (dlog!($crate::os::debug::Level::Debug, $($arg)+))
+}
+
+/// Logs a deferred-format message with the `Trace` level.
+#[macro_export]
+macro_rules! dtrace {
+  ($($arg:tt)+) => (dlog!($crate::os::debug::Level::Trace, $($arg)+))
+}
+
+/// A binary log frame being built.
+pub struct Frame {
+  buf: [u8; MaxFrameSize],
+  len: usize,
+}
+
+impl Frame {
+  /// Starts a frame for the format string at `offset` in `.zinc_log`.
+  pub fn new(level: Level, offset: u16) -> Frame {
+    Frame::with_timestamp(level, offset, debug::timestamp())
+  }
+
+  fn with_timestamp(level: Level, offset: u16, timestamp: u32) -> Frame {
+    let mut frame = Frame {
+      buf: [0; MaxFrameSize],
+      len: 0,
+    };
+    frame.push(&[FrameStart, 0, level as u8]);
+    frame.push(&[offset as u8, (offset >> 8) as u8]);
+    frame.push(&[timestamp as u8, (timestamp >> 8) as u8,
+                 (timestamp >> 16) as u8, (timestamp >> 24) as u8]);
+    frame
+  }
+
+  /// Appends an encoded argument. Arguments that don't fit are dropped whole.
+  pub fn push(&mut self, bytes: &[u8]) {
+    if bytes.len() > MaxFrameSize - self.len {
+      self.buf[2] |= TruncatedFlag;
+      return;
+    }
+    self.buf[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+    self.len += bytes.len();
+  }
+
+  /// Returns the complete frame.
+  pub fn as_bytes(&mut self) -> &[u8] {
+    self.buf[1] = (self.len - 2) as u8;
+    &self.buf[..self.len]
+  }
+
+  /// Appends the frame to the debugging output.
+  pub fn send(mut self) {
+    debug::write_raw(self.as_bytes());
+  }
+}
+
+/// A value that can be sent as a deferred log argument.
+pub trait Encode {
+  /// Appends the tagged value to the frame.
+  fn encode(&self, frame: &mut Frame);
+}
+
+impl Encode for u8 {
+  fn encode(&self, frame: &mut Frame) {
+    frame.push(&[tag::U8, *self]);
+  }
+}
+
+impl Encode for u16 {
+  fn encode(&self, frame: &mut Frame) {
+    frame.push(&[tag::U16, *self as u8, (*self >> 8) as u8]);
+  }
+}
+
+impl Encode for u32 {
+  fn encode(&self, frame: &mut Frame) {
+    let v = *self;
+    frame.push(&[tag::U32, v as u8, (v >> 8) as u8, (v >> 16) as u8,
+                 (v >> 24) as u8]);
+  }
+}
+
+impl Encode for usize {
+  fn encode(&self, frame: &mut Frame) {
+    (*self as u32).encode(frame);
+  }
+}
+
+impl Encode for i8 {
+  fn encode(&self, frame: &mut Frame) {
+    frame.push(&[tag::I8, *self as u8]);
+  }
+}
+
+impl Encode for i16 {
+  fn encode(&self, frame: &mut Frame) {
+    frame.push(&[tag::I16, *self as u8, (*self >> 8) as u8]);
+  }
+}
+
+impl Encode for i32 {
+  fn encode(&self, frame: &mut Frame) {
+    let v = *self as u32;
+    frame.push(&[tag::I32, v as u8, (v >> 8) as u8, (v >> 16) as u8,
+                 (v >> 24) as u8]);
+  }
+}
+
+impl Encode for isize {
+  fn encode(&self, frame: &mut Frame) {
+    (*self as i32).encode(frame);
+  }
+}
+
+impl Encode for bool {
+  fn encode(&self, frame: &mut Frame) {
+    frame.push(&[tag::BOOL, *self as u8]);
+  }
+}
+
+impl<'a> Encode for &'a str {
+  fn encode(&self, frame: &mut Frame) {
+    let bytes = self.as_bytes();
+    let len = if bytes.len() > 255 { 255 } else { bytes.len() };
+    if 2 + len > MaxFrameSize - frame.len {
+      frame.buf[2] |= TruncatedFlag;
+      return;
+    }
+    frame.push(&[tag::STR, len as u8]);
+    frame.push(&bytes[..len]);
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use os::debug::Level;
+  use super::{Frame, Encode, MaxFrameSize, TruncatedFlag};
+
+  #[test]
+  fn frame_should_have_header() {
+    let mut frame = Frame::with_timestamp(Level::Info, 0x1234, 0xAABBCCDD);
+    assert!(frame.as_bytes() ==
+        &[0xFF, 7, 3, 0x34, 0x12, 0xDD, 0xCC, 0xBB, 0xAA]);
+  }
+
+  #[test]
+  fn frame_should_encode_tagged_args() {
+    let mut frame = Frame::with_timestamp(Level::Warn, 0, 0);
+    300u16.encode(&mut frame);
+    (-2i8).encode(&mut frame);
+    "hi".encode(&mut frame);
+    assert!(&frame.as_bytes()[9..] == &[2, 0x2C, 0x01, 4, 0xFE, 8, 2, b'h', b'i']);
+    assert!(frame.as_bytes()[1] == 16);
+  }
+
+  #[test]
+  fn frame_should_flag_dropped_args() {
+    let mut frame = Frame::with_timestamp(Level::Error, 0, 0);
+    for _ in 0..MaxFrameSize {
+      7u32.encode(&mut frame);
+    }
+    let bytes = frame.as_bytes();
+    assert!(bytes.len() <= MaxFrameSize);
+    assert!(bytes[2] == 1 | TruncatedFlag);
+  }
+}
@@ -0,0 +1,500 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2014 Vladimir "farcaller" Pouzanov <farcaller@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/*!
+Cooperative single-threaded executor.
+
+The executor runs up to `MaxFutures` statically allocated futures on the main
+stack, without heap and without the `multitasking` feature. A future is only
+polled after it was woken up, and the core sleeps in `wfi` when nothing is
+ready. Wake-ups come from interrupt handlers through `WakerSlot`, `Signal`,
+`UartRx` and `tick()`.
+
+The toolchain zinc is pinned to predates `async fn` and `core::future`, so the
+executor defines its own `Future` trait and state machines implement `poll()`
+by hand. The trait mirrors the shape of `core::future::Future`, which keeps
+porting to `async fn` mechanical once the toolchain moves on.
+
+```ignore
+struct Blink { delay: Delay }
+
+impl Future for Blink {
+  type Output = ();
+  fn poll(&mut self, waker: &Waker) -> Poll<()> {
+    loop {
+      match self.delay.poll(waker) {
+        Ready(()) => { led.toggle(); self.delay = executor::delay(50); },
+        Pending => return Pending,
+      }
+    }
+  }
+}
+
+static mut BLINK: Blink = Blink { delay: Delay::new(50) };
+
+executor::spawn(unsafe { &mut BLINK });
+executor::run();
+```
+
+`WakerSlot`, `Signal`, `UartRx`, `Delay` and `WaitLevel` are driver-agnostic
+primitives: no HAL driver feeds them, the application wires them to its own
+interrupt handlers. Call `executor::tick()` from a periodic interrupt
+(SysTick or RTC) to drive `Delay`, `Signal::raise()` from the pin interrupt
+for `WaitLevel`, and `UartRx::push()` from the UART receive interrupt:
+
+```ignore
+static EDGE: Signal = Signal::new();
+static RX: UartRx = UartRx::new();
+
+#[no_mangle]
+pub unsafe extern fn isr_systick() { executor::tick(); }
+
+#[no_mangle]
+pub unsafe extern fn isr_gpiote() { GPIOTE.clear_event(0); EDGE.raise(); }
+
+#[no_mangle]
+pub unsafe extern fn isr_uart0() { RX.push(UART0.read_byte()); }
+```
+*/
+
+use core::cell::UnsafeCell;
+use core::marker::Sync;
+use core::option::Option::{self, Some, None};
+
+use hal::irq::NoInterrupts;
+
+use hal::pin::{Gpio, GpioLevel};
+use util::support::wfi;
+
+pub use self::Poll::*;
+
+/// Maximum number of futures run by the executor.
+pub const MaxFutures: usize = 8;
+
+/// Result of polling a future.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Poll<T> {
+  /// The future completed with a value.
+  Ready(T),
+  /// The future isn't complete, it will be polled again once woken up.
+  Pending,
+}
+
+/// An asynchronous computation.
+pub trait Future {
+  /// Value the future completes with.
+  type Output;
+
+  /// Advances the future. If it returns `Pending`, the future must arrange
+  /// for `waker` to be woken up when it can make progress.
+  fn poll(&mut self, waker: &Waker) -> Poll<Self::Output>;
+}
+
+/// Wakes up an executor future.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Waker {
+  mask: u32,
+}
+
+impl Waker {
+  /// Marks the future as ready to be polled. Safe to call from interrupts.
+  pub fn wake(&self) {
+    wake_mask(self.mask);
+  }
+}
+
+/// Futures waiting to be polled, one bit per executor slot.
+static mut ReadyMask: u32 = 0;
+
+/// Scheduled futures.
+static mut Futures: [Option<*mut Future<Output=()>>; MaxFutures] = [None; MaxFutures];
+
+/// Ticks counted by `tick()`.
+static mut Ticks: u32 = 0;
+
+/// Futures waiting for the next tick.
+static TickWaiters: WakerSlot = WakerSlot::new();
+
+fn wake_mask(mask: u32) {
+  let _crit = NoInterrupts::new();
+  unsafe { ReadyMask |= mask };
+}
+
+/// Schedules a future to be run by the executor.
+///
+/// Returns false if all the `MaxFutures` slots are taken.
+pub fn spawn(future: &'static mut Future<Output=()>) -> bool {
+  let _crit = NoInterrupts::new();
+  unsafe {
+    for i in 0..MaxFutures {
+      if Futures[i].is_none() {
+        Futures[i] = Some(future as *mut Future<Output=()>);
+        ReadyMask |= 1 << i;
+        return true;
+      }
+    }
+  }
+  false
+}
+
+/// Polls all the ready futures once. Returns false if there are no futures
+/// left.
+pub fn run_once() -> bool {
+  let ready = {
+    let _crit = NoInterrupts::new();
+    let ready = unsafe { ReadyMask };
+    unsafe { ReadyMask = 0 };
+    ready
+  };
+
+  let mut alive = false;
+  for i in 0..MaxFutures {
+    let future = match unsafe { Futures[i] } {
+      Some(f) => f,
+      None => continue,
+    };
+    alive = true;
+    if ready & (1 << i) == 0 {
+      continue;
+    }
+    let waker = Waker { mask: 1 << i };
+    match unsafe { (*future).poll(&waker) } {
+      Ready(()) => unsafe { Futures[i] = None },
+      Pending => {},
+    }
+  }
+  alive
+}
+
+/// Runs futures until all of them complete, sleeping in `wfi` while none is
+/// ready.
+pub fn run() {
+  while run_once() {
+    // Interrupts are disabled around the check, so that a wake-up can't slip
+    // in between it and `wfi`. A pending interrupt still wakes the core up
+    // and is handled once the critical section ends.
+    let _crit = NoInterrupts::new();
+    if unsafe { ReadyMask } == 0 {
+      wfi();
+    }
+  }
+}
+
+/// Advances the executor tick and wakes up pending `Delay`s.
+///
+/// Call this from a periodic interrupt, `Delay` counts in its periods.
+pub fn tick() {
+  unsafe { Ticks = Ticks.wrapping_add(1) };
+  TickWaiters.wake();
+}
+
+/// Returns the number of ticks counted by `tick()`.
+pub fn ticks() -> u32 {
+  unsafe { Ticks }
+}
+
+/// Wakers registered with an interrupt source.
+///
+/// Statically allocated by drivers, futures register with it before
+/// returning `Pending` and the interrupt handler calls `wake()`.
+pub struct WakerSlot {
+  mask: UnsafeCell<u32>,
+}
+
+impl WakerSlot {
+  /// Creates an empty slot.
+  pub const fn new() -> WakerSlot {
+    WakerSlot { mask: UnsafeCell::new(0) }
+  }
+
+  /// Registers a waker to be woken up by the next `wake()`.
+  pub fn register(&self, waker: &Waker) {
+    let _crit = NoInterrupts::new();
+    unsafe { *self.mask.get() |= waker.mask };
+  }
+
+  /// Wakes up and unregisters all the registered wakers.
+  pub fn wake(&self) {
+    let _crit = NoInterrupts::new();
+    unsafe {
+      ReadyMask |= *self.mask.get();
+      *self.mask.get() = 0;
+    }
+  }
+}
+
+unsafe impl Sync for WakerSlot {}
+
+/// Waits for a number of executor ticks.
+pub struct Delay {
+  ticks: u32,
+  started_at: Option<u32>,
+}
+
+impl Delay {
+  /// Creates a delay of `ticks`, counted from the first poll.
+  pub const fn new(ticks: u32) -> Delay {
+    Delay { ticks: ticks, started_at: None }
+  }
+}
+
+impl Future for Delay {
+  type Output = ();
+
+  fn poll(&mut self, waker: &Waker) -> Poll<()> {
+    let now = ticks();
+    let start = match self.started_at {
+      Some(s) => s,
+      None => {
+        self.started_at = Some(now);
+        now
+      },
+    };
+    if now.wrapping_sub(start) >= self.ticks {
+      Ready(())
+    } else {
+      TickWaiters.register(waker);
+      Pending
+    }
+  }
+}
+
+/// Returns a future that completes after `ticks` executor ticks.
+pub fn delay(ticks: u32) -> Delay {
+  Delay::new(ticks)
+}
+
+/// An event raised by an interrupt handler, e.g. a GPIO edge.
+pub struct Signal {
+  raised: UnsafeCell<bool>,
+  wakers: WakerSlot,
+}
+
+impl Signal {
+  /// Creates a new lowered signal.
+  pub const fn new() -> Signal {
+    Signal {
+      raised: UnsafeCell::new(false),
+      wakers: WakerSlot::new(),
+    }
+  }
+
+  /// Raises the signal, waking up the waiting futures. Call this from the
+  /// interrupt handler.
+  pub fn raise(&self) {
+    {
+      let _crit = NoInterrupts::new();
+      unsafe { *self.raised.get() = true };
+    }
+    self.wakers.wake();
+  }
+
+  /// Returns a future that completes on the next `raise()`.
+  pub fn wait(&'static self) -> WaitSignal {
+    let _crit = NoInterrupts::new();
+    unsafe { *self.raised.get() = false };
+    WaitSignal { signal: self }
+  }
+
+  fn take(&self, waker: &Waker) -> bool {
+    let _crit = NoInterrupts::new();
+    unsafe {
+      if *self.raised.get() {
+        *self.raised.get() = false;
+        true
+      } else {
+        self.wakers.register(waker);
+        false
+      }
+    }
+  }
+
+  /// Clears the signal and registers the waker for the next `raise()`.
+  fn register(&self, waker: &Waker) {
+    let _crit = NoInterrupts::new();
+    unsafe { *self.raised.get() = false };
+    self.wakers.register(waker);
+  }
+}
+
+unsafe impl Sync for Signal {}
+
+/// Future returned by `Signal::wait()`.
+pub struct WaitSignal {
+  signal: &'static Signal,
+}
+
+impl Future for WaitSignal {
+  type Output = ();
+
+  fn poll(&mut self, waker: &Waker) -> Poll<()> {
+    if self.signal.take(waker) { Ready(()) } else { Pending }
+  }
+}
+
+/// Waits for a GPIO pin to reach a level.
+///
+/// `edge` must be raised by the pin interrupt handler (GPIOTE, EXTI, etc.)
+/// on every edge of the pin.
+pub struct WaitLevel {
+  pin: &'static Gpio,
+  level: GpioLevel,
+  edge: &'static Signal,
+}
+
+impl Future for WaitLevel {
+  type Output = ();
+
+  fn poll(&mut self, waker: &Waker) -> Poll<()> {
+    // Register before reading the level, so an edge in between isn't lost.
+    // An edge raised before this poll must not stand in for the registration.
+    self.edge.register(waker);
+    if self.pin.level() == self.level {
+      Ready(())
+    } else {
+      Pending
+    }
+  }
+}
+
+/// Returns a future that completes when `pin` is at `level`.
+pub fn wait_for_level(pin: &'static Gpio, level: GpioLevel,
+    edge: &'static Signal) -> WaitLevel {
+  WaitLevel { pin: pin, level: level, edge: edge }
+}
+
+/// Receive buffer size of `UartRx`.
+pub const UartRxBufferSize: usize = 16;
+
+/// Interrupt-fed UART receive buffer.
+///
+/// The application's UART receive interrupt handler passes every byte to
+/// `push()`, tasks await them with `read()`. Bytes received while the buffer
+/// is full are dropped.
+pub struct UartRx {
+  buf: UnsafeCell<[u8; UartRxBufferSize]>,
+  head: UnsafeCell<usize>,
+  len: UnsafeCell<usize>,
+  wakers: WakerSlot,
+}
+
+impl UartRx {
+  /// Creates an empty receive buffer.
+  pub const fn new() -> UartRx {
+    UartRx {
+      buf: UnsafeCell::new([0; UartRxBufferSize]),
+      head: UnsafeCell::new(0),
+      len: UnsafeCell::new(0),
+      wakers: WakerSlot::new(),
+    }
+  }
+
+  /// Stores a received byte, call this from the UART interrupt handler.
+  /// Returns false if the byte was dropped.
+  pub fn push(&self, byte: u8) -> bool {
+    {
+      let _crit = NoInterrupts::new();
+      unsafe {
+        if *self.len.get() == UartRxBufferSize {
+          return false;
+        }
+        let tail = (*self.head.get() + *self.len.get()) % UartRxBufferSize;
+        (*self.buf.get())[tail] = byte;
+        *self.len.get() += 1;
+      }
+    }
+    self.wakers.wake();
+    true
+  }
+
+  /// Returns a future that completes with the next received byte.
+  pub fn read(&'static self) -> ReadByte {
+    ReadByte { rx: self }
+  }
+
+  fn pop(&self, waker: &Waker) -> Option<u8> {
+    let _crit = NoInterrupts::new();
+    unsafe {
+      if *self.len.get() == 0 {
+        self.wakers.register(waker);
+        return None;
+      }
+      let byte = (*self.buf.get())[*self.head.get()];
+      *self.head.get() = (*self.head.get() + 1) % UartRxBufferSize;
+      *self.len.get() -= 1;
+      Some(byte)
+    }
+  }
+}
+
+unsafe impl Sync for UartRx {}
+
+/// Future returned by `UartRx::read()`.
+pub struct ReadByte {
+  rx: &'static UartRx,
+}
+
+impl Future for ReadByte {
+  type Output = u8;
+
+  fn poll(&mut self, waker: &Waker) -> Poll<u8> {
+    match self.rx.pop(waker) {
+      Some(byte) => Ready(byte),
+      None => Pending,
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use core::cell::Cell;
+  use core::marker::Sync;
+
+  use hal::pin::{Gpio, GpioDirection, GpioLevel};
+  use super::{Future, Pending, Ready, ReadyMask, Signal, Waker};
+  use super::wait_for_level;
+
+  struct Pin {
+    level: Cell<GpioLevel>,
+  }
+
+  unsafe impl Sync for Pin {}
+
+  impl Gpio for Pin {
+    fn set_high(&self) { self.level.set(GpioLevel::High) }
+    fn set_low(&self) { self.level.set(GpioLevel::Low) }
+    fn level(&self) -> GpioLevel { self.level.get() }
+    fn set_direction(&self, _: GpioDirection) {}
+  }
+
+  static PIN: Pin = Pin { level: Cell::new(GpioLevel::Low) };
+  static EDGE: Signal = Signal::new();
+
+  #[test]
+  fn wait_level_should_register_after_early_edge() {
+    let waker = Waker { mask: 1 << 5 };
+    let mut wait = wait_for_level(&PIN, GpioLevel::High, &EDGE);
+
+    // An unrelated edge, raised before the first poll.
+    EDGE.raise();
+    unsafe { ReadyMask &= !waker.mask };
+    assert!(wait.poll(&waker) == Pending);
+
+    PIN.set_high();
+    EDGE.raise();
+    assert!(unsafe { ReadyMask } & waker.mask != 0);
+    assert!(wait.poll(&waker) == Ready(()));
+  }
+}
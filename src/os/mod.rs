@@ -20,9 +20,17 @@ This module is a higher level abstraction over hardware than hal. It might be
 incompatible direct hal usage in some cases.
 */
 
-// pub mod debug;
+#[macro_use] pub mod debug;
+#[macro_use] pub mod deflog;
 pub mod syscall;
 #[cfg(feature = "multitasking")] pub mod task;
+#[cfg(feature = "multitasking")] pub mod stats;
 pub mod mutex;
 pub mod cond_var;
-pub mod debug;
+#[cfg(any(feature = "cpu_cortex-m0",
+          feature = "cpu_cortex-m3",
+          feature = "cpu_cortex-m4",
+          feature = "cpu_cortex-m7"))]
+pub mod timer;
+pub mod workqueue;
+pub mod executor;
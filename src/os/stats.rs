@@ -0,0 +1,202 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2014 Vladimir "farcaller" Pouzanov <farcaller@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/*!
+Runtime task statistics.
+
+The scheduler accounts run time and context switches for every task. Run time
+is measured with a timer set by `os::task::set_run_time_counter()`, or else
+with the DWT cycle counter. Cores without a cycle counter fall back to core
+cycles counted by SysTick.
+
+`snapshot()` takes a consistent copy of all the task counters, and
+`print_top()` formats it as a `top`-like table:
+
+```ignore
+  ID  STATE       SWITCHES  CPU%  STACK
+   0  running          120    12  180/512
+   1  sleeping          64     1  96/256
+idle  ready            183    87  40/256
+load 13%
+```
+*/
+
+use core::option::Option::{self, Some, None};
+
+use hal::irq::NoInterrupts;
+
+use drivers::chario::CharIO;
+use os::task::{self, Tasks, TaskDescriptor, Status, StackUsage, MaxTasksCount};
+use os::task::{Runnable, Blocked, Sleeping, Exited};
+use util::strconv;
+
+/// Statistics of a single task.
+#[derive(Clone, Copy)]
+pub struct TaskStats {
+  /// Task index, `MaxTasksCount` for the idle task.
+  pub index: usize,
+  /// Task state at the time of the snapshot.
+  pub status: Status,
+  /// True if this task was running when the snapshot was taken.
+  pub running: bool,
+  /// Number of times the task was switched to.
+  pub switches: u32,
+  /// Cumulative run time, in cycles or run time counter units.
+  pub run_time: u64,
+  /// Top of the task stack.
+  pub stack_base: u32,
+  /// Lowest address of the task stack.
+  pub stack_bottom: u32,
+  /// Stack usage of the task.
+  pub stack: StackUsage,
+}
+
+impl TaskStats {
+  fn new(index: usize, td: &TaskDescriptor, running: bool) -> TaskStats {
+    let mut run_time = td.run_time;
+    if running {
+      run_time += task::current_run_time() as u64;
+    }
+    TaskStats {
+      index: index,
+      status: td.status,
+      running: running,
+      switches: td.switches,
+      run_time: run_time,
+      stack_base: td.stack_base,
+      stack_bottom: td.stack_bottom,
+      stack: td.stack_usage(),
+    }
+  }
+}
+
+/// Snapshot of all the task statistics.
+pub struct Stats {
+  /// User tasks, `None` for slots without a defined task.
+  pub tasks: [Option<TaskStats>; MaxTasksCount],
+  /// The idle task.
+  pub idle: TaskStats,
+  /// Sum of run times of all tasks, including the idle task.
+  pub total_run_time: u64,
+}
+
+impl Stats {
+  /// Returns the share of total run time spent by a task, in percent.
+  pub fn run_time_percent(&self, task: &TaskStats) -> u32 {
+    if self.total_run_time == 0 {
+      0
+    } else {
+      (task.run_time * 100 / self.total_run_time) as u32
+    }
+  }
+
+  /// Returns the share of time spent in the idle task, in percent.
+  pub fn idle_percent(&self) -> u32 {
+    self.run_time_percent(&self.idle)
+  }
+
+  /// Returns the CPU load, in percent.
+  pub fn load_percent(&self) -> u32 {
+    if self.total_run_time == 0 {
+      0
+    } else {
+      100 - self.idle_percent()
+    }
+  }
+}
+
+/// Takes a snapshot of statistics of all the tasks.
+///
+/// Run times are in core clock cycles unless a run time counter is set. On
+/// cores without a DWT cycle counter the SysTick fallback only advances while
+/// SysTick runs, so time spent in tickless idle is counted in whole ticks.
+pub fn snapshot() -> Stats {
+  let _crit = NoInterrupts::new();
+  let current = unsafe { Tasks.current_task };
+
+  let mut tasks: [Option<TaskStats>; MaxTasksCount] = [None; MaxTasksCount];
+  let mut total_run_time = 0;
+  for i in 0..task::defined_tasks() {
+    let td = unsafe { &Tasks.tasks[i] };
+    if !td.valid() {
+      continue;
+    }
+    let stats = TaskStats::new(i, td, current == i);
+    total_run_time += stats.run_time;
+    tasks[i] = Some(stats);
+  }
+
+  let idle = TaskStats::new(MaxTasksCount, unsafe { &Tasks.idle_task },
+      current == MaxTasksCount);
+  total_run_time += idle.run_time;
+
+  Stats {
+    tasks: tasks,
+    idle: idle,
+    total_run_time: total_run_time,
+  }
+}
+
+/// Prints a `top`-like table of task statistics.
+pub fn print_top(io: &CharIO) {
+  let stats = snapshot();
+
+  io.puts("  ID  STATE       SWITCHES  CPU%  STACK\n");
+  for task in stats.tasks.iter() {
+    match *task {
+      Some(ref t) => print_task(io, &stats, t),
+      None => {},
+    }
+  }
+  print_task(io, &stats, &stats.idle);
+
+  io.puts("load ");
+  io.puti(stats.load_percent());
+  io.puts("%\n");
+}
+
+fn print_task(io: &CharIO, stats: &Stats, task: &TaskStats) {
+  if task.index == MaxTasksCount {
+    io.puts("idle");
+  } else {
+    put_padded(io, task.index as u32, 4);
+  }
+
+  io.puts("  ");
+  let state = if task.running {
+    "running "
+  } else {
+    match task.status {
+      Runnable => "ready   ",
+      Blocked  => "blocked ",
+      Sleeping => "sleeping",
+      Exited   => "exited  ",
+    }
+  };
+  io.puts(state);
+
+  put_padded(io, task.switches, 12);
+  put_padded(io, stats.run_time_percent(task), 6);
+  io.puts("  ");
+  io.puti(task.stack.high_water_mark);
+  io.putc('/');
+  io.puti(task.stack.size);
+  io.putc('\n');
+}
+
+/// Outputs a decimal number right-aligned to the given width.
+fn put_padded(io: &CharIO, val: u32, width: usize) {
+  io.putnum(val as i64, &strconv::Format::decimal().width(width));
+}
@@ -16,10 +16,262 @@
 /*!
 Syscall interface.
 
-This module provides syscall interface that is implemented in assembly due to
-current rust restrictions (see hal/cortex_m3/sched.S for actual implementation).
+Kernel services are requested with the `svc` instruction. The svc immediate
+selects the service and arguments are passed in r0 and r1. `isr_svcall` (see
+hal/cortex_common/sched.S) hands the stacked exception frame to
+`svc_handler()`, which validates the arguments, runs the service in handler
+mode and writes the result back to the stacked r0.
+
+Tasks defined with `os::task::define_unprivileged_task()` run with
+`CONTROL.nPRIV` set and must use the wrappers in this module instead of
+calling into `os::task` directly.
+
+nPRIV only stops a task from executing privileged instructions (`cps`, `msr`
+to special registers) and from accessing the System Control Space. The MPU
+isn't programmed, so there's no memory isolation: an unprivileged task can
+still read and write all of RAM, including kernel statics and the stacks of
+other tasks.
+
+`syscall()` is the legacy entry point that calls an arbitrary function in
+handler mode, it is only available to privileged callers.
+
+Work items submitted with `submit_work()` must be declared with
+`syscall_work!`, which places them in a table the handler checks the pointer
+against, so a task can't have the kernel run a `Work` it forged or write into
+kernel statics.
 */
 
+#[cfg(any(feature = "multitasking", test))]
+use core::mem::size_of;
+use core::result::Result::{self, Ok, Err};
+
+#[cfg(all(feature = "multitasking", feature = "cpu_cortex-m0"))]
+use hal::cortex_m0::sched;
+#[cfg(all(feature = "multitasking", feature = "cpu_cortex-m3"))]
+use hal::cortex_m3::sched;
+#[cfg(all(feature = "multitasking", feature = "cpu_cortex-m4"))]
+use hal::cortex_m4::sched;
+#[cfg(all(feature = "multitasking", feature = "cpu_cortex-m7"))]
+use hal::cortex_m7::sched;
+
+#[cfg(feature = "multitasking")]
+use os::task::{self, Tasks};
+#[cfg(any(feature = "multitasking", test))]
+use os::workqueue::Work;
+
 extern {
   pub fn syscall(f: fn(u32), arg: u32);
 }
+
+/// Calls a function in handler mode (legacy `syscall()`), privileged only.
+pub const SYS_CALL: u8 = 0;
+/// Gives up the rest of the time slice.
+pub const SYS_YIELD: u8 = 1;
+/// Sleeps for a number of scheduler ticks.
+pub const SYS_SLEEP: u8 = 2;
+/// Terminates the calling task.
+pub const SYS_EXIT: u8 = 3;
+/// Submits an `os::workqueue::Work` item.
+pub const SYS_SUBMIT_WORK: u8 = 4;
+
+/// Syscall errors.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Error {
+  /// Unknown service number.
+  NoSuchCall = 1,
+  /// An argument failed validation.
+  InvalidArgument = 2,
+  /// The caller isn't allowed to use the service.
+  PermissionDenied = 3,
+  /// The service must be called from a task.
+  NotInTask = 4,
+}
+
+impl Error {
+  fn from_code(code: u32) -> Result<(), Error> {
+    match code {
+      0 => Ok(()),
+      2 => Err(Error::InvalidArgument),
+      3 => Err(Error::PermissionDenied),
+      4 => Err(Error::NotInTask),
+      _ => Err(Error::NoSuchCall),
+    }
+  }
+}
+
+/// Issues `svc` with one of the `SYS_*` numbers as the immediate.
+#[cfg(target_arch = "arm")]
+macro_rules! svc {
+  ($nr:expr, $a0:expr, $a1:expr) => ({
+    let ret: u32;
+    unsafe {
+      asm!("svc $1" : "={r0}"(ret) : "i"($nr), "{r0}"($a0), "{r1}"($a1) : "memory" : "volatile")
+    };
+    ret
+  })
+}
+
+#[cfg(not(target_arch = "arm"))]
+macro_rules! svc {
+  ($nr:expr, $a0:expr, $a1:expr) => ({
+    let _ = ($nr, $a0, $a1);
+    unimplemented!()
+  })
+}
+
+/// Gives up the rest of the time slice of the calling task.
+#[cfg(feature = "multitasking")]
+pub fn yield_now() -> Result<(), Error> {
+  Error::from_code(svc!(SYS_YIELD, 0u32, 0u32))
+}
+
+/// Puts the calling task to sleep for a number of scheduler ticks.
+#[cfg(feature = "multitasking")]
+pub fn sleep(ticks: u32) -> Result<(), Error> {
+  Error::from_code(svc!(SYS_SLEEP, ticks, 0u32))
+}
+
+/// Terminates the calling task with an exit code, which is returned by
+/// `os::task::join()`.
+#[cfg(feature = "multitasking")]
+pub fn exit(code: u32) -> ! {
+  svc!(SYS_EXIT, code, 0u32);
+  loop {}
+}
+
+/// Queues a work item from an unprivileged task, see `Work::submit()`.
+///
+/// Returns `InvalidArgument` if the work is already pending or wasn't
+/// declared with `syscall_work!`.
+#[cfg(feature = "multitasking")]
+pub fn submit_work(work: &'static Work, arg: u32) -> Result<(), Error> {
+  Error::from_code(svc!(SYS_SUBMIT_WORK, work as *const Work as u32, arg))
+}
+
+/// Declares a static `os::workqueue::Work` that unprivileged tasks may submit
+/// with `submit_work()`.
+///
+/// ```ignore
+/// syscall_work!(BLINK_WORK, blink);
+/// ```
+#[macro_export]
+macro_rules! syscall_work {
+  ($name:ident, $func:expr) => (
+    #[link_section = ".syscall_work"]
+    static $name: $crate::os::workqueue::Work = $crate::os::workqueue::Work::new($func);
+  );
+  (pub $name:ident, $func:expr) => (
+    #[link_section = ".syscall_work"]
+    pub static $name: $crate::os::workqueue::Work = $crate::os::workqueue::Work::new($func);
+  );
+}
+
+/// Stacked exception frame of the svc caller.
+#[cfg(feature = "multitasking")]
+pub type Frame = sched::SavedState;
+
+#[cfg(feature = "multitasking")]
+extern {
+  static _syscall_work: u32;
+  static _esyscall_work: u32;
+}
+
+/// Returns true if `addr` is the start of an item in the table of `Work`
+/// items between `start` and `end`.
+#[cfg(any(feature = "multitasking", test))]
+fn in_work_table(addr: usize, start: usize, end: usize) -> bool {
+  let size = size_of::<Work>();
+  addr >= start && addr < end && end - addr >= size && (addr - start) % size == 0
+}
+
+/// Returns true if `addr` points to a `Work` declared with `syscall_work!`.
+#[cfg(feature = "multitasking")]
+fn registered_work(addr: u32) -> bool {
+  let start = unsafe { &_syscall_work as *const u32 as usize };
+  let end = unsafe { &_esyscall_work as *const u32 as usize };
+  in_work_table(addr as usize, start, end)
+}
+
+/// Decodes and dispatches a system call, called from `isr_svcall`.
+///
+/// `exc_return` is the EXC_RETURN value of the exception, which tells if the
+/// caller was a task (running on PSP) or privileged code on the main stack.
+#[cfg(feature = "multitasking")]
+pub unsafe fn svc_handler(frame: *mut Frame, exc_return: u32) {
+  let frame = &mut *frame;
+  // The svc immediate is the low byte of the instruction before the return
+  // address.
+  let nr = *((frame.pc - 2) as *const u8);
+  let in_task = exc_return & 4 != 0;
+  let privileged = !in_task || Tasks.current_task().privileged;
+
+  let result = match nr {
+    SYS_CALL if privileged => {
+      let f: fn(u32) = ::core::mem::transmute(frame.r0);
+      f(frame.r1);
+      Ok(())
+    },
+    SYS_CALL => Err(Error::PermissionDenied),
+    SYS_YIELD | SYS_SLEEP | SYS_EXIT if !in_task => Err(Error::NotInTask),
+    SYS_YIELD => {
+      sched::switch_context();
+      Ok(())
+    },
+    SYS_SLEEP => {
+      task::sleep(frame.r0);
+      Ok(())
+    },
+    SYS_EXIT => {
+      task::exit_current_task(frame.r0);
+      Ok(())
+    },
+    SYS_SUBMIT_WORK => {
+      if !registered_work(frame.r0) {
+        Err(Error::InvalidArgument)
+      } else {
+        let work = &*(frame.r0 as *const Work);
+        if work.submit(frame.r1) {
+          Ok(())
+        } else {
+          Err(Error::InvalidArgument)
+        }
+      }
+    },
+    _ => Err(Error::NoSuchCall),
+  };
+
+  frame.r0 = match result {
+    Ok(()) => 0,
+    Err(e) => e as u32,
+  };
+}
+
+#[cfg(test)]
+mod test {
+  use core::mem::size_of;
+
+  use os::workqueue::Work;
+  use super::in_work_table;
+
+  fn noop(_: u32) {}
+
+  #[test]
+  fn forged_work_should_be_rejected() {
+    // Stands for the .syscall_work section.
+    static TABLE: [Work; 2] = [Work::new(noop), Work::new(noop)];
+    // Built by a task, outside of the table.
+    static FORGED: Work = Work::new(noop);
+    // A kernel static the forged pointer could alias.
+    static KERNEL: [u32; 8] = [0; 8];
+
+    let start = &TABLE as *const _ as usize;
+    let end = start + 2 * size_of::<Work>();
+    assert!(in_work_table(&TABLE[0] as *const Work as usize, start, end));
+    assert!(in_work_table(&TABLE[1] as *const Work as usize, start, end));
+
+    assert!(!in_work_table(&FORGED as *const Work as usize, start, end));
+    assert!(!in_work_table(&KERNEL as *const _ as usize, start, end));
+    assert!(!in_work_table(start + 4, start, end));
+    assert!(!in_work_table(end, start, end));
+  }
+}
@@ -18,10 +18,19 @@
 use core::mem::size_of;
 use core::intrinsics::abort;
 
-use hal::cortex_m3::{sched, systick};
-use hal::cortex_m3::sched::NoInterrupts;
-use os::syscall::syscall;
+#[cfg(feature = "cpu_cortex-m0")]
+use hal::cortex_m0::{sched, systick, scb};
+#[cfg(feature = "cpu_cortex-m3")]
+use hal::cortex_m3::{sched, systick, scb, dwt};
+#[cfg(feature = "cpu_cortex-m4")]
+use hal::cortex_m4::{sched, systick, scb, dwt};
+#[cfg(feature = "cpu_cortex-m7")]
+use hal::cortex_m7::{sched, systick, scb, dwt};
+use hal::irq::NoInterrupts;
+use os::syscall;
 use hal::stack;
+use hal::timer::{Timer, WakeupTimer};
+use util::support::wfi;
 
 /// Task takes one argument, which is u32.
 pub type Task = fn(u32);
@@ -43,7 +52,241 @@ mod current_stack_offset {
 static ReservedPivilegedStackSize: u32 = 256;
 
 /// Maximum number of tasks.
-static MaxTasksCount: usize = 4;
+pub const MaxTasksCount: usize = 4;
+
+/// Number of reusable stack slots for tasks created with `spawn()`.
+pub const StackPoolSlots: usize = 4;
+
+/// Exit code of a task killed on stack overflow.
+pub const KilledExitCode: u32 = 0xFFFF_FFFF;
+
+/// Index of the idle task in TasksCollection::current_task.
+const IdleTaskIndex: usize = MaxTasksCount;
+
+/// Stack size of the idle task.
+static IdleTaskStackSize: u32 = 256;
+
+mod ticks {
+  /// Scheduler ticks since task::setup(), wrapping around.
+  static mut Ticks: u32 = 0;
+
+  pub fn get() -> u32 {
+    unsafe { Ticks }
+  }
+
+  pub fn advance(val: u32) {
+    unsafe { Ticks = Ticks.wrapping_add(val) };
+  }
+}
+
+mod wakeup_timer {
+  use hal::timer::WakeupTimer;
+
+  /// Low-power timer used to wake up from tickless idle.
+  static mut Timer: Option<&'static WakeupTimer> = None;
+  /// Whether tickless idle should use deep sleep.
+  static mut DeepSleep: bool = false;
+
+  pub fn get() -> Option<&'static WakeupTimer> {
+    unsafe { Timer }
+  }
+
+  pub fn deep_sleep() -> bool {
+    unsafe { DeepSleep }
+  }
+
+  pub fn set(timer: &'static WakeupTimer, deep_sleep: bool) {
+    unsafe {
+      Timer = Some(timer);
+      DeepSleep = deep_sleep;
+    }
+  }
+}
+
+mod privileged_stack {
+  /// Bounds of the privileged stack, as defined in task::setup().
+  static mut PrivilegedStackBottom: u32 = 0;
+  static mut PrivilegedStackTop: u32 = 0;
+
+  pub fn get() -> (u32, u32) {
+    unsafe { (PrivilegedStackBottom, PrivilegedStackTop) }
+  }
+
+  pub fn set(bottom: u32, top: u32) {
+    unsafe {
+      PrivilegedStackBottom = bottom;
+      PrivilegedStackTop = top;
+    }
+  }
+}
+
+mod stack_warning_threshold {
+  /// Stack usage, in percent, that triggers a warning in check_stack_usage().
+  static mut StackWarningThreshold: u32 = 90;
+
+  pub fn get() -> u32 {
+    unsafe { StackWarningThreshold }
+  }
+
+  pub fn set(val: u32) {
+    unsafe { StackWarningThreshold = val };
+  }
+}
+
+mod run_time_clock {
+  use hal::timer::Timer;
+
+  /// Timer used to measure task run time instead of the cycle counter.
+  static mut Counter: Option<&'static Timer> = None;
+  /// SysTick reload value if run time falls back to SysTick, 0 if the cycle
+  /// counter is used.
+  static mut SysTickReload: u32 = 0;
+  /// Run time clock value at the last context switch.
+  static mut LastSwitch: u32 = 0;
+
+  pub fn set_counter(timer: &'static Timer) {
+    unsafe {
+      Counter = Some(timer);
+      LastSwitch = timer.get_counter();
+    }
+  }
+
+  /// Starts the cycle counter, or falls back to counting SysTick cycles on
+  /// cores without one.
+  pub fn start(reload: u32) {
+    unsafe {
+      if !super::start_cycle_counter() {
+        SysTickReload = reload;
+      }
+      LastSwitch = now();
+    }
+  }
+
+  fn now() -> u32 {
+    match unsafe { (Counter, SysTickReload) } {
+      (Some(timer), _) => timer.get_counter(),
+      (None, 0)        => super::cycle_count(),
+      (None, reload)   => super::systick_count(reload),
+    }
+  }
+
+  /// Returns the time elapsed since the last context switch.
+  pub fn elapsed() -> u32 {
+    now().wrapping_sub(unsafe { LastSwitch })
+  }
+
+  /// Returns the time elapsed since the last context switch and restarts the
+  /// measurement.
+  pub fn lap() -> u32 {
+    let now = now();
+    let elapsed = now.wrapping_sub(unsafe { LastSwitch });
+    unsafe { LastSwitch = now };
+    elapsed
+  }
+}
+
+/// Starts the DWT cycle counter, returns false if the core doesn't have one.
+#[cfg(not(feature = "cpu_cortex-m0"))]
+fn start_cycle_counter() -> bool {
+  dwt::enable_cycle_counter()
+}
+
+#[cfg(feature = "cpu_cortex-m0")]
+fn start_cycle_counter() -> bool {
+  false
+}
+
+#[cfg(not(feature = "cpu_cortex-m0"))]
+fn cycle_count() -> u32 {
+  dwt::cycle_count()
+}
+
+/// ARMv6-M has no cycle counter, run time is counted in SysTick cycles
+/// instead.
+#[cfg(feature = "cpu_cortex-m0")]
+fn cycle_count() -> u32 {
+  0
+}
+
+/// Returns the core clock cycles counted by SysTick since task::setup(),
+/// wrapping around.
+///
+/// A SysTick that wrapped but wasn't handled yet is accounted for from its
+/// pending state. Time spent in tickless idle only has tick resolution.
+fn systick_count(reload: u32) -> u32 {
+  loop {
+    let pending = scb::systick_pending();
+    let current = systick::get_current();
+    if pending == scb::systick_pending() {
+      let ticks = ticks::get().wrapping_add(pending as u32);
+      return ticks.wrapping_mul(reload + 1).wrapping_add(reload - current);
+    }
+  }
+}
+
+mod stack_pool {
+  use core::option::Option::{self, Some, None};
+  use super::StackPoolSlots;
+
+  /// Usable stack size of every slot, 0 until `setup_stack_pool()`.
+  static mut StackSize: u32 = 0;
+  /// Stack base (top) of every slot.
+  static mut SlotBases: [u32; StackPoolSlots] = [0; StackPoolSlots];
+  /// Set for slots owned by a running task.
+  static mut SlotUsed: [bool; StackPoolSlots] = [false; StackPoolSlots];
+  /// Set for slots of tasks that exited but may still be running on them.
+  static mut SlotExited: [bool; StackPoolSlots] = [false; StackPoolSlots];
+
+  pub fn stack_size() -> u32 {
+    unsafe { StackSize }
+  }
+
+  pub fn set(stack_size: u32, bases: [u32; StackPoolSlots]) {
+    unsafe {
+      StackSize = stack_size;
+      SlotBases = bases;
+    }
+  }
+
+  /// Takes a free slot, returns its index and stack base.
+  pub fn acquire() -> Option<(usize, u32)> {
+    unsafe {
+      if StackSize == 0 {
+        return None;
+      }
+      for i in 0..StackPoolSlots {
+        if !SlotUsed[i] {
+          SlotUsed[i] = true;
+          return Some((i, SlotBases[i]));
+        }
+      }
+    }
+    None
+  }
+
+  /// Marks the slot of the exiting task, it's released by `release_exited()`
+  /// once the scheduler switched away from it.
+  pub fn release_on_switch(slot: usize) {
+    unsafe { SlotExited[slot] = true };
+  }
+
+  /// Releases the slots of exited tasks, called from the scheduler after the
+  /// next task is loaded.
+  pub fn release_exited() {
+    unsafe {
+      for i in 0..StackPoolSlots {
+        if SlotExited[i] {
+          SlotExited[i] = false;
+          SlotUsed[i] = false;
+        }
+      }
+    }
+  }
+
+  pub fn release(slot: usize) {
+    unsafe { SlotUsed[slot] = false };
+  }
+}
 
 mod defined_tasks_count {
   use core::intrinsics::abort;
@@ -65,15 +308,46 @@ mod defined_tasks_count {
   }
 }
 
+pub use self::Status::*;
+
+#[derive(Clone, Copy, PartialEq)]
 pub enum Status {
   Runnable,
-  Blocked
+  Blocked,
+  /// Waiting for the tick count to reach `TaskDescriptor::wake_at`.
+  Sleeping,
+  /// Finished, waiting for `join()` to collect `TaskDescriptor::exit_code`.
+  Exited
 }
 
 /// Task descriptor, provides task stack pointer.
+#[derive(Clone, Copy)]
 pub struct TaskDescriptor {
   pub stack_start: u32,
   pub stack_end: u32,
+  /// Highest address of the task stack (exclusive).
+  pub stack_base: u32,
+  /// Lowest address of the memory allocated for the task stack, including
+  /// the scratch pad below `stack_end`.
+  pub stack_bottom: u32,
+  /// Set once a stack usage warning has been reported for this task.
+  pub stack_warned: bool,
+  /// Tick count at which a `Sleeping` task becomes runnable again.
+  pub wake_at: u32,
+  /// Number of times the task was switched to.
+  pub switches: u32,
+  /// Cumulative run time, in cycles or run time counter units.
+  pub run_time: u64,
+  /// Set if the task runs in privileged thread mode.
+  pub privileged: bool,
+  /// Stack pool slot owned by the task, if it was created with `spawn()`.
+  pub stack_slot: Option<usize>,
+  /// Exit code of an `Exited` task.
+  pub exit_code: u32,
+  /// Index of the task blocked in `join()` on this task.
+  pub joiner: Option<usize>,
+  /// Set if the descriptor is released on exit, without a `join()`.
+  pub detached: bool,
   pub status: Status
 }
 
@@ -83,40 +357,159 @@ impl TaskDescriptor {
     sched::switch_context();
   }
   pub fn unblock(&mut self, _: &NoInterrupts) { self.status = Runnable; }
+
+  /// Returns true if the task can be scheduled.
+  pub fn runnable(&self) -> bool {
+    match self.status {
+      Runnable => self.valid(),
+      _        => false,
+    }
+  }
+
+  /// Returns the number of ticks left until a sleeping task wakes up, or
+  /// `None` if it's not sleeping.
+  fn ticks_to_wakeup(&self, now: u32) -> Option<u32> {
+    match self.status {
+      Sleeping if self.valid() => {
+        let left = self.wake_at.wrapping_sub(now) as i32;
+        Some(if left > 0 { left as u32 } else { 0 })
+      },
+      _ => None,
+    }
+  }
 }
 
 struct TasksCollection {
   pub current_task: usize,
+  /// Task to switch to next, ahead of the round-robin order.
+  pub urgent_task: Option<usize>,
   pub tasks: [TaskDescriptor; MaxTasksCount],
+  /// Task that runs when no other task is runnable.
+  pub idle_task: TaskDescriptor,
 }
 
 pub static mut Tasks: TasksCollection = TasksCollection {
   current_task: 0,
-  tasks: [TaskDescriptor { stack_start: 0, stack_end: 0, status: Runnable }; MaxTasksCount]
+  urgent_task: None,
+  tasks: [TaskDescriptor {
+    stack_start: 0,
+    stack_end: 0,
+    stack_base: 0,
+    stack_bottom: 0,
+    stack_warned: false,
+    wake_at: 0,
+    switches: 0,
+    run_time: 0,
+    privileged: true,
+    stack_slot: None,
+    exit_code: 0,
+    joiner: None,
+    detached: false,
+    status: Runnable
+  }; MaxTasksCount],
+  idle_task: TaskDescriptor {
+    stack_start: 0,
+    stack_end: 0,
+    stack_base: 0,
+    stack_bottom: 0,
+    stack_warned: false,
+    wake_at: 0,
+    switches: 0,
+    run_time: 0,
+    privileged: true,
+    stack_slot: None,
+    exit_code: 0,
+    joiner: None,
+    detached: false,
+    status: Runnable
+  },
 };
 
 impl TasksCollection {
   pub fn current_task<'a>(&'a mut self) -> &'a mut TaskDescriptor {
-    &mut self.tasks[self.current_task]
+    if self.current_task == IdleTaskIndex {
+      &mut self.idle_task
+    } else {
+      &mut self.tasks[self.current_task]
+    }
   }
 
+  /// Switches to the urgent task if there's one, otherwise to the next
+  /// runnable task in round-robin order, falling back to the idle task if
+  /// there are none.
   fn next_task(&mut self) {
-    loop {
-      self.current_task += 1;
-      if self.current_task == defined_tasks_count::get() {
-        self.current_task = 0;
+    let count = defined_tasks_count::get();
+    match self.urgent_task.take() {
+      Some(index) if index < count && self.tasks[index].runnable() => {
+        self.current_task = index;
+        return;
+      },
+      _ => {},
+    }
+    if count == 0 {
+      self.current_task = IdleTaskIndex;
+      return;
+    }
+    let mut index = if self.current_task == IdleTaskIndex {
+      count - 1
+    } else {
+      self.current_task
+    };
+
+    for _ in 0..count {
+      index += 1;
+      if index == count {
+        index = 0;
       }
-      match self.current_task() {
-        &task if !task.valid()                 => {}
-        &TaskDescriptor {status: Runnable, ..} => break,
-        _                                      => {}
+      if self.tasks[index].runnable() {
+        self.current_task = index;
+        return;
+      }
+    }
+
+    self.current_task = IdleTaskIndex;
+  }
+
+  /// Makes all the sleeping tasks due at `now` runnable. Returns true if any
+  /// task was woken up.
+  fn wake_sleeping(&mut self, now: u32) -> bool {
+    let mut woken = false;
+    for i in 0..defined_tasks_count::get() {
+      let task = &mut self.tasks[i];
+      if task.ticks_to_wakeup(now) == Some(0) {
+        task.status = Runnable;
+        woken = true;
       }
     }
+    woken
   }
 
-  fn add_task(&mut self, t: TaskDescriptor) {
-    self.tasks[defined_tasks_count::get()] = t;
+  /// Returns the number of ticks until the earliest sleeping task wakes up, or
+  /// `None` if there are no sleeping tasks.
+  fn next_wakeup(&self, now: u32) -> Option<u32> {
+    let mut earliest = None;
+    for i in 0..defined_tasks_count::get() {
+      match (self.tasks[i].ticks_to_wakeup(now), earliest) {
+        (Some(t), None)               => earliest = Some(t),
+        (Some(t), Some(e)) if t < e   => earliest = Some(t),
+        _                             => {},
+      }
+    }
+    earliest
+  }
+
+  /// Stores a new task in the first free slot and returns its index.
+  fn add_task(&mut self, t: TaskDescriptor) -> usize {
+    for i in 0..defined_tasks_count::get() {
+      if self.tasks[i].free() {
+        self.tasks[i] = t;
+        return i;
+      }
+    }
+    let index = defined_tasks_count::get();
     defined_tasks_count::increase();
+    self.tasks[index] = t;
+    index
   }
 }
 
@@ -126,9 +519,28 @@ impl TasksCollection {
 /// never returns.
 ///
 /// t should point to initial task.
+///
+/// The scheduler ticks every 10ms, as calibrated by the core. Aborts if the
+/// core doesn't provide a SysTick calibration value, use
+/// `setup_with_core_clock` there.
 #[inline(never)]
 pub fn setup(t: Task, stack_size: u32) {
-  systick::setup(::hal::cortex_m3::systick::CALIBRATED, true);
+  match systick::ten_ms() {
+    Some(reload) => start(t, stack_size, reload),
+    None => unsafe { abort() },
+  }
+}
+
+/// Initialize and start task manager with a 10ms tick derived from the core
+/// clock frequency in Hz, for cores without a SysTick calibration value.
+#[inline(never)]
+pub fn setup_with_core_clock(t: Task, stack_size: u32, core_clock: u32) {
+  start(t, stack_size, core_clock / 100 - 1)
+}
+
+fn start(t: Task, stack_size: u32, reload: u32) {
+  systick::setup(reload);
+  systick::enable_irq();
 
   let current_stack = sched::get_current_stack_pointer();
   // User tasks start at this current stack size + reserved size aligned by 4
@@ -136,10 +548,17 @@ pub fn setup(t: Task, stack_size: u32) {
   let task_stack_base: u32 = (current_stack as u32 - ReservedPivilegedStackSize) & !3;
   current_stack_offset::set(task_stack_base);
 
+  // Everything below the current stack pointer is free to be painted, the
+  // privileged stack keeps growing down from here in handler mode.
+  unsafe { stack::paint(task_stack_base, current_stack & !3) };
+  privileged_stack::set(task_stack_base, current_stack & !3);
+
   let td = define_task(t, 0, stack_size, true);
+  unsafe { Tasks.idle_task = allocate_task(idle, 0, IdleTaskStackSize, false) };
 
   td.load();
 
+  run_time_clock::start(reload);
   systick::enable();
   sched::switch_context();
 
@@ -149,22 +568,259 @@ pub fn setup(t: Task, stack_size: u32) {
 #[inline(never)]
 pub fn define_task(t: Task, arg: u32, stack_size: u32, initial: bool) -> TaskDescriptor {
   systick::disable_irq();
-  let task_base = current_stack_offset::get();
-  let task_stack_size: u32 = (
+  let td = allocate_task(t, arg, stack_size, initial);
+  unsafe { Tasks.add_task(td) };
+  systick::enable_irq();
+  td
+}
+
+/// Defines an unprivileged task.
+///
+/// The task runs with `CONTROL.nPRIV` set, so it can't disable interrupts or
+/// access system registers and must use the services in `os::syscall` instead
+/// of the `os::task` functions. On Cortex-M0 the task still runs privileged.
+///
+/// This gives no memory isolation, the MPU isn't programmed and the task can
+/// write anywhere in RAM.
+#[inline(never)]
+pub fn define_unprivileged_task(t: Task, arg: u32, stack_size: u32) -> TaskDescriptor {
+  systick::disable_irq();
+  let mut td = allocate_task(t, arg, stack_size, false);
+  td.privileged = false;
+  unsafe { Tasks.add_task(td) };
+  systick::enable_irq();
+  td
+}
+
+/// Returns the memory required for a task stack of a given size.
+fn stack_allocation_size(stack_size: u32) -> u32 {
+  (
     stack_size +
     8*4 +  // hw saved regs
     8*4 +  // sw saved regs
     8*4    // scratch pad for __morestack failure. see note on morestack below.
-  ) & !0b1111;
-  current_stack_offset::set(task_base - task_stack_size);
+  ) & !0b1111
+}
 
-  let td = TaskDescriptor::new(t, arg, task_base, stack_size, initial);
-  unsafe { Tasks.add_task(td) };
+/// Allocates and paints a stack for a new task and creates its descriptor.
+fn allocate_task(t: Task, arg: u32, stack_size: u32, initial: bool) -> TaskDescriptor {
+  let task_base = current_stack_offset::get();
+  let task_bottom = task_base - stack_allocation_size(stack_size);
+  current_stack_offset::set(task_bottom);
 
-  systick::enable_irq();
+  unsafe { stack::paint(task_bottom, task_base) };
+  let mut td = TaskDescriptor::new(t, arg, task_base, stack_size, initial);
+  td.stack_bottom = task_bottom;
   td
 }
 
+/// Allocates `StackPoolSlots` reusable stacks of `stack_size` bytes for
+/// `spawn()`.
+///
+/// Stacks are carved out of the task stack area like the ones of
+/// `define_task()`, so the pool should be set up once, early in the initial
+/// task.
+pub fn setup_stack_pool(stack_size: u32) {
+  if stack_pool::stack_size() != 0 {
+    return;
+  }
+  let mut bases = [0; StackPoolSlots];
+  for i in 0..StackPoolSlots {
+    bases[i] = current_stack_offset::get();
+    current_stack_offset::set(bases[i] - stack_allocation_size(stack_size));
+  }
+  stack_pool::set(stack_size, bases);
+}
+
+/// Starts a task on a stack from the pool set up with `setup_stack_pool()`.
+///
+/// Returns the task index, or `None` if there's no free stack slot or task
+/// descriptor. The stack slot is returned to the pool when the task exits,
+/// the descriptor once the task is joined or if it's detached.
+pub fn spawn(t: Task, arg: u32) -> Option<usize> {
+  let _crit = NoInterrupts::new();
+  let (slot, base) = match stack_pool::acquire() {
+    Some(s) => s,
+    None => return None,
+  };
+  unsafe {
+    if defined_tasks_count::get() == MaxTasksCount &&
+        !Tasks.tasks.iter().any(|td| td.free()) {
+      stack_pool::release(slot);
+      return None;
+    }
+  }
+
+  let stack_size = stack_pool::stack_size();
+  let bottom = base - stack_allocation_size(stack_size);
+  unsafe { stack::paint(bottom, base) };
+  let mut td = TaskDescriptor::new(t, arg, base, stack_size, false);
+  td.stack_bottom = bottom;
+  td.stack_slot = Some(slot);
+  Some(unsafe { Tasks.add_task(td) })
+}
+
+/// Terminates the current task with an exit code, see `os::syscall::exit()`.
+pub fn exit(code: u32) -> ! {
+  syscall::exit(code)
+}
+
+/// Waits for a task to exit and returns its exit code.
+///
+/// The task descriptor is released once joined. Returns `None` if there's no
+/// such task, it's detached, or another task is already joining it.
+pub fn join(index: usize) -> Option<u32> {
+  loop {
+    let crit = NoInterrupts::new();
+    unsafe {
+      if index >= defined_tasks_count::get() || index == Tasks.current_task {
+        return None;
+      }
+      {
+        let td = &mut Tasks.tasks[index];
+        if td.status == Exited {
+          td.status = Runnable;
+          return Some(td.exit_code);
+        }
+        if !td.valid() || td.detached || td.joiner.is_some() {
+          return None;
+        }
+        td.joiner = Some(Tasks.current_task);
+      }
+      Tasks.current_task().block(crit);
+    }
+  }
+}
+
+/// Releases the task descriptor automatically when the task exits.
+pub fn detach(index: usize) {
+  let _crit = NoInterrupts::new();
+  if index >= defined_tasks_count::get() {
+    return;
+  }
+  let td = unsafe { &mut Tasks.tasks[index] };
+  if td.status == Exited {
+    td.status = Runnable;
+  } else if td.valid() {
+    td.detached = true;
+  }
+}
+
+/// Sets a timer to measure task run time with, instead of the DWT cycle
+/// counter.
+///
+/// Without it, cores without a cycle counter (Cortex-M0) measure run time in
+/// SysTick cycles. A timer is also useful if the cycle counter wraps too fast.
+pub fn set_run_time_counter(timer: &'static Timer) {
+  run_time_clock::set_counter(timer);
+}
+
+/// Returns the run time of the current task since it was last switched to.
+pub fn current_run_time() -> u32 {
+  run_time_clock::elapsed()
+}
+
+/// Returns the index of the current task.
+pub fn current_task_index() -> usize {
+  unsafe { Tasks.current_task }
+}
+
+/// Makes a blocked task runnable. Returns false if there's no such task.
+pub fn wake(index: usize, crit: &NoInterrupts) -> bool {
+  unsafe {
+    if index >= defined_tasks_count::get() || !Tasks.tasks[index].valid() {
+      return false;
+    }
+    Tasks.tasks[index].unblock(crit);
+  }
+  true
+}
+
+/// Makes a blocked task runnable and switches to it as soon as the current
+/// interrupt handler returns, or right away from thread mode, instead of
+/// waiting for its round-robin turn.
+pub fn wake_urgent(index: usize, crit: &NoInterrupts) {
+  if wake(index, crit) {
+    unsafe { Tasks.urgent_task = Some(index) };
+    sched::switch_context();
+  }
+}
+
+/// Returns the number of tasks defined with `define_task()`.
+pub fn defined_tasks() -> usize {
+  defined_tasks_count::get()
+}
+
+/// Returns the number of scheduler ticks since `setup()`.
+pub fn ticks() -> u32 {
+  ticks::get()
+}
+
+/// Puts the current task to sleep for at least `ticks` scheduler ticks.
+pub fn sleep(ticks: u32) {
+  let _crit = NoInterrupts::new();
+  unsafe {
+    let task = Tasks.current_task();
+    task.wake_at = ticks::get().wrapping_add(ticks);
+    task.status = Sleeping;
+  }
+  sched::switch_context();
+}
+
+/// Enables tickless idle mode.
+///
+/// When all tasks are blocked or sleeping, the idle task stops SysTick and
+/// programs `timer` to wake the core up when the earliest sleeping task is
+/// due. If `deep_sleep` is set, `wfi` enters deep sleep via `scb.scr`. The
+/// tick count is compensated by the number of ticks the timer reports on
+/// wake-up, SysTick resumes its partial tick where it stopped.
+///
+/// Only nrf51822 provides a `WakeupTimer`, `hal::nrf51822::rtc::RtcWakeup`.
+/// Other targets need to implement it for a timer that keeps running in
+/// sleep, such as the STM32L1 RTC wakeup timer.
+pub fn set_wakeup_timer(timer: &'static WakeupTimer, deep_sleep: bool) {
+  wakeup_timer::set(timer, deep_sleep);
+}
+
+/// Idle task, runs when no other task is runnable.
+///
+/// Interrupts are disabled around `wfi`, so that an interrupt that makes a
+/// task runnable can't slip in between the check and the sleep. Pending
+/// interrupts still wake the core up and are handled once the critical
+/// section ends.
+fn idle(_: u32) {
+  loop {
+    let _crit = NoInterrupts::new();
+    let timeout = unsafe { Tasks.next_wakeup(ticks::get()) };
+    match (wakeup_timer::get(), timeout) {
+      (Some(timer), None) => tickless_sleep(timer, timer.max_ticks()),
+      (Some(timer), Some(t)) if t > 1 => {
+        let max = timer.max_ticks();
+        tickless_sleep(timer, if t > max { max } else { t });
+      },
+      _ => wfi(),
+    }
+  }
+}
+
+/// Sleeps for up to `ticks` with SysTick stopped, then compensates the tick
+/// count for the time spent asleep.
+fn tickless_sleep(timer: &WakeupTimer, ticks: u32) {
+  systick::disable();
+  timer.start_wakeup(ticks);
+
+  scb::set_sleepdeep(wakeup_timer::deep_sleep());
+  wfi();
+  scb::set_sleepdeep(false);
+
+  let elapsed = timer.stop_wakeup();
+  ticks::advance(elapsed);
+  if unsafe { Tasks.wake_sleeping(ticks::get()) } {
+    sched::switch_context();
+  }
+  systick::enable();
+}
+
 impl TaskDescriptor {
   /// Creates a new TaskDescriptor for given task, arg and stack base.
   ///
@@ -182,11 +838,31 @@ impl TaskDescriptor {
     TaskDescriptor {
       stack_start: stack_top,
       stack_end: stack_base - stack_size,
+      stack_base: stack_base,
+      stack_bottom: stack_base - stack_size,
+      stack_warned: false,
+      wake_at: 0,
+      switches: 0,
+      run_time: 0,
+      privileged: true,
+      stack_slot: None,
+      exit_code: 0,
+      joiner: None,
+      detached: false,
       status: Runnable,
     }
   }
 
+  /// Returns the stack usage of this task, based on the painted pattern.
+  pub fn stack_usage(&self) -> StackUsage {
+    StackUsage {
+      size: self.stack_base - self.stack_bottom,
+      high_water_mark: stack::high_water_mark(self.stack_bottom, self.stack_base),
+    }
+  }
+
   pub fn load(&self) {
+    sched::set_thread_privileged(self.privileged);
     sched::set_task_stack_pointer(self.stack_start);
     stack::set_stack_limit(self.stack_end);
   }
@@ -202,14 +878,118 @@ impl TaskDescriptor {
   pub fn invalidate(&mut self) {
     self.stack_end = 0;
   }
+
+  /// Returns true if the descriptor slot can be reused for a new task.
+  fn free(&self) -> bool {
+    !self.valid() && self.status != Exited
+  }
+}
+
+/// Stack usage of a single stack.
+#[derive(Clone, Copy)]
+pub struct StackUsage {
+  /// Total size of the stack in bytes.
+  pub size: u32,
+  /// Maximum number of bytes that were ever used.
+  pub high_water_mark: u32,
+}
+
+impl StackUsage {
+  /// Returns the high-water mark as a percentage of the stack size.
+  pub fn percent(&self) -> u32 {
+    if self.size == 0 {
+      0
+    } else {
+      self.high_water_mark * 100 / self.size
+    }
+  }
+}
+
+/// Returns the stack usage for a task with a given index, or `None` if there's
+/// no valid task with such index.
+pub fn task_stack_usage(index: usize) -> Option<StackUsage> {
+  if index >= defined_tasks_count::get() {
+    return None;
+  }
+  let td = unsafe { &Tasks.tasks[index] };
+  if td.valid() {
+    Some(td.stack_usage())
+  } else {
+    None
+  }
+}
+
+/// Returns the stack usage for the privileged (main) stack.
+///
+/// Only the area below the stack pointer at the time of `setup()` is
+/// accounted for, which is bounded by `ReservedPivilegedStackSize`.
+pub fn privileged_stack_usage() -> StackUsage {
+  let (bottom, top) = privileged_stack::get();
+  StackUsage {
+    size: top - bottom,
+    high_water_mark: stack::high_water_mark(bottom, top),
+  }
+}
+
+/// Sets stack usage, in percent, that `check_stack_usage()` reports.
+pub fn set_stack_warning_threshold(percent: u32) {
+  stack_warning_threshold::set(percent);
+}
+
+/// Checks the high-water marks of all the stacks against the warning threshold
+/// and reports the ones above it via `os::debug`.
+///
+/// Each task stack is reported only once. The privileged stack is reported on
+/// every call while it's above the threshold.
+pub fn check_stack_usage() {
+  let threshold = stack_warning_threshold::get();
+
+  for i in 0..defined_tasks_count::get() {
+    let td = unsafe { &mut Tasks.tasks[i] };
+    if !td.valid() || td.stack_warned {
+      continue;
+    }
+    let usage = td.stack_usage();
+    if usage.percent() >= threshold {
+      td.stack_warned = true;
+      warn!("task {} used {}/{} bytes of stack", i, usage.high_water_mark,
+          usage.size);
+    }
+  }
+
+  let usage = privileged_stack_usage();
+  if usage.percent() >= threshold {
+    warn!("privileged stack used {}/{} bytes", usage.high_water_mark,
+          usage.size);
+  }
+}
+
+/// Advances the tick count and wakes up sleeping tasks, called from SysTick.
+#[inline(always)]
+pub unsafe fn task_tick() {
+  ticks::advance(1);
+  Tasks.wake_sleeping(ticks::get());
 }
 
 #[inline(always)]
 pub unsafe fn task_scheduler() {
   stack::set_stack_limit(stack::stack_base() - ReservedPivilegedStackSize);
-  Tasks.current_task().save();
+  let previous = Tasks.current_task;
+  {
+    let current = Tasks.current_task();
+    current.run_time += run_time_clock::lap() as u64;
+    current.save();
+  }
   Tasks.next_task();
-  Tasks.current_task().load();
+  let current = Tasks.current_task;
+  let td = Tasks.current_task();
+  if current != previous {
+    td.switches += 1;
+  }
+  td.load();
+  // The exited task's context was saved on its stack on the way in, nothing
+  // runs on it anymore.
+  stack_pool::release_exited();
 }
 
 // TODO(farcaller): this should not actually use stack!
@@ -220,7 +1000,7 @@ pub fn morestack() {
   let psp = sched::get_task_stack_pointer();
   let sp = sched::get_current_stack_pointer();
   if psp == sp {
-    unsafe { syscall(kill_current_task, 0) };
+    syscall::exit(KilledExitCode);
   } else {
     unsafe { abort() };
   }
@@ -230,6 +1010,33 @@ pub fn morestack() {
 #[no_mangle]
 #[no_stack_check]
 pub fn kill_current_task(_: u32) {
-  unsafe { Tasks.current_task().invalidate() };
+  exit_current_task(KilledExitCode);
+}
+
+/// Terminates the current task, called from the exit system call.
+///
+/// The stack slot is returned to the pool once the scheduler switched away
+/// from the task, the descriptor is kept with the exit code until the task is
+/// joined, unless it's detached.
+pub fn exit_current_task(code: u32) {
+  let _crit = NoInterrupts::new();
+  unsafe {
+    let joiner = {
+      let td = Tasks.current_task();
+      td.invalidate();
+      match td.stack_slot.take() {
+        Some(slot) => stack_pool::release_on_switch(slot),
+        None => {},
+      }
+      td.exit_code = code;
+      td.status = if td.detached { Runnable } else { Exited };
+      td.detached = false;
+      td.joiner.take()
+    };
+    match joiner {
+      Some(j) if Tasks.tasks[j].status == Blocked => Tasks.tasks[j].status = Runnable,
+      _ => {},
+    }
+  }
   sched::switch_context();
 }
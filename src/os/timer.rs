@@ -0,0 +1,297 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2014 Vladimir "farcaller" Pouzanov <farcaller@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/*!
+Software timers.
+
+Timers call a function after a given number of ticks, either once or
+periodically. Timer control blocks are statically allocated by the user and
+linked into a list of active timers when started, so no heap is required:
+
+```ignore
+fn blink(_: u32) { led.toggle(); }
+
+static BLINK: Timer = Timer::new(Mode::AutoReload, 50, blink, 0);
+
+BLINK.start();
+```
+
+Callbacks never run in interrupt context. With `multitasking`, they run in the
+timer task (see `timer_task`), which sleeps until the earliest timer expires,
+and the tick is the scheduler tick. Without `multitasking`, `tick()` must be
+called from a tick source (e.g. SysTick or RTC interrupt) and `run_pending()`
+from the main loop.
+*/
+
+use core::cell::UnsafeCell;
+use core::marker::Sync;
+use core::option::Option::{self, Some, None};
+use core::ptr;
+
+use hal::irq::NoInterrupts;
+
+#[cfg(feature = "multitasking")]
+use os::task::{self, Tasks};
+
+/// Timer callback, called with the timer argument.
+pub type Callback = fn(u32);
+
+/// Timer mode.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Mode {
+  /// Timer fires once and stops.
+  OneShot,
+  /// Timer fires every period until stopped.
+  AutoReload,
+}
+
+/// Software timer control block.
+pub struct Timer {
+  mode: Mode,
+  period: UnsafeCell<u32>,
+  callback: Callback,
+  arg: u32,
+  expires_at: UnsafeCell<u32>,
+  active: UnsafeCell<bool>,
+  next: UnsafeCell<*const Timer>,
+}
+
+/// Head of the active timers list.
+static mut ActiveTimers: *const Timer = 0 as *const Timer;
+
+#[cfg(not(feature = "multitasking"))]
+mod ticks {
+  /// Ticks counted by os::timer::tick().
+  static mut Ticks: u32 = 0;
+
+  pub fn get() -> u32 {
+    unsafe { Ticks }
+  }
+
+  pub fn advance() {
+    unsafe { Ticks = Ticks.wrapping_add(1) };
+  }
+}
+
+#[cfg(feature = "multitasking")]
+mod ticks {
+  use os::task;
+
+  pub fn get() -> u32 {
+    task::ticks()
+  }
+}
+
+#[cfg(feature = "multitasking")]
+mod timer_task {
+  use core::option::Option::{self, None, Some};
+
+  /// Index of the task running os::timer::timer_task.
+  static mut TimerTask: Option<usize> = None;
+
+  pub fn get() -> Option<usize> {
+    unsafe { TimerTask }
+  }
+
+  pub fn set(index: usize) {
+    unsafe { TimerTask = Some(index) };
+  }
+}
+
+impl Timer {
+  /// Creates a new stopped timer with a period in ticks. A period of 0 is
+  /// treated as 1.
+  pub const fn new(mode: Mode, period: u32, callback: Callback, arg: u32) -> Timer {
+    Timer {
+      mode: mode,
+      period: UnsafeCell::new(period),
+      callback: callback,
+      arg: arg,
+      expires_at: UnsafeCell::new(0),
+      active: UnsafeCell::new(false),
+      next: UnsafeCell::new(0 as *const Timer),
+    }
+  }
+
+  /// Starts the timer. Starting an active timer restarts its period.
+  pub fn start(&'static self) {
+    let crit = NoInterrupts::new();
+    unsafe {
+      *self.expires_at.get() = ticks::get().wrapping_add(self.period());
+      if !*self.active.get() {
+        *self.active.get() = true;
+        *self.next.get() = ActiveTimers;
+        ActiveTimers = self as *const Timer;
+      }
+    }
+    wake_timer_task(&crit);
+  }
+
+  /// Stops the timer. The callback won't be called until the timer is started
+  /// again.
+  pub fn stop(&'static self) {
+    let _crit = NoInterrupts::new();
+    unsafe { self.unlink() };
+  }
+
+  /// Restarts the period of an active timer, or starts a stopped one.
+  pub fn reset(&'static self) {
+    self.start();
+  }
+
+  /// Changes the timer period. Takes effect on the next start or reload. A
+  /// period of 0 is treated as 1.
+  pub fn set_period(&self, period: u32) {
+    let _crit = NoInterrupts::new();
+    unsafe { *self.period.get() = period };
+  }
+
+  /// Returns true if the timer is running.
+  pub fn is_active(&self) -> bool {
+    unsafe { *self.active.get() }
+  }
+
+  /// Returns the period, at least 1 so that an auto-reload timer can't expire
+  /// again on the same tick.
+  fn period(&self) -> u32 {
+    match unsafe { *self.period.get() } {
+      0 => 1,
+      period => period,
+    }
+  }
+
+  fn expired(&self, now: u32) -> bool {
+    unsafe { now.wrapping_sub(*self.expires_at.get()) as i32 >= 0 }
+  }
+
+  /// Removes the timer from the active list. Must be called with interrupts
+  /// disabled.
+  unsafe fn unlink(&self) {
+    if !*self.active.get() {
+      return;
+    }
+    *self.active.get() = false;
+
+    let this = self as *const Timer;
+    let mut link: *mut *const Timer = &mut ActiveTimers;
+    while !(*link).is_null() {
+      if *link == this {
+        *link = *self.next.get();
+        break;
+      }
+      link = (**link).next.get();
+    }
+    *self.next.get() = ptr::null();
+  }
+}
+
+unsafe impl Sync for Timer {}
+
+/// Advances the timer tick.
+///
+/// Call this from the tick interrupt when `multitasking` is disabled, the
+/// scheduler tick is used otherwise.
+#[cfg(not(feature = "multitasking"))]
+pub fn tick() {
+  ticks::advance();
+}
+
+/// Finds the next expired timer, reloads or stops it and returns its callback.
+fn take_expired(now: u32) -> Option<(Callback, u32)> {
+  let _crit = NoInterrupts::new();
+  unsafe {
+    let mut timer = ActiveTimers;
+    while !timer.is_null() {
+      let t = &*timer;
+      if t.expired(now) {
+        match t.mode {
+          Mode::AutoReload => *t.expires_at.get() =
+              (*t.expires_at.get()).wrapping_add(t.period()),
+          Mode::OneShot => t.unlink(),
+        }
+        return Some((t.callback, t.arg));
+      }
+      timer = *t.next.get();
+    }
+  }
+  None
+}
+
+/// Runs callbacks of all the expired timers.
+///
+/// Callbacks are called with interrupts enabled, so they are free to start
+/// and stop timers.
+pub fn run_pending() {
+  loop {
+    match take_expired(ticks::get()) {
+      Some((callback, arg)) => callback(arg),
+      None => break,
+    }
+  }
+}
+
+/// Returns the number of ticks until the earliest active timer expires, or
+/// `None` if there are no active timers.
+pub fn next_expiry() -> Option<u32> {
+  let _crit = NoInterrupts::new();
+  let now = ticks::get();
+  let mut earliest: Option<u32> = None;
+  unsafe {
+    let mut timer = ActiveTimers;
+    while !timer.is_null() {
+      let left = (*(*timer).expires_at.get()).wrapping_sub(now) as i32;
+      let left = if left > 0 { left as u32 } else { 0 };
+      earliest = match earliest {
+        Some(e) if e <= left => Some(e),
+        _ => Some(left),
+      };
+      timer = *(*timer).next.get();
+    }
+  }
+  earliest
+}
+
+/// Timer service task.
+///
+/// Define it with `os::task::define_task(os::timer::timer_task, 0, size,
+/// false)`. It sleeps until the earliest timer expires, so it doesn't prevent
+/// tickless idle, and is woken up whenever a timer is started.
+#[cfg(feature = "multitasking")]
+pub fn timer_task(_: u32) {
+  timer_task::set(task::current_task_index());
+  loop {
+    run_pending();
+
+    let crit = NoInterrupts::new();
+    match next_expiry() {
+      Some(0) => {},
+      // Interrupts stay disabled until after the task is marked as sleeping,
+      // so that a timer started in between wakes it up.
+      Some(ticks) => task::sleep(ticks),
+      None => unsafe { Tasks.current_task().block(crit) },
+    }
+  }
+}
+
+#[cfg(feature = "multitasking")]
+fn wake_timer_task(crit: &NoInterrupts) {
+  match timer_task::get() {
+    Some(index) => { task::wake(index, crit); },
+    None => {},
+  }
+}
+
+#[cfg(not(feature = "multitasking"))]
+fn wake_timer_task(_: &NoInterrupts) {}
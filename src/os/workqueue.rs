@@ -0,0 +1,263 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2014 Vladimir "farcaller" Pouzanov <farcaller@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/*!
+Deferred interrupt work.
+
+Interrupt handlers should do as little as possible. The rest of the work can be
+deferred to thread mode by submitting a statically allocated work item:
+
+```ignore
+fn process_tick(_: u32) { music::tick(); }
+
+static TICK_WORK: Work = Work::new(process_tick);
+
+#[no_mangle]
+pub unsafe extern fn isr_rtc0() {
+  RTC.clear_tick();
+  TICK_WORK.submit(0);
+}
+```
+
+Work items run in submission order. With `multitasking`, they run in the work
+task (see `work_task`), which the scheduler switches to ahead of the other
+tasks via PendSV as soon as the submitting ISR returns. Without
+`multitasking`, this module provides `isr_pendsv` itself: submitting pends
+PendSV at the lowest priority, and the handler runs the work once all the
+other interrupt handlers have returned, preempting the main loop.
+*/
+
+use core::cell::UnsafeCell;
+use core::marker::Sync;
+use core::option::Option::{self, Some, None};
+use core::ptr;
+
+use hal::irq::NoInterrupts;
+
+#[cfg(feature = "multitasking")]
+use os::task::{self, Tasks};
+#[cfg(all(not(feature = "multitasking"), feature = "cpu_cortex-m0"))]
+use hal::cortex_m0::scb;
+#[cfg(all(not(feature = "multitasking"), feature = "cpu_cortex-m3"))]
+use hal::cortex_m3::scb;
+#[cfg(all(not(feature = "multitasking"), feature = "cpu_cortex-m4"))]
+use hal::cortex_m4::scb;
+#[cfg(all(not(feature = "multitasking"), feature = "cpu_cortex-m7"))]
+use hal::cortex_m7::scb;
+
+/// Work function, called with the argument passed to `Work::submit()`.
+pub type WorkFn = fn(u32);
+
+/// Deferred work item.
+pub struct Work {
+  func: WorkFn,
+  arg: UnsafeCell<u32>,
+  pending: UnsafeCell<bool>,
+  next: UnsafeCell<*const Work>,
+}
+
+/// Head of the pending work queue.
+static mut QueueHead: *const Work = 0 as *const Work;
+/// Tail of the pending work queue.
+static mut QueueTail: *const Work = 0 as *const Work;
+
+#[cfg(feature = "multitasking")]
+mod work_task {
+  use core::option::Option::{self, None, Some};
+
+  /// Index of the task running os::workqueue::work_task.
+  static mut WorkTask: Option<usize> = None;
+
+  pub fn get() -> Option<usize> {
+    unsafe { WorkTask }
+  }
+
+  pub fn set(index: usize) {
+    unsafe { WorkTask = Some(index) };
+  }
+}
+
+impl Work {
+  /// Creates a new work item.
+  pub const fn new(func: WorkFn) -> Work {
+    Work {
+      func: func,
+      arg: UnsafeCell::new(0),
+      pending: UnsafeCell::new(false),
+      next: UnsafeCell::new(0 as *const Work),
+    }
+  }
+
+  /// Queues the work to be run with a given argument. Safe to call from
+  /// interrupt handlers.
+  ///
+  /// Returns false if the work is already pending, in which case it runs only
+  /// once, with the argument it was first submitted with.
+  pub fn submit(&'static self, arg: u32) -> bool {
+    let crit = NoInterrupts::new();
+    unsafe {
+      if *self.pending.get() {
+        return false;
+      }
+      *self.pending.get() = true;
+      *self.arg.get() = arg;
+      *self.next.get() = ptr::null();
+
+      let this = self as *const Work;
+      if QueueTail.is_null() {
+        QueueHead = this;
+      } else {
+        *(*QueueTail).next.get() = this;
+      }
+      QueueTail = this;
+    }
+    wake_work_task(&crit);
+    true
+  }
+
+  /// Removes the work from the queue if it hasn't run yet.
+  ///
+  /// Returns false if the work wasn't pending.
+  pub fn cancel(&'static self) -> bool {
+    let _crit = NoInterrupts::new();
+    unsafe {
+      if !*self.pending.get() {
+        return false;
+      }
+      *self.pending.get() = false;
+
+      let this = self as *const Work;
+      let mut prev: *const Work = ptr::null();
+      let mut work = QueueHead;
+      while !work.is_null() {
+        if work == this {
+          let next = *self.next.get();
+          if prev.is_null() {
+            QueueHead = next;
+          } else {
+            *(*prev).next.get() = next;
+          }
+          if QueueTail == this {
+            QueueTail = prev;
+          }
+          break;
+        }
+        prev = work;
+        work = *(*work).next.get();
+      }
+      *self.next.get() = ptr::null();
+    }
+    true
+  }
+
+  /// Returns true if the work is queued and hasn't run yet.
+  pub fn is_pending(&self) -> bool {
+    unsafe { *self.pending.get() }
+  }
+}
+
+unsafe impl Sync for Work {}
+
+/// Removes the first work item from the queue and returns its function.
+fn take_pending() -> Option<(WorkFn, u32)> {
+  let _crit = NoInterrupts::new();
+  unsafe {
+    let work = QueueHead;
+    if work.is_null() {
+      return None;
+    }
+    let w = &*work;
+    QueueHead = *w.next.get();
+    if QueueHead.is_null() {
+      QueueTail = ptr::null();
+    }
+    *w.next.get() = ptr::null();
+    *w.pending.get() = false;
+    Some((w.func, *w.arg.get()))
+  }
+}
+
+/// Runs all the pending work, in submission order.
+///
+/// Work functions are called with interrupts enabled, and may submit more
+/// work, which runs before this function returns.
+pub fn run_pending() {
+  loop {
+    match take_pending() {
+      Some((func, arg)) => func(arg),
+      None => break,
+    }
+  }
+}
+
+/// Returns true if there's work waiting to be run.
+pub fn has_pending() -> bool {
+  unsafe { !QueueHead.is_null() }
+}
+
+/// Work queue task.
+///
+/// Define it with `os::task::define_task(os::workqueue::work_task, 0, size,
+/// false)`. It stays blocked until some work is submitted.
+#[cfg(feature = "multitasking")]
+pub fn work_task(_: u32) {
+  work_task::set(task::current_task_index());
+  loop {
+    run_pending();
+
+    // Interrupts stay disabled until after the task is blocked, so that work
+    // submitted in between wakes it up.
+    let crit = NoInterrupts::new();
+    if !has_pending() {
+      unsafe { Tasks.current_task().block(crit) };
+    }
+  }
+}
+
+#[cfg(feature = "multitasking")]
+fn wake_work_task(crit: &NoInterrupts) {
+  match work_task::get() {
+    Some(index) => task::wake_urgent(index, crit),
+    None => {},
+  }
+}
+
+#[cfg(all(not(feature = "multitasking"),
+          any(feature = "cpu_cortex-m0",
+              feature = "cpu_cortex-m3",
+              feature = "cpu_cortex-m4",
+              feature = "cpu_cortex-m7")))]
+fn wake_work_task(_: &NoInterrupts) {
+  scb::set_system_priority(scb::PENDSV, 0xff);
+  scb::set_pendsv(true);
+}
+
+#[cfg(not(any(feature = "cpu_cortex-m0",
+              feature = "cpu_cortex-m3",
+              feature = "cpu_cortex-m4",
+              feature = "cpu_cortex-m7")))]
+fn wake_work_task(_: &NoInterrupts) {}
+
+/// PendSV handler running the pending work when there's no scheduler to
+/// switch to the work task.
+#[cfg(all(not(feature = "multitasking"),
+          any(feature = "cpu_cortex-m0",
+              feature = "cpu_cortex-m3",
+              feature = "cpu_cortex-m4",
+              feature = "cpu_cortex-m7")))]
+#[no_mangle]
+pub unsafe extern fn isr_pendsv() {
+  run_pending();
+}
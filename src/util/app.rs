@@ -43,9 +43,23 @@ pub extern fn __morestack() {
   zinc::os::task::morestack();
 }
 
+#[no_stack_check]
+#[no_mangle]
+#[cfg(feature = "multitasking")]
+pub unsafe fn task_tick() {
+  zinc::os::task::task_tick();
+}
+
 #[no_stack_check]
 #[no_mangle]
 #[cfg(feature = "multitasking")]
 pub unsafe fn task_scheduler() {
   zinc::os::task::task_scheduler();
 }
+
+#[no_stack_check]
+#[no_mangle]
+#[cfg(feature = "multitasking")]
+pub unsafe fn svc_handler(frame: *mut zinc::os::syscall::Frame, exc_return: u32) {
+  zinc::os::syscall::svc_handler(frame, exc_return);
+}
@@ -0,0 +1,281 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2014 Vladimir "farcaller" Pouzanov <farcaller@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/*!
+First-fit heap allocator.
+
+With the `heap` feature zinc becomes the `#![allocator]` crate of the
+application, providing the global allocator used by `alloc::boxed::Box` and
+`collections::vec::Vec`. The toolchain zinc targets predates the
+`GlobalAlloc` trait, so this is done with the `__rust_allocate` family of
+symbols instead.
+
+The heap lives between `_heap_start` and `_heap_end`, placed after `.bss` by
+`layout_common.ld`. It's empty unless the board's `layout.ld` sets its size
+before including the common layout:
+
+```text
+__HEAP_SIZE = 4K;
+```
+
+Allocations disable interrupts while the free list is updated, but the heap
+isn't meant for time-critical code; use `util::pool` there.
+*/
+
+use core::mem;
+use core::ptr;
+
+/// A free region, stored at its own start.
+struct Hole {
+  size: usize,
+  next: *mut Hole,
+}
+
+/// A first-fit free-list allocator over a memory region.
+pub struct Heap {
+  // Dummy list head, holes are sorted by address.
+  head: Hole,
+}
+
+/// Size granularity of all allocations, large enough to fit a `Hole`.
+fn min_block() -> usize {
+  mem::size_of::<Hole>()
+}
+
+fn round_up(value: usize, align: usize) -> usize {
+  (value + align - 1) & !(align - 1)
+}
+
+impl Heap {
+  /// Creates a heap without any memory.
+  pub const fn empty() -> Heap {
+    Heap {
+      head: Hole { size: 0, next: 0 as *mut Hole },
+    }
+  }
+
+  /// Adds the region at `start` of `size` bytes to the heap.
+  pub unsafe fn init(&mut self, start: usize, size: usize) {
+    let aligned = round_up(start, min_block());
+    if size < aligned - start + min_block() {
+      return;
+    }
+    let size = (size - (aligned - start)) & !(min_block() - 1);
+    self.deallocate(aligned as *mut u8, size, min_block());
+  }
+
+  /// Allocates `size` bytes aligned to `align`, which must be a power of two.
+  /// Returns null if there's no large enough free region.
+  pub fn allocate(&mut self, size: usize, align: usize) -> *mut u8 {
+    let size = round_up(if size == 0 { 1 } else { size }, min_block());
+    let align = if align < min_block() { min_block() } else { align };
+
+    unsafe {
+      let mut prev = &mut self.head as *mut Hole;
+      while !(*prev).next.is_null() {
+        let hole = (*prev).next;
+        let start = hole as usize;
+        let end = start + (*hole).size;
+        let addr = round_up(start, align);
+
+        if addr < end && end - addr >= size {
+          let mut rest = (*hole).next;
+          if end > addr + size {
+            let back = (addr + size) as *mut Hole;
+            (*back).size = end - (addr + size);
+            (*back).next = rest;
+            rest = back;
+          }
+          if addr > start {
+            (*hole).size = addr - start;
+            (*hole).next = rest;
+          } else {
+            (*prev).next = rest;
+          }
+          return addr as *mut u8;
+        }
+        prev = hole;
+      }
+    }
+    ptr::null_mut()
+  }
+
+  /// Returns a block from `allocate` with the same `size` back to the heap.
+  pub unsafe fn deallocate(&mut self, ptr: *mut u8, size: usize, _align: usize) {
+    let addr = ptr as usize;
+    let size = round_up(if size == 0 { 1 } else { size }, min_block());
+
+    let head = &mut self.head as *mut Hole;
+    let mut prev = head;
+    while !(*prev).next.is_null() && ((*prev).next as usize) < addr {
+      prev = (*prev).next;
+    }
+
+    let hole = addr as *mut Hole;
+    let next = (*prev).next;
+    (*hole).size = size;
+    (*hole).next = next;
+    if !next.is_null() && addr + size == next as usize {
+      (*hole).size += (*next).size;
+      (*hole).next = (*next).next;
+    }
+
+    if prev != head && prev as usize + (*prev).size == addr {
+      (*prev).size += (*hole).size;
+      (*prev).next = (*hole).next;
+    } else {
+      (*prev).next = hole;
+    }
+  }
+
+  /// Returns the total size of free regions.
+  pub fn free_size(&self) -> usize {
+    let mut size = 0;
+    let mut hole = self.head.next;
+    while !hole.is_null() {
+      unsafe {
+        size += (*hole).size;
+        hole = (*hole).next;
+      }
+    }
+    size
+  }
+}
+
+#[cfg(all(feature = "heap", target_os = "none"))]
+mod global {
+  use core::cmp;
+  use core::ptr;
+
+  use hal::irq::NoInterrupts;
+
+  use super::Heap;
+
+  extern {
+    static _heap_start: u8;
+    static _heap_end: u8;
+  }
+
+  static mut GlobalHeap: Heap = Heap::empty();
+  static mut Initialized: bool = false;
+
+  /// Returns the global heap, adding the linker region on first use. Must be
+  /// called with interrupts disabled.
+  unsafe fn heap(_: &NoInterrupts) -> &'static mut Heap {
+    if !Initialized {
+      let start = &_heap_start as *const u8 as usize;
+      let end = &_heap_end as *const u8 as usize;
+      GlobalHeap.init(start, end - start);
+      Initialized = true;
+    }
+    &mut GlobalHeap
+  }
+
+  #[no_mangle]
+  pub extern fn __rust_allocate(size: usize, align: usize) -> *mut u8 {
+    let crit = NoInterrupts::new();
+    unsafe { heap(&crit).allocate(size, align) }
+  }
+
+  #[no_mangle]
+  pub extern fn __rust_deallocate(ptr: *mut u8, old_size: usize, align: usize) {
+    let crit = NoInterrupts::new();
+    unsafe { heap(&crit).deallocate(ptr, old_size, align) }
+  }
+
+  #[no_mangle]
+  pub extern fn __rust_reallocate(ptr: *mut u8, old_size: usize, size: usize,
+                                  align: usize) -> *mut u8 {
+    let new = __rust_allocate(size, align);
+    if !new.is_null() {
+      unsafe {
+        ptr::copy_nonoverlapping(ptr, new, cmp::min(old_size, size));
+      }
+      __rust_deallocate(ptr, old_size, align);
+    }
+    new
+  }
+
+  #[no_mangle]
+  pub extern fn __rust_reallocate_inplace(_ptr: *mut u8, old_size: usize,
+                                          _size: usize, _align: usize) -> usize {
+    old_size
+  }
+
+  #[no_mangle]
+  pub extern fn __rust_usable_size(size: usize, _align: usize) -> usize {
+    size
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::Heap;
+
+  fn heap(memory: &mut [u64]) -> Heap {
+    let mut heap = Heap::empty();
+    unsafe {
+      heap.init(memory.as_mut_ptr() as usize, memory.len() * 8);
+    }
+    heap
+  }
+
+  #[test]
+  fn allocations_should_not_overlap() {
+    let mut memory = [0u64; 64];
+    let mut heap = heap(&mut memory);
+    let a = heap.allocate(10, 1) as usize;
+    let b = heap.allocate(10, 1) as usize;
+    assert!(a != 0 && b != 0);
+    assert!(b >= a + 10 || a >= b + 10);
+  }
+
+  #[test]
+  fn allocate_should_respect_alignment() {
+    let mut memory = [0u64; 64];
+    let mut heap = heap(&mut memory);
+    heap.allocate(1, 1);
+    let p = heap.allocate(32, 64) as usize;
+    assert!(p != 0 && p % 64 == 0);
+  }
+
+  #[test]
+  fn allocate_should_fail_when_exhausted() {
+    let mut memory = [0u64; 16];
+    let mut heap = heap(&mut memory);
+    let total = heap.free_size();
+    assert!(!heap.allocate(total, 8).is_null());
+    assert!(heap.allocate(1, 1).is_null());
+    assert!(heap.free_size() == 0);
+  }
+
+  #[test]
+  fn deallocate_should_coalesce() {
+    let mut memory = [0u64; 64];
+    let mut heap = heap(&mut memory);
+    let total = heap.free_size();
+    let a = heap.allocate(128, 8);
+    let b = heap.allocate(128, 8);
+    let c = heap.allocate(total - 256, 8);
+    assert!(heap.allocate(1, 1).is_null());
+    unsafe {
+      heap.deallocate(a, 128, 8);
+      heap.deallocate(c, total - 256, 8);
+      heap.deallocate(b, 128, 8);
+    }
+    assert!(heap.free_size() == total);
+    assert!(!heap.allocate(total, 8).is_null());
+  }
+}
@@ -17,6 +17,9 @@
 
 pub mod strconv;
 pub mod support;
+pub mod heap;
+pub mod pool;
+pub mod ringbuf;
 pub mod shared;
 #[cfg(feature = "multitasking")] pub mod queue;
 
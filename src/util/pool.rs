@@ -0,0 +1,179 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2014 Vladimir "farcaller" Pouzanov <farcaller@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/*!
+Static fixed-block memory pool.
+
+A `Pool` owns an array of up to 32 blocks of the same `Copy` type and hands
+them out as `PoolBox` handles, which free their block when dropped. Allocation
+and freeing are safe to use from interrupts.
+
+```ignore
+static PACKETS: Pool<[[u8; 64]; 8]> = Pool::new([[0; 64]; 8]);
+
+let mut packet = PACKETS.alloc([0; 64]).ok().unwrap();
+packet[0] = 0x42;
+```
+*/
+
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut, Drop};
+use core::ptr;
+
+use util::shared::{Resource, MaxCeiling};
+
+/// Backing storage of a `Pool`.
+///
+/// Implemented for `Copy` arrays of 1 to 32 elements.
+pub unsafe trait Blocks {
+  /// Block type.
+  type Item: Copy;
+  /// Number of blocks.
+  fn count() -> usize;
+}
+
+macro_rules! impl_blocks {
+  ($($n:expr)*) => ($(
+    unsafe impl<T: Copy> Blocks for [T; $n] {
+      type Item = T;
+      #[inline(always)]
+      fn count() -> usize { $n }
+    }
+  )*)
+}
+
+impl_blocks!(1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16
+             17 18 19 20 21 22 23 24 25 26 27 28 29 30 31 32);
+
+/// A pool of fixed-size blocks.
+pub struct Pool<A: Blocks> {
+  blocks: UnsafeCell<A>,
+  used: Resource<u32, MaxCeiling>,
+}
+
+unsafe impl<A: Blocks> Sync for Pool<A> where A::Item: Send {}
+
+impl<A: Blocks> Pool<A> {
+  /// Creates a pool with all the blocks free.
+  pub const fn new(blocks: A) -> Pool<A> {
+    Pool {
+      blocks: UnsafeCell::new(blocks),
+      used: Resource::new(0),
+    }
+  }
+
+  /// Moves `value` into a free block, or returns it back if there's none.
+  pub fn alloc<'a>(&'a self, value: A::Item) -> Result<PoolBox<'a, A>, A::Item> {
+    let index = self.used.lock(|used| {
+      for i in 0..A::count() {
+        if *used & (1 << i) == 0 {
+          *used |= 1 << i;
+          return Some(i);
+        }
+      }
+      None
+    });
+
+    match index {
+      Some(index) => {
+        unsafe {
+          ptr::write(self.block(index), value);
+        }
+        Ok(PoolBox { pool: self, index: index })
+      },
+      None => Err(value),
+    }
+  }
+
+  /// Returns the number of free blocks.
+  pub fn available(&self) -> usize {
+    A::count() - self.used.lock(|used| used.count_ones() as usize)
+  }
+
+  fn block(&self, index: usize) -> *mut A::Item {
+    unsafe {
+      (self.blocks.get() as *mut A::Item).offset(index as isize)
+    }
+  }
+
+  fn free(&self, index: usize) {
+    self.used.lock(|used| *used &= !(1 << index));
+  }
+}
+
+/// An owned block from a `Pool`, freed on drop.
+pub struct PoolBox<'a, A: Blocks + 'a> {
+  pool: &'a Pool<A>,
+  index: usize,
+}
+
+impl<'a, A: Blocks> PoolBox<'a, A> {
+  /// Copies the value out and frees the block.
+  pub fn into_inner(self) -> A::Item {
+    *self
+  }
+}
+
+impl<'a, A: Blocks> Deref for PoolBox<'a, A> {
+  type Target = A::Item;
+  fn deref<'b>(&'b self) -> &'b A::Item {
+    unsafe {
+      &*self.pool.block(self.index)
+    }
+  }
+}
+
+impl<'a, A: Blocks> DerefMut for PoolBox<'a, A> {
+  fn deref_mut<'b>(&'b mut self) -> &'b mut A::Item {
+    unsafe {
+      &mut *self.pool.block(self.index)
+    }
+  }
+}
+
+impl<'a, A: Blocks> Drop for PoolBox<'a, A> {
+  fn drop(&mut self) {
+    self.pool.free(self.index);
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::Pool;
+
+  #[test]
+  fn alloc_should_fail_when_exhausted() {
+    let pool = Pool::new([0u32; 2]);
+    let a = pool.alloc(1).ok().unwrap();
+    let b = pool.alloc(2).ok().unwrap();
+    assert!(pool.alloc(3).err() == Some(3));
+    assert!(*a == 1 && *b == 2);
+    assert!(pool.available() == 0);
+  }
+
+  #[test]
+  fn dropped_blocks_should_be_reused() {
+    let pool = Pool::new([[0u8; 4]; 1]);
+    {
+      let mut block = pool.alloc([1; 4]).ok().unwrap();
+      block[3] = 7;
+      assert!(*block == [1, 1, 1, 7]);
+    }
+    assert!(pool.available() == 1);
+    let block = pool.alloc([2; 4]).ok().unwrap();
+    assert!(block.into_inner() == [2; 4]);
+    assert!(pool.available() == 1);
+  }
+}
@@ -12,7 +12,14 @@ use core::ops::Deref;
 use core::ptr::RawPtr;
 use core::option::Option::{self, Some, None};
 
-use hal::cortex_m3::sched::NoInterrupts;
+#[cfg(feature = "cpu_cortex-m0")]
+use hal::cortex_m0::irq::NoInterrupts;
+#[cfg(feature = "cpu_cortex-m3")]
+use hal::cortex_m3::irq::NoInterrupts;
+#[cfg(feature = "cpu_cortex-m4")]
+use hal::cortex_m4::irq::NoInterrupts;
+#[cfg(feature = "cpu_cortex-m7")]
+use hal::cortex_m7::irq::NoInterrupts;
 
 pub struct Node<T> {
   pub next: Unsafe<*mut Node<T>>,
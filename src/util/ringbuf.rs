@@ -0,0 +1,313 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2014 Vladimir "farcaller" Pouzanov <farcaller@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/*!
+Lock-free single-producer/single-consumer ring buffer.
+
+The buffer is split into a `Producer` and a `Consumer` handle, which can be
+used from different contexts (e.g. an ISR and a task) without disabling
+interrupts. Only atomic loads and stores are used, so it works on Cortex-M0 as
+well.
+
+```ignore
+static mut RX: RingBuf<[u8; 64]> = RingBuf::new([0; 64]);
+
+let (producer, consumer) = unsafe { RX.split() };
+```
+
+The backing storage is an array with a power of two length, so indices wrap
+with a mask instead of a division.
+*/
+
+use core::cell::UnsafeCell;
+use core::marker::PhantomData;
+use core::ptr;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Backing storage of a `RingBuf`.
+///
+/// Implemented for `Copy` arrays with a power of two length, from 2 to 4096.
+pub unsafe trait Array {
+  /// Element type.
+  type Item: Copy;
+  /// Number of elements.
+  fn capacity() -> usize;
+}
+
+macro_rules! impl_array {
+  ($($n:expr)*) => ($(
+    unsafe impl<T: Copy> Array for [T; $n] {
+      type Item = T;
+      #[inline(always)]
+      fn capacity() -> usize { $n }
+    }
+  )*)
+}
+
+impl_array!(2 4 8 16 32 64 128 256 512 1024 2048 4096);
+
+/// A fixed-size SPSC ring buffer.
+pub struct RingBuf<A: Array> {
+  storage: UnsafeCell<A>,
+  // Both indices run freely and wrap around usize, the slot is the index
+  // masked by the capacity.
+  head: AtomicUsize,
+  tail: AtomicUsize,
+}
+
+unsafe impl<A: Array> Sync for RingBuf<A> where A::Item: Send {}
+
+impl<A: Array> RingBuf<A> {
+  /// Creates an empty ring buffer, using `storage` for the elements.
+  pub const fn new(storage: A) -> RingBuf<A> {
+    RingBuf {
+      storage: UnsafeCell::new(storage),
+      head: AtomicUsize::new(0),
+      tail: AtomicUsize::new(0),
+    }
+  }
+
+  /// Returns the maximum number of elements.
+  pub fn capacity(&self) -> usize {
+    A::capacity()
+  }
+
+  /// Returns the number of queued elements.
+  pub fn len(&self) -> usize {
+    self.tail.load(Ordering::Acquire).wrapping_sub(self.head.load(Ordering::Acquire))
+  }
+
+  /// Returns true if there are no queued elements.
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  /// Returns true if no more elements can be pushed.
+  pub fn is_full(&self) -> bool {
+    self.len() == A::capacity()
+  }
+
+  /// Splits the buffer into its producer and consumer halves.
+  pub fn split<'a>(&'a mut self) -> (Producer<'a, A>, Consumer<'a, A>) {
+    (Producer { rb: self, _marker: PhantomData },
+     Consumer { rb: self, _marker: PhantomData })
+  }
+
+  #[inline(always)]
+  fn slot(&self, index: usize) -> *mut A::Item {
+    unsafe {
+      (self.storage.get() as *mut A::Item).offset((index & (A::capacity() - 1)) as isize)
+    }
+  }
+}
+
+/// The writing half of a `RingBuf`.
+pub struct Producer<'a, A: Array + 'a> {
+  rb: *const RingBuf<A>,
+  _marker: PhantomData<&'a RingBuf<A>>,
+}
+
+unsafe impl<'a, A: Array> Send for Producer<'a, A> where A::Item: Send {}
+
+impl<'a, A: Array> Producer<'a, A> {
+  /// Appends an element, returning it back if the buffer is full.
+  pub fn push(&mut self, item: A::Item) -> Result<(), A::Item> {
+    let rb = unsafe { &*self.rb };
+    let tail = rb.tail.load(Ordering::Relaxed);
+    if tail.wrapping_sub(rb.head.load(Ordering::Acquire)) == A::capacity() {
+      return Err(item);
+    }
+    unsafe {
+      ptr::write(rb.slot(tail), item);
+    }
+    rb.tail.store(tail.wrapping_add(1), Ordering::Release);
+    Ok(())
+  }
+
+  /// Appends as many elements from `items` as fit and returns their count.
+  pub fn push_slice(&mut self, items: &[A::Item]) -> usize {
+    let rb = unsafe { &*self.rb };
+    let tail = rb.tail.load(Ordering::Relaxed);
+    let free = A::capacity() - tail.wrapping_sub(rb.head.load(Ordering::Acquire));
+    let count = if items.len() < free { items.len() } else { free };
+
+    let start = tail & (A::capacity() - 1);
+    let first = if count < A::capacity() - start { count } else { A::capacity() - start };
+    unsafe {
+      ptr::copy_nonoverlapping(items.as_ptr(), rb.slot(tail), first);
+      ptr::copy_nonoverlapping(items.as_ptr().offset(first as isize), rb.slot(0),
+                               count - first);
+    }
+    rb.tail.store(tail.wrapping_add(count), Ordering::Release);
+    count
+  }
+
+  /// Returns the number of elements that can be pushed.
+  pub fn available(&self) -> usize {
+    let rb = unsafe { &*self.rb };
+    A::capacity() - rb.len()
+  }
+}
+
+/// The reading half of a `RingBuf`.
+pub struct Consumer<'a, A: Array + 'a> {
+  rb: *const RingBuf<A>,
+  _marker: PhantomData<&'a RingBuf<A>>,
+}
+
+unsafe impl<'a, A: Array> Send for Consumer<'a, A> where A::Item: Send {}
+
+impl<'a, A: Array> Consumer<'a, A> {
+  /// Removes the oldest element.
+  pub fn pop(&mut self) -> Option<A::Item> {
+    let rb = unsafe { &*self.rb };
+    let head = rb.head.load(Ordering::Relaxed);
+    if head == rb.tail.load(Ordering::Acquire) {
+      return None;
+    }
+    let item = unsafe { ptr::read(rb.slot(head)) };
+    rb.head.store(head.wrapping_add(1), Ordering::Release);
+    Some(item)
+  }
+
+  /// Returns the oldest element without removing it.
+  pub fn peek(&self) -> Option<A::Item> {
+    let rb = unsafe { &*self.rb };
+    let head = rb.head.load(Ordering::Relaxed);
+    if head == rb.tail.load(Ordering::Acquire) {
+      return None;
+    }
+    Some(unsafe { ptr::read(rb.slot(head)) })
+  }
+
+  /// Moves as many elements as fit into `items` and returns their count.
+  pub fn pop_slice(&mut self, items: &mut [A::Item]) -> usize {
+    let rb = unsafe { &*self.rb };
+    let head = rb.head.load(Ordering::Relaxed);
+    let queued = rb.tail.load(Ordering::Acquire).wrapping_sub(head);
+    let count = if items.len() < queued { items.len() } else { queued };
+
+    let start = head & (A::capacity() - 1);
+    let first = if count < A::capacity() - start { count } else { A::capacity() - start };
+    unsafe {
+      ptr::copy_nonoverlapping(rb.slot(head), items.as_mut_ptr(), first);
+      ptr::copy_nonoverlapping(rb.slot(0), items.as_mut_ptr().offset(first as isize),
+                               count - first);
+    }
+    rb.head.store(head.wrapping_add(count), Ordering::Release);
+    count
+  }
+
+  /// Returns the number of queued elements.
+  pub fn len(&self) -> usize {
+    let rb = unsafe { &*self.rb };
+    rb.len()
+  }
+
+  /// Returns true if there are no queued elements.
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use std::thread;
+  use std::vec::Vec;
+  use super::RingBuf;
+
+  #[test]
+  fn push_and_pop_should_keep_order() {
+    let mut rb = RingBuf::new([0u8; 4]);
+    let (mut p, mut c) = rb.split();
+    assert!(p.push(1) == Ok(()));
+    assert!(p.push(2) == Ok(()));
+    assert!(c.peek() == Some(1));
+    assert!(c.pop() == Some(1));
+    assert!(c.pop() == Some(2));
+    assert!(c.pop() == None);
+  }
+
+  #[test]
+  fn push_should_fail_when_full() {
+    let mut rb = RingBuf::new([0u8; 2]);
+    {
+      let (mut p, _) = rb.split();
+      assert!(p.push(1) == Ok(()));
+      assert!(p.push(2) == Ok(()));
+      assert!(p.push(3) == Err(3));
+      assert!(p.available() == 0);
+    }
+    assert!(rb.is_full());
+  }
+
+  #[test]
+  fn slices_should_wrap_around() {
+    let mut rb = RingBuf::new([0u8; 8]);
+    let (mut p, mut c) = rb.split();
+    let mut out = [0u8; 8];
+
+    assert!(p.push_slice(&[1, 2, 3, 4, 5, 6]) == 6);
+    assert!(c.pop_slice(&mut out[..4]) == 4);
+    assert!(p.push_slice(&[7, 8, 9, 10, 11, 12, 13]) == 6);
+    assert!(c.len() == 8);
+    assert!(c.pop_slice(&mut out) == 8);
+    assert!(out == [5, 6, 7, 8, 9, 10, 11, 12]);
+    assert!(c.is_empty());
+  }
+
+  #[test]
+  fn should_pass_data_between_threads() {
+    static mut RB: RingBuf<[u32; 16]> = RingBuf::new([0; 16]);
+    const COUNT: u32 = 20000;
+
+    let (mut p, mut c) = unsafe { RB.split() };
+    let producer = thread::spawn(move || {
+      let mut next = 0;
+      while next < COUNT {
+        if next % 3 == 0 {
+          let chunk: Vec<u32> = (next..next + 5).filter(|&v| v < COUNT).collect();
+          next += p.push_slice(&chunk) as u32;
+        } else if p.push(next).is_ok() {
+          next += 1;
+        } else {
+          thread::yield_now();
+        }
+      }
+    });
+
+    let mut expected = 0;
+    let mut buf = [0u32; 7];
+    while expected < COUNT {
+      let count = if expected % 2 == 0 {
+        c.pop_slice(&mut buf)
+      } else {
+        match c.pop() {
+          Some(v) => { buf[0] = v; 1 },
+          None => 0,
+        }
+      };
+      if count == 0 {
+        thread::yield_now();
+      }
+      for &v in &buf[..count] {
+        assert!(v == expected);
+        expected += 1;
+      }
+    }
+    producer.join().unwrap();
+    assert!(c.pop() == None);
+  }
+}
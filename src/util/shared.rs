@@ -14,31 +14,40 @@
 // limitations under the License.
 
 //! Concurrency-friendly shared state
+//!
+//! `Shared<T>` is accessed with all interrupts disabled, while `Resource<T, C>`
+//! only masks the interrupts that can touch it: it is declared with a
+//! `Ceiling`, the priority of the most urgent interrupt sharing it, and
+//! `lock()` raises BASEPRI to that priority for the duration of a closure.
+//! Cortex-M0 has no BASEPRI, so there (and for a ceiling that is zero in the
+//! priority bits the NVIC implements) the lock disables all interrupts with
+//! PRIMASK instead.
+//!
+//! ```ignore
+//! ceiling!(UartCeiling, 0x40);
+//!
+//! static RX_COUNT: Resource<u32, UartCeiling> = Resource::new(0);
+//!
+//! RX_COUNT.lock(|count| *count += 1);
+//! ```
+//!
+//! The value is only reachable through the `lock()` closure, and the
+//! reference it gets can't outlive it, so resources can't be touched outside
+//! a lock. Locking a resource again from inside its own lock aborts.
 
 use core::cell::UnsafeCell;
 use core::ops::{Deref, DerefMut};
-use core::marker::{Sync, Send};
+use core::marker::{Sync, Send, PhantomData};
+#[cfg(target_os = "none")]
+use core::intrinsics::abort;
 
+use hal::irq::NoInterrupts;
 #[cfg(feature = "cpu_cortex-m3")]
-use hal::cortex_m3::irq::NoInterrupts;
+use hal::cortex_m3::irq;
 #[cfg(feature = "cpu_cortex-m4")]
-use hal::cortex_m4::irq::NoInterrupts;
-// If cpu doesn't have nointerrupts provide dummy implementation
-#[cfg(not(any(feature = "cpu_cortex-m3",
-              feature = "cpu_cortex-m4")))]
-use self::dummy_irq::NoInterrupts;
-
-#[allow(missing_docs)]
-mod dummy_irq {
-  pub struct NoInterrupts;
-
-  impl NoInterrupts {
-    #[allow(dead_code)]
-    pub fn new() -> NoInterrupts {
-      NoInterrupts
-    }
-  }
-}
+use hal::cortex_m4::irq;
+#[cfg(feature = "cpu_cortex-m7")]
+use hal::cortex_m7::irq;
 
 /// This allows safe sharing of state, ensuring access occurs only
 /// when in a critical section.
@@ -86,3 +95,184 @@ impl<'a, T> DerefMut for SharedRef<'a, T> {
 }
 
 unsafe impl<T: Send> Sync for Shared<T> {}
+
+/// Interrupt priority a `Resource` is shared with.
+pub trait Ceiling {
+  /// Raw priority value (as passed to `nvic::set_priority`) of the most urgent
+  /// interrupt that uses the resource. Zero masks all interrupts, and so does
+  /// a value only set in priority bits the NVIC doesn't implement (e.g. 1
+  /// with 4 priority bits).
+  fn priority() -> u8;
+}
+
+/// Declares a `Ceiling` type for a given raw interrupt priority.
+#[macro_export]
+macro_rules! ceiling {
+  ($name:ident, $priority:expr) => (
+    #[allow(missing_docs)]
+    pub struct $name;
+
+    impl $crate::util::shared::Ceiling for $name {
+      #[inline(always)]
+      fn priority() -> u8 { $priority }
+    }
+  )
+}
+
+/// Ceiling of resources shared with interrupts of any priority.
+pub struct MaxCeiling;
+
+impl Ceiling for MaxCeiling {
+  #[inline(always)]
+  fn priority() -> u8 { 0 }
+}
+
+/// State shared with interrupts of priority up to `C`.
+pub struct Resource<T, C: Ceiling> {
+  value: UnsafeCell<T>,
+  locked: UnsafeCell<bool>,
+  _ceiling: PhantomData<C>,
+}
+
+impl<T, C: Ceiling> Resource<T, C> {
+  /// Create a new `Resource`
+  pub const fn new(value: T) -> Resource<T, C> {
+    Resource {
+      value: UnsafeCell::new(value),
+      locked: UnsafeCell::new(false),
+      _ceiling: PhantomData,
+    }
+  }
+
+  /// Runs `f` with the interrupts sharing the resource masked.
+  pub fn lock<R, F: FnOnce(&mut T) -> R>(&self, f: F) -> R {
+    let _ceiling = CeilingLock::new(C::priority());
+    unsafe {
+      if *self.locked.get() {
+        reentered();
+      }
+      *self.locked.get() = true;
+      let result = f(&mut *self.value.get());
+      *self.locked.get() = false;
+      result
+    }
+  }
+}
+
+unsafe impl<T: Send, C: Ceiling> Sync for Resource<T, C> {}
+
+#[cfg(target_os = "none")]
+fn reentered() -> ! {
+  unsafe { abort() }
+}
+
+#[cfg(not(target_os = "none"))]
+fn reentered() -> ! {
+  panic!("resource is already locked")
+}
+
+/// Masks interrupts up to a ceiling while alive.
+#[cfg(any(feature = "cpu_cortex-m3",
+          feature = "cpu_cortex-m4",
+          feature = "cpu_cortex-m7"))]
+enum CeilingLock {
+  Basepri(u8),
+  Primask(NoInterrupts),
+}
+
+#[cfg(any(feature = "cpu_cortex-m3",
+          feature = "cpu_cortex-m4",
+          feature = "cpu_cortex-m7"))]
+impl CeilingLock {
+  #[inline(always)]
+  fn new(priority: u8) -> CeilingLock {
+    // BASEPRI ignores the priority bits the NVIC doesn't implement, so a
+    // ceiling made only of those would read back as 0 and mask nothing.
+    if priority & implemented_priority_bits() == 0 {
+      return CeilingLock::Primask(NoInterrupts::new());
+    }
+    let previous = irq::get_basepri();
+    unsafe {
+      irq::set_basepri_max(priority);
+    }
+    CeilingLock::Basepri(previous)
+  }
+}
+
+/// Implemented priority bits, probed on first use. Zero until then.
+#[cfg(any(feature = "cpu_cortex-m3",
+          feature = "cpu_cortex-m4",
+          feature = "cpu_cortex-m7"))]
+static mut PriorityBits: u8 = 0;
+
+/// Returns the mask of priority bits implemented by the NVIC, found by
+/// writing all ones to BASEPRI and reading it back.
+#[cfg(any(feature = "cpu_cortex-m3",
+          feature = "cpu_cortex-m4",
+          feature = "cpu_cortex-m7"))]
+fn implemented_priority_bits() -> u8 {
+  unsafe {
+    if PriorityBits == 0 {
+      // BASEPRI is only changed with all interrupts disabled.
+      let _crit = NoInterrupts::new();
+      let previous = irq::get_basepri();
+      irq::set_basepri(0xff);
+      PriorityBits = irq::get_basepri();
+      irq::set_basepri(previous);
+    }
+    PriorityBits
+  }
+}
+
+#[cfg(any(feature = "cpu_cortex-m3",
+          feature = "cpu_cortex-m4",
+          feature = "cpu_cortex-m7"))]
+impl Drop for CeilingLock {
+  #[inline(always)]
+  fn drop(&mut self) {
+    if let CeilingLock::Basepri(previous) = *self {
+      unsafe {
+        irq::set_basepri(previous);
+      }
+    }
+  }
+}
+
+#[cfg(not(any(feature = "cpu_cortex-m3",
+              feature = "cpu_cortex-m4",
+              feature = "cpu_cortex-m7")))]
+struct CeilingLock {
+  #[allow(dead_code)]
+  crit: NoInterrupts,
+}
+
+#[cfg(not(any(feature = "cpu_cortex-m3",
+              feature = "cpu_cortex-m4",
+              feature = "cpu_cortex-m7")))]
+impl CeilingLock {
+  #[inline(always)]
+  fn new(_priority: u8) -> CeilingLock {
+    CeilingLock { crit: NoInterrupts::new() }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{Resource, MaxCeiling};
+
+  ceiling!(TestCeiling, 0x80);
+
+  #[test]
+  fn lock_should_give_access_to_value() {
+    let res: Resource<u32, TestCeiling> = Resource::new(1);
+    res.lock(|v| *v += 1);
+    assert!(res.lock(|v| *v) == 2);
+  }
+
+  #[test]
+  #[should_panic]
+  fn nested_lock_should_fail() {
+    let res: Resource<u32, MaxCeiling> = Resource::new(0);
+    res.lock(|_| res.lock(|_| ()));
+  }
+}
@@ -13,40 +13,473 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-//! A simple integer-to-string conversion code with statically sized buffers.
+/*!
+Number to string conversion and parsing with statically sized buffers.
 
-use core::mem::uninitialized;
+The `format_*` functions write into a caller buffer and return the number of
+bytes written, formatted as described by a `Format`:
 
-/// Convert an integer to a string.
-pub fn itoa(val: u32, buf : &mut [u8], base: u32) {
-  let mut rbuf : [u8; 32] = unsafe { uninitialized() };
-  let mut myval : u32 = val;
-  let mut idx: isize = 0;
+```ignore
+let mut buf = [0u8; 16];
+let len = format_u32(0xbeef, &mut buf, &Format::hex().width(8).zero_pad().prefix());
+// "0x00beef"
+```
 
-  if myval == 0 {
-    buf[0] = '0' as u8;
-    return;
+Fixed-point values are integers scaled by a power of ten, e.g. `2150` with two
+decimals is `21.50`. `parse_fixed` does the reverse, for reading such values
+from serial commands.
+*/
+
+/// Conversion errors.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Error {
+  /// There were no digits to parse.
+  Empty,
+  /// An unexpected character was found.
+  InvalidDigit,
+  /// The value doesn't fit into the result type.
+  Overflow,
+  /// The output buffer is too small.
+  BufferTooSmall,
+}
+
+/// Number formatting options.
+#[derive(Clone, Copy)]
+pub struct Format {
+  /// Radix, from 2 to 16. Fixed-point and float values are always decimal.
+  pub radix: u32,
+  /// Minimum width of the output, padded on the left.
+  pub width: usize,
+  /// Pad with zeros after the sign and prefix instead of spaces before them.
+  pub zero_pad: bool,
+  /// Add a `0x`, `0o` or `0b` prefix for radix 16, 8 or 2.
+  pub prefix: bool,
+  /// Use upper case hex digits.
+  pub upper: bool,
+  /// Output a `+` for positive values.
+  pub plus: bool,
+  /// Digits after the decimal point for fixed-point and float values.
+  pub precision: usize,
+}
+
+impl Format {
+  /// Decimal format without padding, with two digits precision.
+  pub const fn decimal() -> Format {
+    Format {
+      radix: 10,
+      width: 0,
+      zero_pad: false,
+      prefix: false,
+      upper: false,
+      plus: false,
+      precision: 2,
+    }
   }
 
-  while myval > 0 {
-    let digit : u8 = (myval % base) as u8;
-    myval = myval / base;
+  /// Hexadecimal format without padding.
+  pub const fn hex() -> Format {
+    Format { radix: 16, ..Format::decimal() }
+  }
 
-    rbuf[idx as usize] = digit;
+  /// Binary format without padding.
+  pub const fn binary() -> Format {
+    Format { radix: 2, ..Format::decimal() }
+  }
 
-    idx += 1;
+  /// Sets the minimum width.
+  pub fn width(self, width: usize) -> Format {
+    Format { width: width, ..self }
   }
 
-  idx -= 1;
+  /// Pads with zeros.
+  pub fn zero_pad(self) -> Format {
+    Format { zero_pad: true, ..self }
+  }
+
+  /// Adds a radix prefix.
+  pub fn prefix(self) -> Format {
+    Format { prefix: true, ..self }
+  }
 
-  let mut ridx = 0;
+  /// Uses upper case hex digits.
+  pub fn upper(self) -> Format {
+    Format { upper: true, ..self }
+  }
+
+  /// Always outputs the sign.
+  pub fn plus(self) -> Format {
+    Format { plus: true, ..self }
+  }
 
-  while idx >= 0 {
-    let charcode : u8 = ['0','1','2','3','4','5','6','7','8','9','a','b','c','d','e','f'][rbuf[idx as usize] as usize] as u8;
+  /// Sets the digits after the decimal point.
+  pub fn precision(self, precision: usize) -> Format {
+    Format { precision: precision, ..self }
+  }
+}
+
+const Digits: &'static [u8; 16] = b"0123456789abcdef";
+const UpperDigits: &'static [u8; 16] = b"0123456789ABCDEF";
+
+/// Writes the digits of `val` in reverse order and returns their count.
+fn reversed_digits(mut val: u64, radix: u32, upper: bool, out: &mut [u8; 64]) -> usize {
+  let digits = if upper { UpperDigits } else { Digits };
+  let radix = radix as u64;
+  let mut len = 0;
+  loop {
+    out[len] = digits[(val % radix) as usize];
+    val /= radix;
+    len += 1;
+    if val == 0 {
+      return len;
+    }
+  }
+}
+
+fn radix_prefix(format: &Format) -> &'static [u8] {
+  if !format.prefix {
+    return b"";
+  }
+  match format.radix {
+    16 => b"0x",
+    8 => b"0o",
+    2 => b"0b",
+    _ => b"",
+  }
+}
+
+/// Writes sign, prefix, padding, integer digits (reversed) and an optional
+/// fraction into `buf`.
+fn emit(buf: &mut [u8], format: &Format, negative: bool, prefix: &[u8],
+        int_rev: &[u8], frac: &[u8]) -> Result<usize, Error> {
+  let sign: &[u8] = if negative { b"-" } else if format.plus { b"+" } else { b"" };
+  let frac_len = if frac.is_empty() { 0 } else { frac.len() + 1 };
+  let len = sign.len() + prefix.len() + int_rev.len() + frac_len;
+  let pad = if format.width > len { format.width - len } else { 0 };
+  if len + pad > buf.len() {
+    return Err(Error::BufferTooSmall);
+  }
 
-    buf[ridx] = charcode;
+  let mut pos = 0;
+  {
+    let mut put = |c: u8| {
+      buf[pos] = c;
+      pos += 1;
+    };
+    if !format.zero_pad {
+      for _ in 0..pad {
+        put(b' ');
+      }
+    }
+    for &c in sign.iter().chain(prefix.iter()) {
+      put(c);
+    }
+    if format.zero_pad {
+      for _ in 0..pad {
+        put(b'0');
+      }
+    }
+    for &c in int_rev.iter().rev() {
+      put(c);
+    }
+    if !frac.is_empty() {
+      put(b'.');
+      for &c in frac.iter() {
+        put(c);
+      }
+    }
+  }
+  Ok(pos)
+}
+
+/// Returns the magnitude of a signed value.
+fn magnitude(val: i64) -> u64 {
+  if val < 0 {
+    (!(val as u64)).wrapping_add(1)
+  } else {
+    val as u64
+  }
+}
+
+fn pow10(exp: usize) -> Result<u64, Error> {
+  let mut val: u64 = 1;
+  for _ in 0..exp {
+    val = try!(val.checked_mul(10).ok_or(Error::Overflow));
+  }
+  Ok(val)
+}
+
+/// Formats `val`, scaled by `10^precision`, with `format.precision` digits.
+fn emit_scaled(buf: &mut [u8], format: &Format, negative: bool,
+               val: u64) -> Result<usize, Error> {
+  if format.precision > 18 {
+    return Err(Error::Overflow);
+  }
+  let scale = try!(pow10(format.precision));
+  let mut int_rev = [0u8; 64];
+  let int_len = reversed_digits(val / scale, 10, false, &mut int_rev);
+
+  let mut frac_rev = [0u8; 64];
+  let mut frac = [0u8; 64];
+  if format.precision > 0 {
+    reversed_digits(val % scale + scale, 10, false, &mut frac_rev);
+    for i in 0..format.precision {
+      frac[i] = frac_rev[format.precision - 1 - i];
+    }
+  }
+  emit(buf, format, negative, b"", &int_rev[..int_len], &frac[..format.precision])
+}
+
+/// Formats an unsigned integer.
+pub fn format_u64(val: u64, buf: &mut [u8], format: &Format) -> Result<usize, Error> {
+  let mut digits = [0u8; 64];
+  let len = reversed_digits(val, format.radix, format.upper, &mut digits);
+  emit(buf, format, false, radix_prefix(format), &digits[..len], b"")
+}
+
+/// Formats a signed integer.
+pub fn format_i64(val: i64, buf: &mut [u8], format: &Format) -> Result<usize, Error> {
+  let mut digits = [0u8; 64];
+  let len = reversed_digits(magnitude(val), format.radix, format.upper, &mut digits);
+  emit(buf, format, val < 0, radix_prefix(format), &digits[..len], b"")
+}
+
+/// Formats an unsigned 32-bit integer.
+pub fn format_u32(val: u32, buf: &mut [u8], format: &Format) -> Result<usize, Error> {
+  format_u64(val as u64, buf, format)
+}
+
+/// Formats a signed 32-bit integer.
+pub fn format_i32(val: i32, buf: &mut [u8], format: &Format) -> Result<usize, Error> {
+  format_i64(val as i64, buf, format)
+}
+
+/// Formats a fixed-point value with `decimals` decimal digits, rounded or
+/// extended to `format.precision` digits.
+pub fn format_fixed(val: i64, decimals: usize, buf: &mut [u8],
+                    format: &Format) -> Result<usize, Error> {
+  let mut scaled = magnitude(val);
+  if format.precision < decimals {
+    let divisor = try!(pow10(decimals - format.precision));
+    scaled = scaled / divisor + if scaled % divisor >= divisor / 2 { 1 } else { 0 };
+  } else {
+    let multiplier = try!(pow10(format.precision - decimals));
+    scaled = try!(scaled.checked_mul(multiplier).ok_or(Error::Overflow));
+  }
+  emit_scaled(buf, format, val < 0, scaled)
+}
+
+/// Formats a float with `format.precision` digits after the decimal point.
+pub fn format_f32(val: f32, buf: &mut [u8], format: &Format) -> Result<usize, Error> {
+  let plain = Format { zero_pad: false, ..*format };
+  if val != val {
+    return emit(buf, &plain, false, b"", b"nan", b"");
+  }
+  let negative = val < 0.0;
+  let abs = if negative { -val } else { val };
+  let scaled = abs * try!(pow10(format.precision)) as f32 + 0.5;
+  if scaled >= 18446744073709551615.0 {
+    // Also covers infinity, digits are reversed.
+    return emit(buf, &plain, negative, b"", b"fni", b"");
+  }
+  emit_scaled(buf, format, negative, scaled as u64)
+}
+
+/// Converts an integer to a string, returning the number of digits written.
+pub fn itoa(val: u32, buf: &mut [u8], base: u32) -> usize {
+  format_u32(val, buf, &Format { radix: base, ..Format::decimal() }).unwrap_or(0)
+}
+
+fn digit_value(c: u8, radix: u32) -> Result<u32, Error> {
+  let val = match c {
+    b'0'...b'9' => c - b'0',
+    b'a'...b'z' => c - b'a' + 10,
+    b'A'...b'Z' => c - b'A' + 10,
+    _ => return Err(Error::InvalidDigit),
+  } as u32;
+  if val < radix { Ok(val) } else { Err(Error::InvalidDigit) }
+}
+
+/// Parses digits, without sign or prefix.
+fn parse_digits(s: &[u8], radix: u32) -> Result<u64, Error> {
+  if s.is_empty() {
+    return Err(Error::Empty);
+  }
+  let mut val: u64 = 0;
+  for &c in s.iter() {
+    let digit = try!(digit_value(c, radix));
+    val = try!(val.checked_mul(radix as u64)
+                  .and_then(|v| v.checked_add(digit as u64))
+                  .ok_or(Error::Overflow));
+  }
+  Ok(val)
+}
+
+/// Splits off a leading sign.
+fn split_sign(s: &[u8]) -> (bool, &[u8]) {
+  match s.first() {
+    Some(&b'-') => (true, &s[1..]),
+    Some(&b'+') => (false, &s[1..]),
+    _ => (false, s),
+  }
+}
+
+/// Applies the sign to a magnitude, checking it fits into `i32`.
+fn signed(negative: bool, val: u64) -> Result<i32, Error> {
+  if negative {
+    if val > 1 << 31 {
+      Err(Error::Overflow)
+    } else {
+      Ok((val as i64).wrapping_neg() as i32)
+    }
+  } else if val > 0x7fffffff {
+    Err(Error::Overflow)
+  } else {
+    Ok(val as i32)
+  }
+}
+
+/// Parses an unsigned integer in the given radix. A `0x`, `0o` or `0b` prefix
+/// matching the radix is allowed.
+pub fn parse_u32(s: &[u8], radix: u32) -> Result<u32, Error> {
+  let prefix: &[u8] = match radix {
+    16 => b"0x",
+    8 => b"0o",
+    2 => b"0b",
+    _ => b"",
+  };
+  let digits = if !prefix.is_empty() && s.len() > 2 &&
+      s[0] == b'0' && (s[1] | 0x20) == prefix[1] {
+    &s[2..]
+  } else {
+    s
+  };
+  let val = try!(parse_digits(digits, radix));
+  if val > 0xffffffff {
+    return Err(Error::Overflow);
+  }
+  Ok(val as u32)
+}
+
+/// Parses a signed decimal integer.
+pub fn atoi(s: &[u8]) -> Result<i32, Error> {
+  let (negative, digits) = split_sign(s);
+  signed(negative, try!(parse_digits(digits, 10)))
+}
+
+/// Parses a decimal number into a fixed-point value with `decimals` decimal
+/// digits, e.g. `"-1.5"` with two decimals is `-150`. Extra fraction digits
+/// are truncated.
+pub fn parse_fixed(s: &[u8], decimals: usize) -> Result<i32, Error> {
+  let (negative, s) = split_sign(s);
+  let (int, frac) = match s.iter().position(|&c| c == b'.') {
+    Some(dot) => (&s[..dot], &s[dot + 1..]),
+    None => (s, &s[s.len()..]),
+  };
+  if int.is_empty() && frac.is_empty() {
+    return Err(Error::Empty);
+  }
+
+  let mut val = if int.is_empty() { 0 } else { try!(parse_digits(int, 10)) };
+  for i in 0..decimals {
+    let digit = if i < frac.len() { try!(digit_value(frac[i], 10)) } else { 0 };
+    val = try!(val.checked_mul(10)
+                  .and_then(|v| v.checked_add(digit as u64))
+                  .ok_or(Error::Overflow));
+  }
+  for &c in frac.iter().skip(decimals) {
+    try!(digit_value(c, 10));
+  }
+  signed(negative, val)
+}
+
+#[cfg(test)]
+mod test {
+  use core::str;
+  use super::*;
+
+  fn fmt<F: Fn(&mut [u8]) -> Result<usize, Error>>(f: F) -> [u8; 32] {
+    let mut buf = [0u8; 32];
+    let len = f(&mut buf).unwrap();
+    assert!(len == buf.iter().position(|&c| c == 0).unwrap_or(32));
+    buf
+  }
+
+  fn check<F: Fn(&mut [u8]) -> Result<usize, Error>>(f: F, expected: &str) {
+    let buf = fmt(f);
+    let len = buf.iter().position(|&c| c == 0).unwrap_or(32);
+    assert_eq!(str::from_utf8(&buf[..len]).unwrap(), expected);
+  }
+
+  #[test]
+  fn formats_integers() {
+    check(|b| format_u32(0, b, &Format::decimal()), "0");
+    check(|b| format_i32(-42, b, &Format::decimal()), "-42");
+    check(|b| format_i32(42, b, &Format::decimal().plus()), "+42");
+    check(|b| format_i64(-9223372036854775808, b, &Format::decimal()),
+          "-9223372036854775808");
+    check(|b| format_u64(18446744073709551615, b, &Format::hex().upper()),
+          "FFFFFFFFFFFFFFFF");
+  }
+
+  #[test]
+  fn formats_padding_and_prefixes() {
+    check(|b| format_u32(0xbeef, b, &Format::hex().width(8).zero_pad().prefix()),
+          "0x00beef");
+    check(|b| format_i32(-5, b, &Format::decimal().width(4)), "  -5");
+    check(|b| format_i32(-5, b, &Format::decimal().width(4).zero_pad()), "-005");
+    check(|b| format_u32(5, b, &Format::binary().prefix()), "0b101");
+  }
+
+  #[test]
+  fn formats_fixed_point() {
+    check(|b| format_fixed(2150, 2, b, &Format::decimal()), "21.50");
+    check(|b| format_fixed(-5, 2, b, &Format::decimal()), "-0.05");
+    check(|b| format_fixed(2156, 2, b, &Format::decimal().precision(1)), "21.6");
+    check(|b| format_fixed(21, 0, b, &Format::decimal().precision(3)), "21.000");
+    check(|b| format_fixed(7, 1, b, &Format::decimal().precision(0)), "1");
+  }
+
+  #[test]
+  fn formats_floats() {
+    check(|b| format_f32(3.14159, b, &Format::decimal().precision(3)), "3.142");
+    check(|b| format_f32(-0.5, b, &Format::decimal().precision(1).width(6)), "  -0.5");
+    check(|b| format_f32(1.0 / 0.0, b, &Format::decimal()), "inf");
+    check(|b| format_f32(0.0 / 0.0, b, &Format::decimal()), "nan");
+  }
+
+  #[test]
+  fn reports_small_buffers() {
+    let mut buf = [0u8; 3];
+    assert!(format_u32(1234, &mut buf, &Format::decimal()) == Err(Error::BufferTooSmall));
+  }
+
+  #[test]
+  fn itoa_returns_length() {
+    let mut buf = [0u8; 32];
+    assert!(itoa(255, &mut buf, 16) == 2);
+    assert!(&buf[..2] == b"ff");
+  }
+
+  #[test]
+  fn parses_integers() {
+    assert!(atoi(b"-2147483648") == Ok(-2147483648));
+    assert!(atoi(b"+17") == Ok(17));
+    assert!(atoi(b"2147483648") == Err(Error::Overflow));
+    assert!(atoi(b"-") == Err(Error::Empty));
+    assert!(atoi(b"12a") == Err(Error::InvalidDigit));
+    assert!(parse_u32(b"0xFFffFFff", 16) == Ok(0xffffffff));
+    assert!(parse_u32(b"0b101", 2) == Ok(5));
+    assert!(parse_u32(b"777", 8) == Ok(511));
+    assert!(parse_u32(b"100000000", 16) == Err(Error::Overflow));
+  }
 
-    idx -= 1;
-    ridx += 1;
+  #[test]
+  fn parses_fixed_point() {
+    assert!(parse_fixed(b"21.5", 2) == Ok(2150));
+    assert!(parse_fixed(b"-1.239", 2) == Ok(-123));
+    assert!(parse_fixed(b".5", 1) == Ok(5));
+    assert!(parse_fixed(b"3", 1) == Ok(30));
+    assert!(parse_fixed(b"1.x", 1) == Err(Error::InvalidDigit));
+    assert!(parse_fixed(b".", 1) == Err(Error::Empty));
   }
 }
@@ -25,6 +25,8 @@ if [ "$PLATFORM" == "native" ]; then
   (cd ./macro_platformtree; cargo build --verbose; cargo test --verbose)
   echo " * building zinc macro"
   (cd ./macro_zinc; cargo test --verbose)
+  echo " * building deflog decoder"
+  (cd ./deflog_decoder; cargo build --verbose; cargo test --verbose; cargo clippy --all-targets -- -D warnings)
 
   echo " * generating coverage data"
   if [ "$TRAVIS_JOB_ID" != "" ]; then